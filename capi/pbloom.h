@@ -0,0 +1,63 @@
+/*
+ * C ABI for the pbloom portable bloom filter.
+ *
+ * Link against libpbloom built from this crate (`cargo build --release` in capi/). From Go:
+ *
+ *     // #cgo LDFLAGS: -lpbloom
+ *     // #include "pbloom.h"
+ *     import "C"
+ *
+ * Functions returning int return a negative value on error. Filters and buffers returned by
+ * this library must be released with pbloom_free and pbloom_buffer_free. Internal panics are
+ * reported as PBLOOM_ERR_FILTER or a NULL filter, never unwound into the caller.
+ */
+
+#ifndef PBLOOM_H
+#define PBLOOM_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* Invalid arguments, such as null pointers. */
+#define PBLOOM_ERR_ARGUMENT -1
+/* The filter rejected the operation. */
+#define PBLOOM_ERR_FILTER -2
+
+typedef struct pbloom_filter pbloom_filter;
+
+/* Creates a filter with the given size in bytes and number of hash functions. */
+pbloom_filter *pbloom_new(size_t size, uint8_t hash_count);
+
+/* Creates a filter for the number of entries and false positive rate, or returns NULL if they
+ * are invalid. */
+pbloom_filter *pbloom_new_from_entries_and_fp(size_t entries, double fp_rate);
+
+/* Deserializes a filter, or returns NULL if the data is invalid. */
+pbloom_filter *pbloom_from_serialized(const uint8_t *data, size_t len);
+
+/* Frees a filter. Passing NULL is a no-op. */
+void pbloom_free(pbloom_filter *filter);
+
+/* Adds a key to the filter. Returns 0 on success. */
+int pbloom_add(pbloom_filter *filter, const uint8_t *key, size_t len);
+
+/* Checks if a key is present in the filter. Returns 1 if it may be present and 0 if it is
+ * not. */
+int pbloom_contains(const pbloom_filter *filter, const uint8_t *key, size_t len);
+
+/* Serializes the filter into a newly allocated buffer, to be released with
+ * pbloom_buffer_free. Returns 0 on success. */
+int pbloom_serialize(const pbloom_filter *filter, uint8_t **out, size_t *out_len);
+
+/* Frees a buffer returned by pbloom_serialize. Passing NULL is a no-op. */
+void pbloom_buffer_free(uint8_t *data, size_t len);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* PBLOOM_H */
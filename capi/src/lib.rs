@@ -0,0 +1,230 @@
+//! C ABI for `pbloom::Filter`, declared in `pbloom.h`.
+//!
+//! Functions returning `int` return a negative value on error. Filters and buffers returned by
+//! this library must be released with `pbloom_free` and `pbloom_buffer_free`. Panics never
+//! unwind into the caller: they are reported as `PBLOOM_ERR_FILTER` or a null filter.
+
+use std::panic::{self, AssertUnwindSafe};
+use std::{ptr, slice};
+
+use pbloom_core::Filter;
+
+/// Error returned for invalid arguments, such as null pointers.
+const ERR_ARGUMENT: i32 = -1;
+
+/// Error returned when the filter rejects the operation.
+const ERR_FILTER: i32 = -2;
+
+/// Borrows a byte slice from a pointer and length, accepting null for an empty slice.
+unsafe fn bytes<'a>(data: *const u8, len: usize) -> Option<&'a [u8]> {
+    match (data.is_null(), len) {
+        (true, 0) => Some(&[]),
+        (true, _) => None,
+        (false, _) => Some(slice::from_raw_parts(data, len)),
+    }
+}
+
+/// Runs `f`, returning `on_panic` instead of unwinding across the C boundary.
+fn guard<T>(on_panic: T, f: impl FnOnce() -> T) -> T {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or(on_panic)
+}
+
+/// Creates a filter with the given size in bytes and number of hash functions.
+#[no_mangle]
+pub extern "C" fn pbloom_new(size: usize, hash_count: u8) -> *mut Filter {
+    guard(ptr::null_mut(), || {
+        Box::into_raw(Box::new(Filter::new(size, hash_count)))
+    })
+}
+
+/// Creates a filter for the number of entries and false positive rate, or returns null if they
+/// are invalid.
+#[no_mangle]
+pub extern "C" fn pbloom_new_from_entries_and_fp(entries: usize, fp_rate: f64) -> *mut Filter {
+    guard(ptr::null_mut(), || {
+        match Filter::new_from_entries_and_fp(entries, fp_rate) {
+            Ok(filter) => Box::into_raw(Box::new(filter)),
+            Err(_) => ptr::null_mut(),
+        }
+    })
+}
+
+/// Deserializes a filter, or returns null if the data is invalid.
+///
+/// # Safety
+///
+/// `data` must point to `len` readable bytes, or be null if `len` is 0.
+#[no_mangle]
+pub unsafe extern "C" fn pbloom_from_serialized(data: *const u8, len: usize) -> *mut Filter {
+    let Some(data) = bytes(data, len) else {
+        return ptr::null_mut();
+    };
+    guard(ptr::null_mut(), || match Filter::from_serialized(data) {
+        Ok(filter) => Box::into_raw(Box::new(filter)),
+        Err(_) => ptr::null_mut(),
+    })
+}
+
+/// Frees a filter. Passing null is a no-op.
+///
+/// # Safety
+///
+/// `filter` must have been returned by this library and not freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn pbloom_free(filter: *mut Filter) {
+    if !filter.is_null() {
+        drop(Box::from_raw(filter));
+    }
+}
+
+/// Adds a key to the filter. Returns 0 on success.
+///
+/// # Safety
+///
+/// `filter` must be a live filter, and `key` must point to `len` readable bytes or be null if
+/// `len` is 0.
+#[no_mangle]
+pub unsafe extern "C" fn pbloom_add(filter: *mut Filter, key: *const u8, len: usize) -> i32 {
+    let (Some(filter), Some(key)) = (filter.as_mut(), bytes(key, len)) else {
+        return ERR_ARGUMENT;
+    };
+    guard(ERR_FILTER, || match filter.add(key) {
+        Ok(()) => 0,
+        Err(_) => ERR_FILTER,
+    })
+}
+
+/// Checks if a key is present in the filter. Returns 1 if it may be present and 0 if it is not.
+///
+/// # Safety
+///
+/// `filter` must be a live filter, and `key` must point to `len` readable bytes or be null if
+/// `len` is 0.
+#[no_mangle]
+pub unsafe extern "C" fn pbloom_contains(filter: *const Filter, key: *const u8, len: usize) -> i32 {
+    let (Some(filter), Some(key)) = (filter.as_ref(), bytes(key, len)) else {
+        return ERR_ARGUMENT;
+    };
+    guard(ERR_FILTER, || match filter.contains(key) {
+        Ok(present) => present as i32,
+        Err(_) => ERR_FILTER,
+    })
+}
+
+/// Serializes the filter into a newly allocated buffer. Returns 0 on success.
+///
+/// # Safety
+///
+/// `filter` must be a live filter, and `out` and `out_len` must be writable. The buffer written
+/// to `out` must be released with `pbloom_buffer_free`.
+#[no_mangle]
+pub unsafe extern "C" fn pbloom_serialize(
+    filter: *const Filter,
+    out: *mut *mut u8,
+    out_len: *mut usize,
+) -> i32 {
+    let Some(filter) = filter.as_ref() else {
+        return ERR_ARGUMENT;
+    };
+    if out.is_null() || out_len.is_null() {
+        return ERR_ARGUMENT;
+    }
+    let Ok(Ok(serialized)) = panic::catch_unwind(|| filter.serialize()) else {
+        return ERR_FILTER;
+    };
+    let buf = Box::into_raw(serialized.into_boxed_slice());
+    *out_len = buf.len();
+    *out = buf as *mut u8;
+    0
+}
+
+/// Frees a buffer returned by `pbloom_serialize`. Passing null is a no-op.
+///
+/// # Safety
+///
+/// `data` and `len` must be exactly as returned by `pbloom_serialize`, and not freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn pbloom_buffer_free(data: *mut u8, len: usize) {
+    if !data.is_null() {
+        drop(Box::from_raw(ptr::slice_from_raw_parts_mut(data, len)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sha2::{Digest, Sha256};
+
+    use super::*;
+
+    unsafe fn serialize(filter: *const Filter) -> Vec<u8> {
+        let mut out = ptr::null_mut();
+        let mut len = 0;
+        assert_eq!(pbloom_serialize(filter, &mut out, &mut len), 0);
+        let serialized = slice::from_raw_parts(out, len).to_vec();
+        pbloom_buffer_free(out, len);
+        serialized
+    }
+
+    #[test]
+    fn test_golden_vectors() {
+        let vectors: Vec<serde_json::Value> =
+            serde_json::from_str(include_str!("../../testdata/golden.json")).unwrap();
+
+        for vector in vectors.iter().filter(|vector| vector["variant"] == "plain") {
+            let size = vector["size"].as_u64().unwrap() as usize;
+            let hash_count = vector["hash_count"].as_u64().unwrap() as u8;
+            unsafe {
+                let filter = pbloom_new(size, hash_count);
+                for i in 0..vector["keys"].as_u64().unwrap() {
+                    let key = i.to_string();
+                    assert_eq!(pbloom_add(filter, key.as_ptr(), key.len()), 0);
+                }
+                assert_eq!(
+                    hex::encode(Sha256::digest(serialize(filter))),
+                    vector["sha256"].as_str().unwrap(),
+                    "{}",
+                    vector
+                );
+                pbloom_free(filter);
+            }
+        }
+    }
+
+    #[test]
+    fn test_c_api() {
+        unsafe {
+            let filter = pbloom_new_from_entries_and_fp(1000, 0.01);
+            assert!(!filter.is_null());
+            assert_eq!(pbloom_add(filter, b"hello".as_ptr(), 5), 0);
+            assert_eq!(pbloom_contains(filter, b"hello".as_ptr(), 5), 1);
+            assert_eq!(pbloom_contains(filter, b"world".as_ptr(), 5), 0);
+            assert_eq!(pbloom_contains(filter, ptr::null(), 0), 0);
+            assert_eq!(pbloom_contains(filter, ptr::null(), 5), ERR_ARGUMENT);
+            assert_eq!(pbloom_add(ptr::null_mut(), b"x".as_ptr(), 1), ERR_ARGUMENT);
+
+            let serialized = serialize(filter);
+            let copy = pbloom_from_serialized(serialized.as_ptr(), serialized.len());
+            assert_eq!(pbloom_contains(copy, b"hello".as_ptr(), 5), 1);
+            assert!(pbloom_from_serialized(b"garbage".as_ptr(), 7).is_null());
+            assert!(pbloom_new_from_entries_and_fp(0, 0.01).is_null());
+
+            pbloom_free(copy);
+            pbloom_free(filter);
+            pbloom_free(ptr::null_mut());
+        }
+    }
+
+    #[test]
+    fn test_empty_filters() {
+        unsafe {
+            let filter = pbloom_new(0, 3);
+            assert_eq!(pbloom_add(filter, b"hello".as_ptr(), 5), 0);
+            assert_eq!(pbloom_contains(filter, b"hello".as_ptr(), 5), 1);
+            pbloom_free(filter);
+
+            let empty = b"\xc4\x00\xcc\x03";
+            assert!(pbloom_from_serialized(empty.as_ptr(), empty.len()).is_null());
+        }
+        assert_eq!(guard(ERR_FILTER, || panic!("probe")), ERR_FILTER);
+    }
+}
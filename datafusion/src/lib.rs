@@ -0,0 +1,229 @@
+use std::any::Any;
+use std::sync::Arc;
+
+use datafusion::arrow::array::{Array, AsArray, BooleanArray};
+use datafusion::arrow::buffer::{BooleanBuffer, Buffer};
+use datafusion::arrow::datatypes::DataType;
+use datafusion::common::{exec_err, DataFusionError, Result, ScalarValue};
+use datafusion::execution::FunctionRegistry;
+use datafusion::logical_expr::{ColumnarValue, ScalarUDF, ScalarUDFImpl, Signature, Volatility};
+use pbloom::{Filter, FilterError};
+
+/// `pbloom_contains(filter, key)` as a DataFusion scalar function over `Binary` and `LargeBinary`
+/// columns.
+///
+/// Filters exported from Postgres as `bytea` can be used as-is. When the filter argument is a
+/// literal, it is deserialized once per batch and the keys are checked with
+/// `Filter::contains_many_bitset`, whose result is used as the output bitmap directly.
+#[derive(Debug)]
+pub struct PbloomContains {
+    signature: Signature,
+}
+
+impl PbloomContains {
+    pub fn new() -> Self {
+        Self {
+            signature: Signature::uniform(
+                2,
+                vec![DataType::Binary, DataType::LargeBinary],
+                Volatility::Immutable,
+            ),
+        }
+    }
+}
+
+impl Default for PbloomContains {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn execution_error(err: FilterError) -> DataFusionError {
+    DataFusionError::Execution(format!("pbloom_contains: {}", err))
+}
+
+fn load(filter: &[u8]) -> Result<Filter> {
+    Filter::from_serialized(filter).map_err(execution_error)
+}
+
+fn scalar_bytes(value: &ScalarValue) -> Result<Option<&[u8]>> {
+    match value {
+        ScalarValue::Binary(bytes) | ScalarValue::LargeBinary(bytes) => Ok(bytes.as_deref()),
+        other => exec_err!(
+            "pbloom_contains: expected binary argument, got {}",
+            other.data_type()
+        ),
+    }
+}
+
+/// Iterates over the values of a `Binary` or `LargeBinary` array.
+fn binary_iter(array: &dyn Array) -> Result<Box<dyn Iterator<Item = Option<&[u8]>> + '_>> {
+    match array.data_type() {
+        DataType::Binary => Ok(Box::new(array.as_binary::<i32>().iter())),
+        DataType::LargeBinary => Ok(Box::new(array.as_binary::<i64>().iter())),
+        other => exec_err!("pbloom_contains: expected binary argument, got {}", other),
+    }
+}
+
+/// Checks every key against one filter. Null keys give null results.
+fn contains_keys(filter: &Filter, keys: &dyn Array) -> Result<BooleanArray> {
+    let words = filter
+        .contains_many_bitset(binary_iter(keys)?.map(Option::unwrap_or_default))
+        .map_err(execution_error)?;
+    let values = BooleanBuffer::new(Buffer::from_vec(words), 0, keys.len());
+    Ok(BooleanArray::new(values, keys.nulls().cloned()))
+}
+
+impl ScalarUDFImpl for PbloomContains {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn name(&self) -> &str {
+        "pbloom_contains"
+    }
+
+    fn signature(&self) -> &Signature {
+        &self.signature
+    }
+
+    fn return_type(&self, _arg_types: &[DataType]) -> Result<DataType> {
+        Ok(DataType::Boolean)
+    }
+
+    fn invoke(&self, args: &[ColumnarValue]) -> Result<ColumnarValue> {
+        let [filter, key] = args else {
+            return exec_err!("pbloom_contains: expected 2 arguments, got {}", args.len());
+        };
+
+        match (filter, key) {
+            (ColumnarValue::Scalar(filter), ColumnarValue::Scalar(key)) => {
+                let result = match (scalar_bytes(filter)?, scalar_bytes(key)?) {
+                    (Some(filter), Some(key)) => {
+                        Some(load(filter)?.contains(key).map_err(execution_error)?)
+                    }
+                    _ => None,
+                };
+                Ok(ColumnarValue::Scalar(ScalarValue::Boolean(result)))
+            }
+            (ColumnarValue::Scalar(filter), ColumnarValue::Array(keys)) => {
+                let result = match scalar_bytes(filter)? {
+                    Some(filter) => contains_keys(&load(filter)?, keys)?,
+                    None => BooleanArray::new_null(keys.len()),
+                };
+                Ok(ColumnarValue::Array(Arc::new(result)))
+            }
+            (ColumnarValue::Array(filters), key) => {
+                let keys = key.clone().into_array(filters.len())?;
+                let result = binary_iter(filters)?
+                    .zip(binary_iter(&keys)?)
+                    .map(|(filter, key)| match (filter, key) {
+                        (Some(filter), Some(key)) => {
+                            Ok(Some(load(filter)?.contains(key).map_err(execution_error)?))
+                        }
+                        _ => Ok(None),
+                    })
+                    .collect::<Result<BooleanArray>>()?;
+                Ok(ColumnarValue::Array(Arc::new(result)))
+            }
+        }
+    }
+}
+
+/// Registers the pbloom scalar functions with a DataFusion session.
+pub fn register(registry: &mut dyn FunctionRegistry) -> Result<()> {
+    registry.register_udf(Arc::new(ScalarUDF::from(PbloomContains::new())))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use datafusion::arrow::array::{ArrayRef, BinaryArray, LargeBinaryArray};
+    use datafusion::arrow::record_batch::RecordBatch;
+    use datafusion::prelude::SessionContext;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_pbloom_contains() -> Result<()> {
+        let mut ctx = SessionContext::new();
+        register(&mut ctx)?;
+
+        let mut filter = Filter::new(1000, 7);
+        filter.add(b"hello").unwrap();
+        let serialized = hex::encode(filter.serialize().unwrap());
+
+        let keys: ArrayRef = Arc::new(BinaryArray::from_opt_vec(vec![
+            Some(&b"hello"[..]),
+            Some(&b"world"[..]),
+            None,
+        ]));
+        ctx.register_batch("t", RecordBatch::try_from_iter(vec![("key", keys)])?)?;
+
+        let batches = ctx
+            .sql(&format!(
+                "SELECT pbloom_contains(X'{}', key) FROM t",
+                serialized
+            ))
+            .await?
+            .collect()
+            .await?;
+        let hits = batches[0].column(0).as_boolean();
+        assert!(hits.value(0));
+        assert!(!hits.value(1));
+        assert!(hits.is_null(2));
+        Ok(())
+    }
+
+    #[test]
+    fn test_invoke_record_batch() -> Result<()> {
+        let mut a = Filter::new(1000, 7);
+        a.add(b"hello").unwrap();
+        let mut b = Filter::new(1000, 7);
+        b.add(b"world").unwrap();
+        let (a, b) = (a.serialize().unwrap(), b.serialize().unwrap());
+
+        let filters: ArrayRef = Arc::new(BinaryArray::from_opt_vec(vec![
+            Some(&a[..]),
+            Some(&a[..]),
+            Some(&b[..]),
+            None,
+        ]));
+        let keys: ArrayRef = Arc::new(LargeBinaryArray::from_opt_vec(vec![
+            Some(&b"hello"[..]),
+            Some(&b"world"[..]),
+            Some(&b"world"[..]),
+            Some(&b"hello"[..]),
+        ]));
+        let batch = RecordBatch::try_from_iter(vec![("filter", filters), ("key", keys)])?;
+        let udf = PbloomContains::new();
+
+        // A filter per row.
+        let args = [
+            ColumnarValue::Array(batch.column(0).clone()),
+            ColumnarValue::Array(batch.column(1).clone()),
+        ];
+        let ColumnarValue::Array(hits) = udf.invoke(&args)? else {
+            panic!("expected an array");
+        };
+        let hits = hits.as_boolean();
+        assert_eq!(
+            hits.iter().collect::<Vec<_>>(),
+            vec![Some(true), Some(false), Some(true), None]
+        );
+
+        // One filter for the whole batch, through the bitset path.
+        let args = [
+            ColumnarValue::Scalar(ScalarValue::Binary(Some(a.clone()))),
+            ColumnarValue::Array(batch.column(1).clone()),
+        ];
+        let ColumnarValue::Array(hits) = udf.invoke(&args)? else {
+            panic!("expected an array");
+        };
+        assert_eq!(
+            hits.as_boolean().iter().collect::<Vec<_>>(),
+            vec![Some(true), Some(false), Some(false), Some(true)]
+        );
+        Ok(())
+    }
+}
@@ -3,6 +3,8 @@ package pbloom
 import (
 	"crypto/sha256"
 	"encoding/hex"
+	"encoding/json"
+	"os"
 	"strconv"
 	"testing"
 
@@ -482,6 +484,37 @@ func TestGenerateBloom(t *testing.T) {
 	assert.Equal(t, "b38258a2d43384e9d346f0a18f5f430fe3098fec322c97b6569d0aa1f7de610d", SHA256Hex(serialized))
 }
 
+// TestGoldenVectors checks serialized filters against the vectors shared with the Rust tests.
+// Only the plain variant is implemented in Go.
+func TestGoldenVectors(t *testing.T) {
+	data, err := os.ReadFile("../testdata/golden.json")
+	assert.NoError(t, err)
+
+	var vectors []struct {
+		Variant   string `json:"variant"`
+		Size      int    `json:"size"`
+		HashCount uint8  `json:"hash_count"`
+		Keys      int    `json:"keys"`
+		SHA256    string `json:"sha256"`
+	}
+	assert.NoError(t, json.Unmarshal(data, &vectors))
+
+	for _, v := range vectors {
+		if v.Variant != "plain" {
+			continue
+		}
+		filter, err := NewFilterFromBits(make([]byte, v.Size), v.HashCount)
+		assert.NoError(t, err)
+		for i := 0; i < v.Keys; i++ {
+			filter.Put([]byte(strconv.Itoa(i)))
+		}
+
+		serialized, err := filter.Serialize()
+		assert.NoError(t, err)
+		assert.Equal(t, v.SHA256, SHA256Hex(serialized), "size=%d k=%d keys=%d", v.Size, v.HashCount, v.Keys)
+	}
+}
+
 func TestHashPortability(t *testing.T) {
 	assert.Equal(t, hex.EncodeToString([]byte("hello")), "68656c6c6f")
 	hasher := murmur3.New128()
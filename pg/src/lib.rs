@@ -1,38 +1,876 @@
+use std::ffi::{CStr, CString};
+
 use pgrx::prelude::*;
-use pbloom::Filter;
+use pgrx::{GucContext, GucFlags, GucRegistry, GucSetting, PgMemoryContexts};
+use pbloom::{load_any, AgingFilter, Filter, FilterError, KeyEncode};
 
 ::pgrx::pg_module_magic!();
 
-#[pg_extern]
+/// Whether `pbloom_notify_updated` triggers send notifications.
+static NOTIFY_UPDATES: GucSetting<bool> = GucSetting::<bool>::new(true);
+
+/// Number of entries a tenant's filter is sized for when it is created.
+static TENANT_ENTRIES: GucSetting<i32> = GucSetting::<i32>::new(100_000);
+
+/// False positive rate a tenant's filter is sized for when it is created.
+static TENANT_FP_RATE: GucSetting<f64> = GucSetting::<f64>::new(0.01);
+
+#[pg_guard]
+pub extern "C" fn _PG_init() {
+    GucRegistry::define_bool_guc(
+        "pbloom.notify_updates",
+        "Send NOTIFY pbloom_updated from pbloom_notify_updated triggers.",
+        "Turn off in a session to silence notifications, for example during bulk loads.",
+        &NOTIFY_UPDATES,
+        GucContext::Userset,
+        GucFlags::default(),
+    );
+    GucRegistry::define_int_guc(
+        "pbloom.tenant_entries",
+        "Number of entries new tenant filters are sized for.",
+        "Used by pbloom.tenant_add when a tenant has no filter yet.",
+        &TENANT_ENTRIES,
+        1,
+        i32::MAX,
+        GucContext::Userset,
+        GucFlags::default(),
+    );
+    GucRegistry::define_float_guc(
+        "pbloom.tenant_fp_rate",
+        "False positive rate new tenant filters are sized for.",
+        "Used by pbloom.tenant_add when a tenant has no filter yet.",
+        &TENANT_FP_RATE,
+        0.0,
+        1.0,
+        GucContext::Userset,
+        GucFlags::default(),
+    );
+}
+
+/// Raises an `ERROR` describing why a filter could not be deserialized.
+fn invalid_filter(err: FilterError) -> ! {
+    ereport!(
+        ERROR,
+        PgSqlErrorCode::ERRCODE_INVALID_BINARY_REPRESENTATION,
+        format!("invalid pbloom filter: {}", err)
+    )
+}
+
+fn load(filter_column: &[u8]) -> Filter {
+    Filter::from_serialized(filter_column).unwrap_or_else(invalid_filter)
+}
+
+fn store(filter: &Filter) -> Vec<u8> {
+    filter.serialize().unwrap_or_else(invalid_filter)
+}
+
+/// False positive rate assumed for filters the planner cannot inspect, such as a column of filters.
+const ASSUMED_FP_RATE: f64 = 0.01;
+
+/// Runs `f` on the planner's statistics for an argument of the call being estimated.
+unsafe fn with_stats<R>(
+    request: *mut pg_sys::SupportRequestSelectivity,
+    node: *mut pg_sys::Node,
+    f: impl FnOnce(&mut pg_sys::VariableStatData) -> R,
+) -> R {
+    let mut vardata = std::mem::zeroed::<pg_sys::VariableStatData>();
+    pg_sys::examine_variable((*request).root, node, (*request).varRelid, &mut vardata);
+    let result = f(&mut vardata);
+    if !vardata.statsTuple.is_null() {
+        if let Some(free) = vardata.freefunc {
+            free(vardata.statsTuple);
+        }
+    }
+    result
+}
+
+/// Estimates the fraction of rows for which `pbloom_contains(filter, key)` is true.
+///
+/// A key matches if it is one of the filter's `n` items, or is a false positive. Out of `d`
+/// distinct keys, that is `n / d + (1 - n / d) * fp`. For a constant filter, `n` and `fp` are
+/// estimated from its bits. For a column of filters, each filter is assumed to be at the
+/// capacity it was sized for with a 1% false positive rate, so `n` follows from the column's
+/// average width. Returns `None` when there is nothing to estimate from, including when the
+/// number of distinct keys is only the planner's default guess, e.g. for an unanalyzed column or
+/// a constant key.
+unsafe fn contains_selectivity(request: *mut pg_sys::SupportRequestSelectivity) -> Option<f64> {
+    let args = PgList::<pg_sys::Node>::from_pg((*request).args);
+    let (filter_arg, key_arg) = (args.get_ptr(0)?, args.get_ptr(1)?);
+
+    let (items, fp_rate) = if pgrx::is_a(filter_arg, pg_sys::NodeTag::T_Const) {
+        let arg = filter_arg.cast::<pg_sys::Const>();
+        let filter = <&[u8]>::from_datum((*arg).constvalue, (*arg).constisnull)
+            .and_then(|filter| Filter::from_serialized(filter).ok())?;
+        (filter.estimated_items(), filter.estimated_fp_rate())
+    } else {
+        let width = with_stats(request, filter_arg, |vardata| {
+            if vardata.statsTuple.is_null() {
+                return None;
+            }
+            let stats =
+                pg_sys::heap_tuple_get_struct::<pg_sys::FormData_pg_statistic>(vardata.statsTuple);
+            Some((*stats).stawidth)
+        })?;
+        let bits = width as f64 * 8.0;
+        let items = bits * std::f64::consts::LN_2.powi(2) / -ASSUMED_FP_RATE.ln();
+        (items, ASSUMED_FP_RATE)
+    };
+
+    let distinct = with_stats(request, key_arg, |vardata| {
+        let mut is_default = false;
+        let distinct = pg_sys::get_variable_numdistinct(vardata, &mut is_default);
+        (!is_default).then_some(distinct)
+    })?;
+    let member = (items / distinct).clamp(0.0, 1.0);
+    let selectivity = member + (1.0 - member) * fp_rate;
+    selectivity.is_finite().then(|| selectivity.clamp(0.0, 1.0))
+}
+
+/// Planner support for `pbloom_contains`.
+///
+/// When the filter is the same on every call, a constant or a parameter of a prepared statement,
+/// rewrites the call to `pbloom_contains_cached`, which deserializes the filter once per query
+/// instead of once per row. Estimates the selectivity with `contains_selectivity`, both for a
+/// constant filter and for a column of filters, e.g. in a join on
+/// `pbloom_contains(f.filter, t.key)`.
+#[pg_extern(immutable, parallel_safe)]
+fn pbloom_contains_support(request: Internal) -> Internal {
+    let Some(request) = request.unwrap() else {
+        return Internal::from(None);
+    };
+    let node = request.cast_mut_ptr::<pg_sys::Node>();
+    unsafe {
+        if pgrx::is_a(node, pg_sys::NodeTag::T_SupportRequestSimplify) {
+            let request = node.cast::<pg_sys::SupportRequestSimplify>();
+            return Internal::from(cached_call((*request).fcall).map(pg_sys::Datum::from));
+        }
+        if !pgrx::is_a(node, pg_sys::NodeTag::T_SupportRequestSelectivity) {
+            return Internal::from(None);
+        }
+        let request = node.cast::<pg_sys::SupportRequestSelectivity>();
+        let Some(selectivity) = contains_selectivity(request) else {
+            return Internal::from(None);
+        };
+        (*request).selectivity = selectivity;
+        Internal::from(Some(pg_sys::Datum::from(request)))
+    }
+}
+
+#[pg_extern(immutable, parallel_safe, support = pbloom_contains_support)]
 fn pbloom_contains(filter_column: &[u8], key: &[u8]) -> bool {
+    load(filter_column)
+        .contains(key)
+        .unwrap_or_else(invalid_filter)
+}
+
+#[pg_extern(immutable, parallel_safe)]
+fn pbloom_add(filter_column: &[u8], key: &[u8]) -> Vec<u8> {
+    let mut filter = load(filter_column);
+    filter.add(key).unwrap_or_else(invalid_filter);
+    store(&filter)
+}
+
+/// Microseconds between the Unix epoch and the Postgres epoch (2000-01-01).
+const PG_EPOCH_OFFSET_MICROS: i64 = 946_684_800_000_000;
+
+fn contains_key<K: KeyEncode + ?Sized>(filter_column: &[u8], key: &K) -> bool {
+    load(filter_column)
+        .contains_key(key)
+        .unwrap_or_else(invalid_filter)
+}
+
+fn add_key<K: KeyEncode + ?Sized>(filter_column: &[u8], key: &K) -> Vec<u8> {
+    let mut filter = load(filter_column);
+    filter.add_key(key).unwrap_or_else(invalid_filter);
+    store(&filter)
+}
+
+/// Converts a timestamp to microseconds since the Unix epoch, the canonical key encoding.
+fn unix_micros(key: TimestampWithTimeZone) -> i64 {
+    pg_sys::TimestampTz::from(key).saturating_add(PG_EPOCH_OFFSET_MICROS)
+}
+
+#[pg_extern(immutable, parallel_safe, support = pbloom_contains_support)]
+fn pbloom_contains_text(filter_column: &[u8], key: &str) -> bool {
+    contains_key(filter_column, key)
+}
+
+#[pg_extern(immutable, parallel_safe)]
+fn pbloom_add_text(filter_column: &[u8], key: &str) -> Vec<u8> {
+    add_key(filter_column, key)
+}
+
+#[pg_extern(immutable, parallel_safe, support = pbloom_contains_support)]
+fn pbloom_contains_bigint(filter_column: &[u8], key: i64) -> bool {
+    contains_key(filter_column, &key)
+}
+
+#[pg_extern(immutable, parallel_safe)]
+fn pbloom_add_bigint(filter_column: &[u8], key: i64) -> Vec<u8> {
+    add_key(filter_column, &key)
+}
+
+#[pg_extern(immutable, parallel_safe, support = pbloom_contains_support)]
+fn pbloom_contains_uuid(filter_column: &[u8], key: pgrx::Uuid) -> bool {
+    contains_key(filter_column, key.as_bytes())
+}
+
+#[pg_extern(immutable, parallel_safe)]
+fn pbloom_add_uuid(filter_column: &[u8], key: pgrx::Uuid) -> Vec<u8> {
+    add_key(filter_column, key.as_bytes())
+}
+
+#[pg_extern(immutable, parallel_safe, support = pbloom_contains_support)]
+fn pbloom_contains_timestamptz(filter_column: &[u8], key: TimestampWithTimeZone) -> bool {
+    contains_key(filter_column, &unix_micros(key))
+}
+
+#[pg_extern(immutable, parallel_safe)]
+fn pbloom_add_timestamptz(filter_column: &[u8], key: TimestampWithTimeZone) -> Vec<u8> {
+    add_key(filter_column, &unix_micros(key))
+}
+
+/// Returns a copy of a `pbloom_contains` call calling `pbloom_contains_cached` instead, if its
+/// filter argument is the same on every call.
+unsafe fn cached_call(call: *mut pg_sys::FuncExpr) -> Option<*mut pg_sys::FuncExpr> {
+    let args = PgList::<pg_sys::Node>::from_pg((*call).args);
+    let filter = args.get_ptr(0)?;
+    let stable = pgrx::is_a(filter, pg_sys::NodeTag::T_Const)
+        || (pgrx::is_a(filter, pg_sys::NodeTag::T_Param)
+            && (*filter.cast::<pg_sys::Param>()).paramkind == pg_sys::ParamKind::PARAM_EXTERN);
+    if !stable {
+        return None;
+    }
+
+    // Look up the overload with the same argument types, in the schema of the extension.
+    let argtypes: Vec<pg_sys::Oid> = args.iter_ptr().map(|arg| pg_sys::exprType(arg)).collect();
+    let schema = pg_sys::get_namespace_name(pg_sys::get_func_namespace((*call).funcid));
+    let mut name = PgList::<pg_sys::Node>::new();
+    name.push(pg_sys::makeString(schema).cast());
+    name.push(pg_sys::makeString(pg_sys::pstrdup(c"pbloom_contains_cached".as_ptr())).cast());
+    let funcid = pg_sys::LookupFuncName(
+        name.into_pg(),
+        argtypes.len() as i32,
+        argtypes.as_ptr(),
+        true,
+    );
+    if funcid == pg_sys::InvalidOid || funcid == (*call).funcid {
+        return None;
+    }
+
+    let cached = pg_sys::copyObjectImpl(call.cast()).cast::<pg_sys::FuncExpr>();
+    (*cached).funcid = funcid;
+    Some(cached)
+}
+
+/// Runs `f` on the filter argument of a call, deserializing it only on the first call of the query
+/// when the argument is the same on every call.
+fn with_stable_filter<R>(
+    fcinfo: pg_sys::FunctionCallInfo,
+    filter_column: &[u8],
+    f: impl FnOnce(&Filter) -> R,
+) -> R {
+    unsafe {
+        let flinfo = if fcinfo.is_null() { std::ptr::null_mut() } else { (*fcinfo).flinfo };
+        if flinfo.is_null() || !pg_sys::get_fn_expr_arg_stable(flinfo, 0) {
+            return f(&load(filter_column));
+        }
+        if (*flinfo).fn_extra.is_null() {
+            let filter = load(filter_column);
+            (*flinfo).fn_extra = PgMemoryContexts::For((*flinfo).fn_mcxt)
+                .leak_and_drop_on_delete(filter)
+                .cast();
+        }
+        f(&*(*flinfo).fn_extra.cast::<Filter>())
+    }
+}
+
+fn contains_cached<K: KeyEncode + ?Sized>(
+    fcinfo: pg_sys::FunctionCallInfo,
+    filter_column: &[u8],
+    key: &K,
+) -> bool {
+    with_stable_filter(fcinfo, filter_column, |filter| filter.contains_key(key))
+        .unwrap_or_else(invalid_filter)
+}
+
+/// `pbloom_contains` for a filter that is the same on every call, which the planner substitutes.
+#[pg_extern(immutable, parallel_safe, support = pbloom_contains_support)]
+fn pbloom_contains_cached(
+    filter_column: &[u8],
+    key: &[u8],
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> bool {
+    contains_cached(fcinfo, filter_column, key)
+}
+
+#[pg_extern(name = "pbloom_contains_cached", immutable, parallel_safe, support = pbloom_contains_support)]
+fn pbloom_contains_cached_text(
+    filter_column: &[u8],
+    key: &str,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> bool {
+    contains_cached(fcinfo, filter_column, key)
+}
+
+#[pg_extern(name = "pbloom_contains_cached", immutable, parallel_safe, support = pbloom_contains_support)]
+fn pbloom_contains_cached_bigint(
+    filter_column: &[u8],
+    key: i64,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> bool {
+    contains_cached(fcinfo, filter_column, &key)
+}
+
+#[pg_extern(name = "pbloom_contains_cached", immutable, parallel_safe, support = pbloom_contains_support)]
+fn pbloom_contains_cached_uuid(
+    filter_column: &[u8],
+    key: pgrx::Uuid,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> bool {
+    contains_cached(fcinfo, filter_column, key.as_bytes())
+}
+
+#[pg_extern(name = "pbloom_contains_cached", immutable, parallel_safe, support = pbloom_contains_support)]
+fn pbloom_contains_cached_timestamptz(
+    filter_column: &[u8],
+    key: TimestampWithTimeZone,
+    fcinfo: pg_sys::FunctionCallInfo,
+) -> bool {
+    contains_cached(fcinfo, filter_column, &unix_micros(key))
+}
+
+/// Like `pbloom_contains`, but returns NULL instead of raising an error on a malformed filter.
+#[pg_extern(immutable, parallel_safe)]
+fn pbloom_try_contains(filter_column: &[u8], key: &[u8]) -> Option<bool> {
     Filter::from_serialized(filter_column)
         .and_then(|filter| filter.contains(key))
-        .unwrap_or(false)
+        .ok()
 }
 
-#[pg_extern]
-fn pbloom_add(filter_column: &[u8], key: &[u8]) -> Vec<u8> {
+/// Like `pbloom_add`, but returns NULL instead of raising an error on a malformed filter.
+#[pg_extern(immutable, parallel_safe)]
+fn pbloom_try_add(filter_column: &[u8], key: &[u8]) -> Option<Vec<u8>> {
     Filter::from_serialized(filter_column)
         .and_then(|mut filter| {
-            let _ = filter.add(key);
+            filter.add(key)?;
             filter.serialize()
         })
-        .unwrap_or_default()
+        .ok()
+}
+
+/// Returns a human-readable breakdown of a lookup: the probed bits, how many are set, the verdict
+/// and the filter's estimated false positive rate.
+#[pg_extern(immutable, parallel_safe)]
+fn pbloom_explain(filter_column: &[u8], key: &[u8]) -> String {
+    load(filter_column).explain(key).to_string()
+}
+
+/// Returns the structure, size in bytes, number of hash functions, fill ratio and estimated false
+/// positive rate of any pbloom filter, plain or aging.
+#[pg_extern(immutable, parallel_safe)]
+fn pbloom_info(
+    filter_column: &[u8],
+) -> TableIterator<
+    'static,
+    (
+        name!(kind, String),
+        name!(size, i64),
+        name!(hash_count, i32),
+        name!(fill_ratio, f64),
+        name!(estimated_fp_rate, f64),
+    ),
+> {
+    let stats = load_any(filter_column).unwrap_or_else(invalid_filter).stats();
+    TableIterator::once((
+        stats.kind.to_string(),
+        stats.size as i64,
+        stats.hash_count as i32,
+        stats.fill_ratio,
+        stats.estimated_fp_rate,
+    ))
+}
+
+/// Re-encodes a filter in the compressed format, which all pbloom functions accept.
+///
+/// Functions that modify a filter, like `pbloom_add`, return it uncompressed.
+#[pg_extern(immutable, parallel_safe)]
+fn pbloom_compress(filter_column: &[u8]) -> Vec<u8> {
+    load(filter_column)
+        .serialize_compressed()
+        .unwrap_or_else(invalid_filter)
+}
+
+/// Rewrites a filter stored in any format pbloom has written in the current versioned format.
+///
+/// Filters already in the current format are returned unchanged.
+#[pg_extern(immutable, parallel_safe)]
+fn pbloom_upgrade(filter_column: Vec<u8>) -> Vec<u8> {
+    Filter::upgrade_in_place(filter_column).unwrap_or_else(invalid_filter)
+}
+
+/// Applies `op` to `filter` with `other`, raising an `ERROR` if the filters are incompatible.
+fn apply(
+    filter: &mut Filter,
+    other: &Filter,
+    op: fn(&mut Filter, &Filter) -> Result<(), FilterError>,
+) {
+    op(filter, other).unwrap_or_else(|err| {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_INVALID_PARAMETER_VALUE,
+            format!("cannot combine pbloom filters: {}", err)
+        )
+    });
+}
+
+/// Applies `op` to a copy of `a` with `b`, raising an `ERROR` if the filters are incompatible.
+fn combine(a: &[u8], b: &[u8], op: fn(&mut Filter, &Filter) -> Result<(), FilterError>) -> Vec<u8> {
+    let mut filter = load(a);
+    apply(&mut filter, &load(b), op);
+    store(&filter)
 }
 
-#[pg_extern]
+#[pg_extern(immutable, parallel_safe)]
+fn pbloom_union(a: &[u8], b: &[u8]) -> Vec<u8> {
+    combine(a, b, Filter::union)
+}
+
+#[pg_extern(immutable, parallel_safe)]
+fn pbloom_intersect(a: &[u8], b: &[u8]) -> Vec<u8> {
+    combine(a, b, Filter::intersect)
+}
+
+/// Aggregate union of filters: `SELECT pbloom_union_agg(filter) FROM shards`.
+///
+/// The state holds the deserialized union, which is only serialized once all rows are added.
+pub struct PbloomUnionAgg;
+
+#[pg_aggregate]
+impl Aggregate for PbloomUnionAgg {
+    const NAME: &'static str = "pbloom_union_agg";
+    const PARALLEL: Option<pgrx::aggregate::ParallelOption> =
+        Some(pgrx::aggregate::ParallelOption::Safe);
+    type State = Internal;
+    type Args = pgrx::name!(filter, Option<Vec<u8>>);
+    type Finalize = Option<Vec<u8>>;
+
+    fn state(
+        mut current: Self::State,
+        filter: Self::Args,
+        _fcinfo: pg_sys::FunctionCallInfo,
+    ) -> Self::State {
+        let Some(filter) = filter else {
+            return current;
+        };
+        let filter = load(&filter);
+        // SAFETY: the state is only ever set to a `Filter`, in the aggregate's memory context.
+        match unsafe { current.get_mut::<Filter>() } {
+            Some(union) => apply(union, &filter, Filter::union),
+            None => {
+                unsafe { current.insert(filter) };
+            }
+        }
+        current
+    }
+
+    fn combine(
+        mut a: Self::State,
+        b: Self::State,
+        _fcinfo: pg_sys::FunctionCallInfo,
+    ) -> Self::State {
+        // SAFETY: as in `state`.
+        let Some(other) = (unsafe { b.get::<Filter>() }) else {
+            return a;
+        };
+        match unsafe { a.get_mut::<Filter>() } {
+            Some(union) => {
+                apply(union, other, Filter::union);
+                a
+            }
+            None => b,
+        }
+    }
+
+    fn finalize(
+        current: Self::State,
+        _direct_args: Self::OrderedSetArgs,
+        _fcinfo: pg_sys::FunctionCallInfo,
+    ) -> Self::Finalize {
+        // SAFETY: as in `state`.
+        unsafe { current.get::<Filter>() }.map(store)
+    }
+}
+
+#[pg_extern(immutable, parallel_safe)]
 fn pbloom_create(entries: i32, fp: f64) -> Vec<u8> {
-    Filter::new_from_entries_and_fp(entries as usize, fp)
-        .unwrap()
-        .serialize()
-        .unwrap()
+    if entries <= 0 {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_INVALID_PARAMETER_VALUE,
+            "number of entries must be positive"
+        );
+    }
+    let filter = Filter::new_from_entries_and_fp(entries as usize, fp).unwrap_or_else(|err| {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_INVALID_PARAMETER_VALUE,
+            err.to_lowercase()
+        )
+    });
+    store(&filter)
+}
+
+fn load_aging(filter_column: &[u8]) -> AgingFilter {
+    AgingFilter::from_serialized(filter_column).unwrap_or_else(invalid_filter)
+}
+
+fn store_aging(filter: &AgingFilter) -> Vec<u8> {
+    filter.serialize().unwrap_or_else(invalid_filter)
+}
+
+/// Creates an aging filter with `generations` sub-filters, each sized for `entries` items.
+#[pg_extern(immutable, parallel_safe)]
+fn pbloom_aging_create(entries: i32, fp: f64, generations: i32) -> Vec<u8> {
+    if entries <= 0 || generations <= 0 {
+        ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_INVALID_PARAMETER_VALUE,
+            "number of entries and generations must be positive"
+        );
+    }
+    let filter = AgingFilter::new_from_entries_and_fp(entries as usize, fp, generations as usize)
+        .unwrap_or_else(|err| {
+            ereport!(
+                ERROR,
+                PgSqlErrorCode::ERRCODE_INVALID_PARAMETER_VALUE,
+                err.to_lowercase()
+            )
+        });
+    store_aging(&filter)
+}
+
+#[pg_extern(immutable, parallel_safe)]
+fn pbloom_aging_add(filter_column: &[u8], key: &[u8]) -> Vec<u8> {
+    let mut filter = load_aging(filter_column);
+    filter.add(key).unwrap_or_else(invalid_filter);
+    store_aging(&filter)
+}
+
+#[pg_extern(immutable, parallel_safe)]
+fn pbloom_aging_contains(filter_column: &[u8], key: &[u8]) -> bool {
+    load_aging(filter_column)
+        .contains(key)
+        .unwrap_or_else(invalid_filter)
+}
+
+/// Drops the oldest generation of an aging filter and starts a fresh one.
+#[pg_extern(immutable, parallel_safe)]
+fn pbloom_rotate(filter_column: &[u8]) -> Vec<u8> {
+    let mut filter = load_aging(filter_column);
+    filter.rotate();
+    store_aging(&filter)
+}
+
+/// Channel notified when a filter is updated.
+const NOTIFY_CHANNEL: &CStr = c"pbloom_updated";
+
+/// Trigger issuing `NOTIFY pbloom_updated, '<name>'` for every changed row, so application caches
+/// of the filters can refresh.
+///
+/// The name is the value of the column given as the trigger argument, or the table name without
+/// one. Notifications are sent on commit, once per distinct name.
+///
+/// ```sql
+/// CREATE TRIGGER filters_notify AFTER INSERT OR UPDATE ON filters
+///     FOR EACH ROW EXECUTE FUNCTION pbloom_notify_updated('name');
+/// ```
+#[pg_trigger]
+fn pbloom_notify_updated<'a>(
+    trigger: &'a PgTrigger<'a>,
+) -> Result<Option<PgHeapTuple<'a, AllocatedByPostgres>>, PgTriggerError> {
+    let row = trigger.new().or_else(|| trigger.old());
+    let payload = notify_payload(NOTIFY_UPDATES.get(), || {
+        Ok(match (trigger.extra_args()?.first(), &row) {
+            (Some(column), Some(row)) => {
+                row.get_by_name::<String>(column).unwrap_or_else(|_| {
+                    ereport!(
+                        ERROR,
+                        PgSqlErrorCode::ERRCODE_UNDEFINED_COLUMN,
+                        format!("cannot read filter name from text column \"{}\"", column)
+                    )
+                })
+            }
+            _ => Some(trigger.table_name()?),
+        })
+    })?;
+    if let Some(payload) = payload {
+        unsafe { pg_sys::Async_Notify(NOTIFY_CHANNEL.as_ptr(), payload.as_ptr()) };
+    }
+    Ok(row)
+}
+
+/// Returns the payload `pbloom_notify_updated` sends, if any. `name` is only resolved when
+/// notifications are `enabled`, and a NULL name sends nothing.
+fn notify_payload<E>(
+    enabled: bool,
+    name: impl FnOnce() -> Result<Option<String>, E>,
+) -> Result<Option<CString>, E> {
+    if !enabled {
+        return Ok(None);
+    }
+    Ok(name()?.map(|name| CString::new(name).expect("text cannot contain NUL")))
+}
+
+extension_sql!("CREATE SCHEMA pbloom;\n", name = "pbloom_schema", bootstrap);
+
+extension_sql!(
+    r#"
+CREATE TABLE pbloom.tenant_roles (
+    tenant_id text PRIMARY KEY,
+    role regrole NOT NULL
+);
+
+CREATE TABLE pbloom.tenant_filters (
+    tenant_id text PRIMARY KEY,
+    filter bytea NOT NULL
+);
+
+ALTER TABLE pbloom.tenant_filters ENABLE ROW LEVEL SECURITY;
+ALTER TABLE pbloom.tenant_filters FORCE ROW LEVEL SECURITY;
+
+CREATE POLICY tenant_isolation ON pbloom.tenant_filters
+    USING (EXISTS (
+        SELECT 1 FROM pbloom.tenant_roles r
+        WHERE r.tenant_id = tenant_filters.tenant_id
+          AND pg_catalog.pg_has_role(r.role, 'MEMBER')
+    ));
+"#,
+    name = "tenant_filters",
+);
+
+/// Adds a key to the filter of a tenant, creating it if needed.
+///
+/// The filter is stored in `pbloom.tenant_filters`, whose row-level security policy only allows
+/// members of the role `pbloom.tenant_roles` maps the tenant to. New filters are sized by the
+/// `pbloom.tenant_entries` and `pbloom.tenant_fp_rate` settings.
+#[pg_extern(schema = "pbloom", name = "tenant_add", requires = ["tenant_filters"])]
+fn pbloom_tenant_add(tenant_id: &str, key: &[u8]) {
+    let tenant = || (PgBuiltInOids::TEXTOID.oid(), tenant_id.into_datum());
+    Spi::connect(|mut client| -> Result<(), pgrx::spi::SpiError> {
+        loop {
+            let rows = client.update(
+                "SELECT filter FROM pbloom.tenant_filters WHERE tenant_id = $1 FOR UPDATE",
+                None,
+                Some(vec![tenant()]),
+            )?;
+            let existing = if rows.is_empty() {
+                None
+            } else {
+                rows.first().get_one::<Vec<u8>>()?
+            };
+
+            if let Some(filter) = existing {
+                client.update(
+                    "UPDATE pbloom.tenant_filters SET filter = $2 WHERE tenant_id = $1",
+                    None,
+                    Some(vec![
+                        tenant(),
+                        (PgBuiltInOids::BYTEAOID.oid(), pbloom_add(&filter, key).into_datum()),
+                    ]),
+                )?;
+                return Ok(());
+            }
+
+            // Only build a new filter when the tenant has none. If another session creates it
+            // first, nothing is inserted and the next iteration updates theirs.
+            let empty = pbloom_create(TENANT_ENTRIES.get(), TENANT_FP_RATE.get());
+            let filter = pbloom_add(&empty, key);
+            let inserted = client.update(
+                "INSERT INTO pbloom.tenant_filters (tenant_id, filter) VALUES ($1, $2)
+                 ON CONFLICT (tenant_id) DO NOTHING
+                 RETURNING tenant_id",
+                None,
+                Some(vec![tenant(), (PgBuiltInOids::BYTEAOID.oid(), filter.into_datum())]),
+            )?;
+            if !inserted.is_empty() {
+                return Ok(());
+            }
+        }
+    })
+    .unwrap_or_else(spi_error);
+}
+
+/// Checks if a key is present in the filter of a tenant. Tenants without a filter, or hidden by
+/// row-level security, contain no keys.
+#[pg_extern(schema = "pbloom", name = "tenant_contains", stable, requires = ["tenant_filters"])]
+fn pbloom_tenant_contains(tenant_id: &str, key: &[u8]) -> bool {
+    // The scalar subquery always returns one row, NULL when there is no visible filter, so any
+    // error is a real failure.
+    Spi::get_one_with_args::<Vec<u8>>(
+        "SELECT (SELECT filter FROM pbloom.tenant_filters WHERE tenant_id = $1)",
+        vec![(PgBuiltInOids::TEXTOID.oid(), tenant_id.into_datum())],
+    )
+    .unwrap_or_else(spi_error)
+    .is_some_and(|filter| pbloom_contains(&filter, key))
+}
+
+#[pg_extern(schema = "pbloom", name = "tenant_add_text", requires = ["tenant_filters"])]
+fn pbloom_tenant_add_text(tenant_id: &str, key: &str) {
+    pbloom_tenant_add(tenant_id, key.as_bytes())
+}
+
+#[pg_extern(schema = "pbloom", name = "tenant_contains_text", stable, requires = ["tenant_filters"])]
+fn pbloom_tenant_contains_text(tenant_id: &str, key: &str) -> bool {
+    pbloom_tenant_contains(tenant_id, key.as_bytes())
+}
+
+/// Number of keys `pbloom.append_keys` fetches from the key query at a time.
+const APPEND_BATCH_SIZE: i64 = 10_000;
+
+/// Raises an `ERROR` for a failed SPI call.
+fn spi_error(err: pgrx::spi::SpiError) -> ! {
+    ereport!(
+        ERROR,
+        PgSqlErrorCode::ERRCODE_INTERNAL_ERROR,
+        format!("pbloom SPI call failed: {}", err)
+    )
+}
+
+/// Adds the key in the first column of `row`, encoded as by `pbloom_add`, to every filter.
+/// NULL keys are skipped.
+fn append_key(
+    filters: &mut [(String, Filter)],
+    row: &pgrx::spi::SpiHeapTupleData,
+    key_type: PgOid,
+) {
+    fn add<K: KeyEncode + ?Sized>(filters: &mut [(String, Filter)], key: Option<&K>) {
+        let Some(key) = key else {
+            return;
+        };
+        for (_, filter) in filters.iter_mut() {
+            filter.add_key(key).unwrap_or_else(invalid_filter);
+        }
+    }
+
+    match key_type {
+        PgOid::BuiltIn(PgBuiltInOids::BYTEAOID) => {
+            add(filters, row.get::<&[u8]>(1).unwrap_or_else(spi_error))
+        }
+        PgOid::BuiltIn(PgBuiltInOids::TEXTOID | PgBuiltInOids::VARCHAROID) => {
+            add(filters, row.get::<&str>(1).unwrap_or_else(spi_error))
+        }
+        PgOid::BuiltIn(PgBuiltInOids::INT8OID) => {
+            add(filters, row.get::<i64>(1).unwrap_or_else(spi_error).as_ref())
+        }
+        PgOid::BuiltIn(PgBuiltInOids::UUIDOID) => {
+            let key = row.get::<pgrx::Uuid>(1).unwrap_or_else(spi_error);
+            add(filters, key.as_ref().map(|key| key.as_bytes()))
+        }
+        PgOid::BuiltIn(PgBuiltInOids::TIMESTAMPTZOID) => {
+            let key = row.get::<TimestampWithTimeZone>(1).unwrap_or_else(spi_error);
+            add(filters, key.map(unix_micros).as_ref())
+        }
+        _ => ereport!(
+            ERROR,
+            PgSqlErrorCode::ERRCODE_DATATYPE_MISMATCH,
+            "key query must return bytea, text, bigint, uuid or timestamptz keys"
+        ),
+    }
+}
+
+/// Adds every key returned by `key_query` to the filters in column `filter_col` of `target`.
+///
+/// Each filter is deserialized and written back once, and the keys are read in batches, which
+/// is much cheaper than an `UPDATE ... SET filter = pbloom_add(filter, key)` per key. Every row
+/// of `target` is updated, and its rows are locked until the end of the transaction.
+///
+/// ```sql
+/// CALL pbloom.append_keys('filters', 'filter', 'SELECT email FROM users');
+/// ```
+#[pg_extern(
+    sql = r#"
+CREATE PROCEDURE pbloom.append_keys(target regclass, filter_col name, key_query text)
+    LANGUAGE c AS 'MODULE_PATHNAME', '@FUNCTION_NAME@';
+"#,
+    requires = ["pbloom_schema"]
+)]
+fn pbloom_append_keys(target: pg_sys::Oid, filter_col: &CStr, key_query: &str) {
+    Spi::connect(|mut client| {
+        let table = client
+            .select(
+                "SELECT $1::regclass::text, quote_ident($2)",
+                None,
+                Some(vec![
+                    (PgBuiltInOids::OIDOID.oid(), target.into_datum()),
+                    (PgBuiltInOids::TEXTOID.oid(), filter_col.to_str().ok().into_datum()),
+                ]),
+            )
+            .unwrap_or_else(spi_error)
+            .first();
+        let names = table.get_two::<String, String>().unwrap_or_else(spi_error);
+        let (Some(table), Some(column)) = names else {
+            ereport!(
+                ERROR,
+                PgSqlErrorCode::ERRCODE_INVALID_PARAMETER_VALUE,
+                "invalid target or filter column"
+            )
+        };
+
+        let mut filters = Vec::new();
+        let rows = client
+            .update(
+                &format!("SELECT ctid::text, {} FROM {} FOR UPDATE", column, table),
+                None,
+                None,
+            )
+            .unwrap_or_else(spi_error);
+        for row in rows {
+            let ctid = row.get::<String>(1).unwrap_or_else(spi_error);
+            let filter = row.get::<&[u8]>(2).unwrap_or_else(spi_error);
+            if let (Some(ctid), Some(filter)) = (ctid, filter) {
+                filters.push((ctid, load(filter)));
+            }
+        }
+
+        let mut cursor = client.open_cursor(key_query, None);
+        loop {
+            let batch = cursor.fetch(APPEND_BATCH_SIZE).unwrap_or_else(spi_error);
+            if batch.is_empty() {
+                break;
+            }
+            let key_type = batch.column_type_oid(1).unwrap_or_else(spi_error);
+            for row in batch {
+                append_key(&mut filters, &row, key_type);
+            }
+        }
+        drop(cursor);
+
+        let update = format!("UPDATE {} SET {} = $2 WHERE ctid = $1::tid", table, column);
+        for (ctid, filter) in &filters {
+            client
+                .update(
+                    &update,
+                    None,
+                    Some(vec![
+                        (PgBuiltInOids::TEXTOID.oid(), ctid.as_str().into_datum()),
+                        (PgBuiltInOids::BYTEAOID.oid(), store(filter).into_datum()),
+                    ]),
+                )
+                .unwrap_or_else(spi_error);
+        }
+    })
 }
 
 #[cfg(any(test, feature = "pg_test"))]
 #[pg_schema]
 mod tests {
+    use std::ffi::CStr;
+
     use pgrx::prelude::*;
 
+    const TRUNCATED: &[u8] = b"\xc4\x10abc";
+
     #[pg_test]
     fn test_pbloom_check() {
         let mut filter = pbloom::Filter::new_from_entries_and_fp(1000, 0.01).unwrap();
@@ -41,6 +879,567 @@ mod tests {
         assert_eq!(crate::pbloom_contains(filter_column.as_slice(), b"hello"), true);
     }
 
+    #[pg_test(error = "invalid pbloom filter: invalid format: truncated bit array")]
+    fn test_pbloom_contains_malformed() {
+        crate::pbloom_contains(TRUNCATED, b"hello");
+    }
+
+    #[pg_test(error = "invalid pbloom filter: invalid format: unexpected MessagePack type")]
+    fn test_pbloom_add_malformed() {
+        Spi::get_one::<Vec<u8>>("SELECT pbloom_add('garbage'::bytea, 'hello'::bytea)").unwrap();
+    }
+
+    #[pg_test]
+    fn test_pbloom_try_malformed() {
+        assert_eq!(crate::pbloom_try_contains(TRUNCATED, b"hello"), None);
+        assert_eq!(crate::pbloom_try_add(TRUNCATED, b"hello"), None);
+
+        let filter = crate::pbloom_create(1000, 0.01);
+        let added = crate::pbloom_try_add(&filter, b"hello").unwrap();
+        assert_eq!(crate::pbloom_try_contains(&added, b"hello"), Some(true));
+    }
+
+    #[pg_test]
+    fn test_pbloom_strict() {
+        let result = Spi::get_one::<bool>("SELECT pbloom_contains(NULL, 'hello'::bytea)").unwrap();
+        assert_eq!(result, None);
+    }
+
+    #[pg_test]
+    fn test_pbloom_typed_keys() {
+        use std::time::{Duration, UNIX_EPOCH};
+
+        let mut filter = pbloom::Filter::new(1000, 7);
+        filter.add_key("hello").unwrap();
+        filter.add_key(&42i64).unwrap();
+        filter.add_key(&[7u8; 16]).unwrap();
+        filter.add_key(&(UNIX_EPOCH + Duration::from_secs(1))).unwrap();
+        let filter = filter.serialize().unwrap();
+
+        assert!(crate::pbloom_contains_text(&filter, "hello"));
+        assert!(crate::pbloom_contains(&filter, b"hello"));
+        assert!(crate::pbloom_contains_bigint(&filter, 42));
+        assert!(!crate::pbloom_contains_bigint(&filter, 43));
+        assert!(crate::pbloom_contains_uuid(&filter, pgrx::Uuid::from_bytes([7; 16])));
+
+        let ts = Spi::get_one::<TimestampWithTimeZone>(
+            "SELECT '1970-01-01 00:00:01+00'::timestamptz",
+        )
+        .unwrap()
+        .unwrap();
+        assert!(crate::pbloom_contains_timestamptz(&filter, ts));
+
+        let result = Spi::get_one::<bool>(
+            "SELECT pbloom_contains_bigint(pbloom_add_bigint(pbloom_create(1000, 0.01), 42), 42)",
+        );
+        assert_eq!(result, Ok(Some(true)));
+
+        // Untyped literals still resolve to the bytea functions.
+        let filter = Spi::get_one::<Vec<u8>>(
+            "SELECT pbloom_add(pbloom_create(1000, 0.01), '\\x0102')",
+        )
+        .unwrap()
+        .unwrap();
+        assert!(crate::pbloom_contains(&filter, &[1, 2]));
+        let result = Spi::get_one::<bool>(&format!(
+            "SELECT pbloom_contains('\\x{}'::bytea, '\\x0102')",
+            filter.iter().map(|byte| format!("{:02x}", byte)).collect::<String>()
+        ));
+        assert_eq!(result, Ok(Some(true)));
+    }
+
+    #[pg_test]
+    fn test_pbloom_contains_selectivity() {
+        Spi::run(
+            "CREATE TABLE keys AS SELECT i::bigint AS key FROM generate_series(1, 10000) AS i;
+             ANALYZE keys",
+        )
+        .unwrap();
+        let plan = Spi::get_one::<pgrx::Json>(
+            "EXPLAIN (FORMAT JSON)
+             SELECT * FROM keys WHERE pbloom_contains_bigint(pbloom_create(1000, 0.01), key)",
+        )
+        .unwrap()
+        .unwrap();
+        // An empty filter matches nothing, so the estimate bottoms out at one row.
+        assert_eq!(plan.0[0]["Plan"]["Plan Rows"], 1);
+
+        // A filter of 1000 of the 10000 keys matches them plus about 1% of the rest.
+        let mut filter = crate::pbloom_create(1000, 0.01);
+        for key in 1..=1000 {
+            filter = crate::pbloom_add_bigint(&filter, key);
+        }
+        let literal: String = filter.iter().map(|byte| format!("{:02x}", byte)).collect();
+        let plan = Spi::get_one::<pgrx::Json>(&format!(
+            "EXPLAIN (FORMAT JSON)
+             SELECT * FROM keys WHERE pbloom_contains_bigint('\\x{}'::bytea, key)",
+            literal
+        ))
+        .unwrap()
+        .unwrap();
+        let rows = plan.0[0]["Plan"]["Plan Rows"].as_f64().unwrap();
+        assert!((900.0..1300.0).contains(&rows), "{}", rows);
+    }
+
+    #[pg_test]
+    fn test_pbloom_contains_selectivity_unanalyzed() {
+        Spi::run("CREATE TABLE keys AS SELECT i::bigint AS key FROM generate_series(1, 10000) AS i")
+            .unwrap();
+        let mut filter = crate::pbloom_create(1000, 0.01);
+        for key in 1..=1000 {
+            filter = crate::pbloom_add_bigint(&filter, key);
+        }
+        let literal: String = filter.iter().map(|byte| format!("{:02x}", byte)).collect();
+        let rows = |filter: &str| {
+            let plan = Spi::get_one::<pgrx::Json>(&format!(
+                "EXPLAIN (FORMAT JSON) SELECT * FROM keys WHERE {}",
+                filter
+            ))
+            .unwrap()
+            .unwrap();
+            plan.0[0]["Plan"]["Plan Rows"].as_f64().unwrap()
+        };
+        // Without key statistics the planner's default of a third of the rows is kept, rather
+        // than dividing the filter's items by a guessed 200 distinct keys.
+        let ratio = rows(&format!("pbloom_contains_bigint('\\x{}'::bytea, key)", literal)) / rows("true");
+        assert!((0.3..0.4).contains(&ratio), "{}", ratio);
+    }
+
+    #[pg_test]
+    fn test_pbloom_contains_column_selectivity() {
+        // 10 filters of 100 keys each, out of 10000 distinct keys: about 1000 true matches and
+        // 1000 false positives among the 100000 pairs.
+        Spi::run(
+            "CREATE TABLE keys AS SELECT i::bigint AS key FROM generate_series(1, 10000) AS i;
+             CREATE TABLE filters AS
+                 SELECT g, pbloom_union_agg(pbloom_add_bigint(pbloom_create(100, 0.01), key)) AS filter
+                 FROM keys, generate_series(0, 9) AS g
+                 WHERE key % 100 = g
+                 GROUP BY g;
+             ANALYZE keys;
+             ANALYZE filters",
+        )
+        .unwrap();
+        let plan = Spi::get_one::<pgrx::Json>(
+            "EXPLAIN (FORMAT JSON)
+             SELECT * FROM filters f JOIN keys t ON pbloom_contains_bigint(f.filter, t.key)",
+        )
+        .unwrap()
+        .unwrap();
+        // Without an estimate the planner would assume a third of the pairs match.
+        let rows = plan.0[0]["Plan"]["Plan Rows"].as_f64().unwrap();
+        assert!((1000.0..4000.0).contains(&rows), "{}", rows);
+    }
+
+    #[pg_test]
+    fn test_pbloom_contains_cached() {
+        Spi::run(
+            "CREATE TABLE keys AS SELECT i::bigint AS key FROM generate_series(1, 10000) AS i;
+             SET plan_cache_mode = force_generic_plan;
+             PREPARE lookup(bytea) AS SELECT count(*) FROM keys WHERE pbloom_contains_bigint($1, key)",
+        )
+        .unwrap();
+
+        let cases = [
+            "SELECT count(*) FROM keys WHERE pbloom_contains_bigint(pbloom_add_bigint(pbloom_create(1000, 0.01), 42), key)",
+            "EXECUTE lookup(pbloom_add_bigint(pbloom_create(1000, 0.01), 42))",
+        ];
+        for query in cases {
+            let plan = Spi::get_one::<pgrx::Json>(&format!("EXPLAIN (VERBOSE, FORMAT JSON) {}", query))
+                .unwrap()
+                .unwrap();
+            let filter = plan.0[0]["Plan"]["Plans"][0]["Filter"].to_string();
+            assert!(filter.contains("pbloom_contains_cached("), "{}", filter);
+            assert_eq!(Spi::get_one::<i64>(query).unwrap(), Some(1), "{}", query);
+        }
+
+        // Each execution of the prepared statement deserializes its own filter.
+        let count = Spi::get_one::<i64>(
+            "EXECUTE lookup(pbloom_add_bigint(pbloom_add_bigint(pbloom_create(1000, 0.01), 7), 8))",
+        )
+        .unwrap();
+        assert_eq!(count, Some(2));
+
+        // Filters read from a column differ between rows, so they are not cached.
+        Spi::run("CREATE TABLE filters AS SELECT pbloom_create(1000, 0.01) AS filter").unwrap();
+        let plan = Spi::get_one::<pgrx::Json>(
+            "EXPLAIN (VERBOSE, FORMAT JSON) SELECT pbloom_contains_bigint(filter, 42) FROM filters",
+        )
+        .unwrap()
+        .unwrap();
+        assert!(!plan.0[0].to_string().contains("pbloom_contains_cached"));
+    }
+
+    #[pg_test]
+    fn test_pbloom_rotate() {
+        let filter = crate::pbloom_aging_create(1000, 0.01, 2);
+        let filter = crate::pbloom_aging_add(&filter, b"old");
+        let filter = crate::pbloom_rotate(&filter);
+        let filter = crate::pbloom_aging_add(&filter, b"new");
+        assert!(crate::pbloom_aging_contains(&filter, b"old"));
+        assert!(crate::pbloom_aging_contains(&filter, b"new"));
+
+        let filter = crate::pbloom_rotate(&filter);
+        assert!(!crate::pbloom_aging_contains(&filter, b"old"));
+        assert!(crate::pbloom_aging_contains(&filter, b"new"));
+    }
+
+    #[pg_test(error = "invalid pbloom filter: invalid format: not an aging filter")]
+    fn test_pbloom_rotate_plain_filter() {
+        crate::pbloom_rotate(&crate::pbloom_create(1000, 0.01));
+    }
+
+    #[pg_test]
+    fn test_pbloom_compress() {
+        let filter = crate::pbloom_add(&crate::pbloom_create(100000, 0.01), b"hello");
+        let compressed = crate::pbloom_compress(&filter);
+        assert!(compressed.len() < filter.len() / 100);
+        assert!(crate::pbloom_contains(&compressed, b"hello"));
+        assert!(!crate::pbloom_contains(&compressed, b"world"));
+        assert_eq!(crate::pbloom_add(&compressed, b"world"), crate::pbloom_add(&filter, b"world"));
+    }
+
+    #[pg_test]
+    fn test_pbloom_union() {
+        let a = crate::pbloom_add(&crate::pbloom_create(1000, 0.01), b"hello");
+        let b = crate::pbloom_add(&crate::pbloom_create(1000, 0.01), b"world");
+
+        let union = crate::pbloom_union(&a, &b);
+        assert!(crate::pbloom_contains(&union, b"hello"));
+        assert!(crate::pbloom_contains(&union, b"world"));
+
+        let intersection = crate::pbloom_intersect(&a, &b);
+        assert!(!crate::pbloom_contains(&intersection, b"hello"));
+        assert!(!crate::pbloom_contains(&intersection, b"world"));
+    }
+
+    #[pg_test]
+    fn test_pbloom_union_agg() {
+        Spi::run(
+            "CREATE TABLE shards AS
+             SELECT pbloom_add(pbloom_create(1000, 0.01), key::bytea) AS filter
+             FROM unnest(ARRAY['a', 'b', 'c']) AS key
+             UNION ALL SELECT NULL",
+        )
+        .unwrap();
+        let merged = Spi::get_one::<Vec<u8>>("SELECT pbloom_union_agg(filter) FROM shards")
+            .unwrap()
+            .unwrap();
+        for key in [b"a", b"b", b"c"] {
+            assert!(crate::pbloom_contains(&merged, key));
+        }
+        let empty =
+            Spi::get_one::<Vec<u8>>("SELECT pbloom_union_agg(filter) FROM shards WHERE false")
+                .unwrap();
+        assert_eq!(empty, None);
+    }
+
+    #[pg_test(error = "cannot combine pbloom filters: incompatible filters: sizes differ")]
+    fn test_pbloom_union_mismatched() {
+        crate::pbloom_union(&crate::pbloom_create(1000, 0.01), &crate::pbloom_create(10, 0.01));
+    }
+
+    #[pg_test(error = "number of entries must be positive")]
+    fn test_pbloom_create_invalid() {
+        crate::pbloom_create(0, 0.01);
+    }
+
+    #[pg_test]
+    fn test_pbloom_upgrade() {
+        let mut filter = pbloom::Filter::new_from_entries_and_fp(1000, 0.01).unwrap();
+        filter.add(b"hello").unwrap();
+        let legacy = filter.serialize().unwrap();
+
+        let upgraded = crate::pbloom_upgrade(legacy);
+        assert_eq!(upgraded, filter.serialize_compressed().unwrap());
+        assert_eq!(crate::pbloom_upgrade(upgraded.clone()), upgraded);
+        assert_eq!(crate::pbloom_contains(&upgraded, b"hello"), true);
+    }
+
+    #[pg_test(error = "invalid pbloom filter: invalid format: truncated bit array")]
+    fn test_pbloom_upgrade_malformed() {
+        crate::pbloom_upgrade(TRUNCATED.to_vec());
+    }
+
+    #[pg_test]
+    fn test_pbloom_explain() {
+        let mut filter = pbloom::Filter::new(16, 3);
+        filter.add(b"hello").unwrap();
+        let filter_column = filter.serialize().unwrap();
+
+        let cases: [(&[u8], &str); 2] = [
+            (b"hello", "verdict: possibly present"),
+            (b"", "verdict: absent"),
+        ];
+        for (key, verdict) in cases {
+            let explanation = crate::pbloom_explain(&filter_column, key);
+            assert!(explanation.starts_with("probes: 3 over 128 bits\n"), "{}", explanation);
+            assert!(explanation.contains(verdict), "{}", explanation);
+        }
+    }
+
+    #[pg_test(error = "invalid pbloom filter: invalid format: truncated bit array")]
+    fn test_pbloom_explain_malformed() {
+        crate::pbloom_explain(TRUNCATED, b"hello");
+    }
+
+    #[pg_test]
+    fn test_pbloom_info() {
+        let cases = [
+            ("pbloom_create(1000, 0.01)", "filter", 1199),
+            ("pbloom_compress(pbloom_create(1000, 0.01))", "filter", 1199),
+            ("pbloom_aging_create(1000, 0.01, 3)", "aging", 3597),
+        ];
+        for (filter, kind, size) in cases {
+            let (info_kind, info_size) = Spi::get_two::<String, i64>(&format!(
+                "SELECT kind, size FROM pbloom_info({})",
+                filter
+            ))
+            .unwrap();
+            assert_eq!(info_kind.as_deref(), Some(kind), "{}", filter);
+            assert_eq!(info_size, Some(size), "{}", filter);
+        }
+
+        let fp_rate = Spi::get_one::<f64>(
+            "SELECT estimated_fp_rate FROM pbloom_info(pbloom_add(pbloom_create(1000, 0.01), 'hello'::bytea))",
+        )
+        .unwrap()
+        .unwrap();
+        assert!(fp_rate > 0.0 && fp_rate < 0.01, "{}", fp_rate);
+    }
+
+    #[pg_test(error = "invalid pbloom filter: invalid format: truncated bit array")]
+    fn test_pbloom_info_malformed() {
+        crate::pbloom_info(TRUNCATED);
+    }
+
+    #[pg_test]
+    fn test_pbloom_notify_updated() {
+        Spi::run(
+            "CREATE TABLE filters (name text, filter bytea);
+             CREATE TRIGGER filters_notify AFTER INSERT OR UPDATE ON filters
+                 FOR EACH ROW EXECUTE FUNCTION pbloom_notify_updated('name');
+             INSERT INTO filters VALUES ('users', pbloom_create(1000, 0.01));
+             UPDATE filters SET filter = pbloom_add(filter, 'hello'::bytea);
+             SET pbloom.notify_updates = off;
+             UPDATE filters SET filter = pbloom_add(filter, 'world'::bytea)",
+        )
+        .unwrap();
+        let setting = Spi::get_one::<String>("SHOW pbloom.notify_updates").unwrap();
+        assert_eq!(setting.as_deref(), Some("off"));
+    }
+
+    #[test]
+    fn test_pbloom_notify_updated_delivered() {
+        use postgres::fallible_iterator::FallibleIterator;
+
+        // Notifications are only sent on commit, which a `#[pg_test]` never reaches, so this
+        // listens from its own session. Running a `#[pg_test]` first starts the test instance.
+        pgrx_tests::run_test(
+            "test_pbloom_notify_updated",
+            None,
+            crate::pg_test::postgresql_conf_options(),
+        )
+        .unwrap();
+        let (mut client, _) = pgrx_tests::client().unwrap();
+        client
+            .batch_execute(
+                "CREATE TABLE notify_filters (name text, filter bytea);
+                 CREATE TRIGGER filters_notify AFTER INSERT OR UPDATE ON notify_filters
+                     FOR EACH ROW EXECUTE FUNCTION pbloom_notify_updated('name');
+                 LISTEN pbloom_updated",
+            )
+            .unwrap();
+
+        let cases: [(&str, &str, &[&str]); 3] = [
+            (
+                "insert",
+                "INSERT INTO notify_filters VALUES ('users', pbloom_create(1000, 0.01))",
+                &["users"],
+            ),
+            (
+                "disabled",
+                "SET pbloom.notify_updates = off;
+                 UPDATE notify_filters SET filter = pbloom_add(filter, 'hello'::bytea)",
+                &[],
+            ),
+            (
+                "enabled",
+                "SET pbloom.notify_updates = on;
+                 UPDATE notify_filters SET filter = pbloom_add(filter, 'world'::bytea)",
+                &["users"],
+            ),
+        ];
+        for (title, query, expected) in cases {
+            client.batch_execute(query).unwrap();
+            let payloads: Vec<String> = client
+                .notifications()
+                .iter()
+                .map(|notification| Ok(notification.payload().to_string()))
+                .collect()
+                .unwrap();
+            assert_eq!(payloads, expected, "{}", title);
+        }
+        client.batch_execute("DROP TABLE notify_filters").unwrap();
+    }
+
+    #[pg_test]
+    fn test_notify_payload() {
+        let cases: Vec<(&str, bool, Option<&str>, Option<&CStr>)> = vec![
+            ("enabled", true, Some("users"), Some(c"users")),
+            ("disabled", false, Some("users"), None),
+            ("null name", true, None, None),
+        ];
+
+        for (title, enabled, name, expected) in cases {
+            let mut resolved = false;
+            let payload = crate::notify_payload::<()>(enabled, || {
+                resolved = true;
+                Ok(name.map(String::from))
+            })
+            .unwrap();
+            assert_eq!(payload.as_deref(), expected, "{}", title);
+            assert_eq!(resolved, enabled, "{}", title);
+        }
+    }
+
+    #[pg_test]
+    fn test_pbloom_notify_updated_disabled() {
+        Spi::run(
+            "SET pbloom.notify_updates = off;
+             CREATE TABLE filters (name text, filter bytea);
+             CREATE TRIGGER filters_notify AFTER INSERT ON filters
+                 FOR EACH ROW EXECUTE FUNCTION pbloom_notify_updated('missing');
+             INSERT INTO filters VALUES ('users', pbloom_create(1000, 0.01))",
+        )
+        .unwrap();
+    }
+
+    #[pg_test(error = "cannot read filter name from text column \"missing\"")]
+    fn test_pbloom_notify_updated_missing_column() {
+        Spi::run(
+            "CREATE TABLE filters (name text, filter bytea);
+             CREATE TRIGGER filters_notify AFTER INSERT ON filters
+                 FOR EACH ROW EXECUTE FUNCTION pbloom_notify_updated('missing');
+             INSERT INTO filters VALUES ('users', pbloom_create(1000, 0.01))",
+        )
+        .unwrap();
+    }
+
+    /// Creates roles `tenant_a` and `tenant_b` for tenants `a` and `b`, and switches to `tenant_a`.
+    fn setup_tenants() {
+        Spi::run(
+            "CREATE ROLE tenant_a;
+             CREATE ROLE tenant_b;
+             GRANT USAGE ON SCHEMA pbloom TO tenant_a, tenant_b;
+             GRANT SELECT, INSERT, UPDATE ON pbloom.tenant_filters TO tenant_a, tenant_b;
+             GRANT SELECT ON pbloom.tenant_roles TO tenant_a, tenant_b;
+             INSERT INTO pbloom.tenant_roles VALUES ('a', 'tenant_a'), ('b', 'tenant_b');
+             SET ROLE tenant_a",
+        )
+        .unwrap();
+    }
+
+    #[pg_test]
+    fn test_pbloom_tenant_filters() {
+        setup_tenants();
+        Spi::run(
+            "SELECT pbloom.tenant_add_text('a', 'hello');
+             SELECT pbloom.tenant_add('a', 'world'::bytea)",
+        )
+        .unwrap();
+
+        let cases = [
+            ("a", "hello", true),
+            ("a", "world", true),
+            ("a", "other", false),
+            ("b", "hello", false),
+        ];
+        for (tenant, key, expected) in cases {
+            let contains = crate::pbloom_tenant_contains_text(tenant, key);
+            assert_eq!(contains, expected, "{} {}", tenant, key);
+        }
+
+        Spi::run("SET ROLE tenant_b").unwrap();
+        assert!(!crate::pbloom_tenant_contains_text("a", "hello"));
+
+        // The policy applies to the table owner too.
+        Spi::run(
+            "RESET ROLE;
+             CREATE ROLE tenant_owner;
+             ALTER TABLE pbloom.tenant_filters OWNER TO tenant_owner;
+             GRANT SELECT ON pbloom.tenant_roles TO tenant_owner;
+             SET ROLE tenant_owner",
+        )
+        .unwrap();
+        let visible = Spi::get_one::<i64>("SELECT count(*) FROM pbloom.tenant_filters").unwrap();
+        assert_eq!(visible, Some(0));
+    }
+
+    #[pg_test]
+    fn test_pbloom_append_keys() {
+        Spi::run(
+            "CREATE TABLE filters (name text, filter bytea);
+             INSERT INTO filters VALUES
+                 ('a', pbloom_create(1000, 0.01)), ('b', pbloom_add_text(pbloom_create(1000, 0.01), 'old'));
+             CALL pbloom.append_keys('filters', 'filter',
+                 'SELECT ''user'' || i FROM generate_series(1, 25000) i UNION ALL SELECT NULL')",
+        )
+        .unwrap();
+
+        let cases = [
+            ("a", "user1", true),
+            ("a", "user25000", true),
+            ("a", "old", false),
+            ("b", "user12345", true),
+            ("b", "old", true),
+        ];
+        for (name, key, expected) in cases {
+            let contains = Spi::get_one_with_args::<bool>(
+                "SELECT pbloom_contains_text(filter, $2) FROM filters WHERE name = $1",
+                vec![
+                    (PgBuiltInOids::TEXTOID.oid(), name.into_datum()),
+                    (PgBuiltInOids::TEXTOID.oid(), key.into_datum()),
+                ],
+            )
+            .unwrap();
+            assert_eq!(contains, Some(expected), "{} {}", name, key);
+        }
+    }
+
+    #[pg_test]
+    fn test_pbloom_append_keys_typed() {
+        Spi::run(
+            "CREATE TABLE filters (filter bytea);
+             INSERT INTO filters VALUES (pbloom_create(1000, 0.01));
+             CALL pbloom.append_keys('filters', 'filter', 'SELECT 42::bigint')",
+        )
+        .unwrap();
+        let contains = Spi::get_one::<bool>("SELECT pbloom_contains_bigint(filter, 42) FROM filters");
+        assert_eq!(contains.unwrap(), Some(true));
+    }
+
+    #[pg_test(error = "key query must return bytea, text, bigint, uuid or timestamptz keys")]
+    fn test_pbloom_append_keys_invalid_type() {
+        Spi::run(
+            "CREATE TABLE filters (filter bytea);
+             INSERT INTO filters VALUES (pbloom_create(1000, 0.01));
+             CALL pbloom.append_keys('filters', 'filter', 'SELECT 1.5::float8')",
+        )
+        .unwrap();
+    }
+
+    #[pg_test(error = "new row violates row-level security policy for table \"tenant_filters\"")]
+    fn test_pbloom_tenant_add_other_tenant() {
+        setup_tenants();
+        crate::pbloom_tenant_add_text("b", "hello");
+    }
+
+    #[pg_test(error = "permission denied for table tenant_roles")]
+    fn test_pbloom_tenant_roles_read_only() {
+        setup_tenants();
+        Spi::run("INSERT INTO pbloom.tenant_roles VALUES ('c', 'tenant_a')").unwrap();
+    }
 }
 
 /// This module is required by `cargo pgrx test` invocations.
@@ -0,0 +1,151 @@
+use std::borrow::Cow;
+
+use pyo3::exceptions::{PyTypeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyString};
+
+fn value_error(err: impl ToString) -> PyErr {
+    PyValueError::new_err(err.to_string())
+}
+
+/// Returns the bytes of a `bytes` key, or the UTF-8 encoding of a `str` key.
+fn key_bytes<'a>(key: &'a Bound<'_, PyAny>) -> PyResult<Cow<'a, [u8]>> {
+    if let Ok(bytes) = key.downcast::<PyBytes>() {
+        return Ok(Cow::Borrowed(bytes.as_bytes()));
+    }
+    if let Ok(string) = key.downcast::<PyString>() {
+        return Ok(Cow::Owned(string.to_cow()?.into_owned().into_bytes()));
+    }
+    Err(PyTypeError::new_err("key must be bytes or str"))
+}
+
+/// A Bloom filter that can be used from Python.
+///
+/// This wraps the same `pbloom::Filter` as the Postgres extension, so filters built by ETL jobs
+/// are byte-identical to ones built in Rust or Go.
+#[pyclass(module = "pbloom")]
+pub struct Filter {
+    inner: pbloom::Filter,
+}
+
+#[pymethods]
+impl Filter {
+    /// Creates a new `Filter` with the specified size in bytes and number of hash functions.
+    #[new]
+    pub fn new(size: usize, hash_count: u8) -> Self {
+        Self {
+            inner: pbloom::Filter::new(size, hash_count),
+        }
+    }
+
+    /// Creates a new `Filter` based on the number of entries and desired false positive rate.
+    #[staticmethod]
+    pub fn from_entries_and_fp(entries: usize, fp_rate: f64) -> PyResult<Self> {
+        let inner =
+            pbloom::Filter::new_from_entries_and_fp(entries, fp_rate).map_err(value_error)?;
+        Ok(Self { inner })
+    }
+
+    /// Deserializes a `Filter` from bytes produced by any pbloom implementation.
+    #[staticmethod]
+    pub fn from_serialized(serialized: &[u8]) -> PyResult<Self> {
+        let inner = pbloom::Filter::from_serialized(serialized).map_err(value_error)?;
+        Ok(Self { inner })
+    }
+
+    /// Returns the size of the filter in bytes.
+    #[getter]
+    pub fn size(&self) -> usize {
+        self.inner.size()
+    }
+
+    /// Returns the number of hash functions.
+    #[getter]
+    pub fn hash_count(&self) -> u8 {
+        self.inner.hash_count()
+    }
+
+    /// Adds a `bytes` or `str` key to the filter.
+    pub fn add(&mut self, key: &Bound<'_, PyAny>) -> PyResult<()> {
+        self.inner.add(&key_bytes(key)?).map_err(value_error)
+    }
+
+    /// Checks if a `bytes` or `str` key is present in the filter.
+    pub fn contains(&self, key: &Bound<'_, PyAny>) -> PyResult<bool> {
+        self.inner.contains(&key_bytes(key)?).map_err(value_error)
+    }
+
+    fn __contains__(&self, key: &Bound<'_, PyAny>) -> PyResult<bool> {
+        self.contains(key)
+    }
+
+    /// Serializes the filter into bytes.
+    pub fn serialize<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyBytes>> {
+        let serialized = self.inner.serialize().map_err(value_error)?;
+        Ok(PyBytes::new(py, &serialized))
+    }
+}
+
+#[pymodule]
+#[pyo3(name = "pbloom")]
+fn pbloom_py(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<Filter>()
+}
+
+#[cfg(test)]
+mod tests {
+    use sha2::{Digest, Sha256};
+
+    use super::*;
+
+    #[test]
+    fn test_golden_vectors() {
+        pyo3::prepare_freethreaded_python();
+        let vectors: Vec<serde_json::Value> =
+            serde_json::from_str(include_str!("../../testdata/golden.json")).unwrap();
+
+        Python::with_gil(|py| {
+            for vector in vectors.iter().filter(|vector| vector["variant"] == "plain") {
+                let size = vector["size"].as_u64().unwrap() as usize;
+                let hash_count = vector["hash_count"].as_u64().unwrap() as u8;
+                let mut filter = Filter::new(size, hash_count);
+                for i in 0..vector["keys"].as_u64().unwrap() {
+                    filter
+                        .add(PyString::new(py, &i.to_string()).as_any())
+                        .unwrap();
+                }
+
+                let serialized = filter.serialize(py).unwrap();
+                assert_eq!(
+                    hex::encode(Sha256::digest(serialized.as_bytes())),
+                    vector["sha256"].as_str().unwrap(),
+                    "{}",
+                    vector
+                );
+            }
+        });
+    }
+
+    #[test]
+    fn test_keys() {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let mut filter = Filter::from_entries_and_fp(1000, 0.01).unwrap();
+            filter.add(PyBytes::new(py, b"hello").as_any()).unwrap();
+            assert!(filter
+                .contains(PyString::new(py, "hello").as_any())
+                .unwrap());
+            assert!(!filter
+                .contains(PyString::new(py, "world").as_any())
+                .unwrap());
+            assert!(filter.contains(&1i32.into_pyobject(py).unwrap()).is_err());
+
+            let serialized = filter.serialize(py).unwrap();
+            let defilter = Filter::from_serialized(serialized.as_bytes()).unwrap();
+            assert_eq!(defilter.size(), 1199);
+            assert_eq!(defilter.hash_count(), 7);
+            assert!(Filter::from_serialized(b"garbage").is_err());
+            assert!(Filter::from_entries_and_fp(0, 0.01).is_err());
+        });
+    }
+}
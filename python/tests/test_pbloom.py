@@ -0,0 +1,33 @@
+import hashlib
+import json
+import pathlib
+
+import pytest
+
+import pbloom
+
+GOLDEN = pathlib.Path(__file__).parents[2] / "testdata" / "golden.json"
+
+
+def test_golden_vectors():
+    for vector in json.loads(GOLDEN.read_text()):
+        if vector["variant"] != "plain":
+            continue
+        f = pbloom.Filter(vector["size"], vector["hash_count"])
+        for i in range(vector["keys"]):
+            f.add(str(i))
+        assert hashlib.sha256(f.serialize()).hexdigest() == vector["sha256"], vector
+
+
+def test_keys():
+    f = pbloom.Filter.from_entries_and_fp(1000, 0.01)
+    f.add(b"hello")
+    assert "hello" in f
+    assert not f.contains("world")
+    with pytest.raises(TypeError):
+        f.contains(1)
+
+    g = pbloom.Filter.from_serialized(f.serialize())
+    assert (g.size, g.hash_count) == (1199, 7)
+    with pytest.raises(ValueError):
+        pbloom.Filter.from_serialized(b"garbage")
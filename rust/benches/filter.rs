@@ -0,0 +1,79 @@
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use pbloom::Filter;
+
+const ENTRIES: usize = 100_000;
+
+fn keys() -> Vec<Vec<u8>> {
+    (0..ENTRIES).map(|i| i.to_string().into_bytes()).collect()
+}
+
+fn filled() -> Filter {
+    let mut filter = Filter::new_from_entries_and_fp(ENTRIES, 0.01).unwrap();
+    for key in keys() {
+        filter.add(&key).unwrap();
+    }
+    filter
+}
+
+fn bench_filter(c: &mut Criterion) {
+    let keys = keys();
+    let filter = filled();
+
+    c.bench_function("add", |b| {
+        b.iter_batched_ref(
+            || Filter::new_from_entries_and_fp(ENTRIES, 0.01).unwrap(),
+            |filter| {
+                for key in &keys {
+                    filter.add(key).unwrap();
+                }
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    c.bench_function("contains", |b| {
+        b.iter(|| {
+            keys.iter()
+                .filter(|key| filter.contains(key).unwrap())
+                .count()
+        })
+    });
+    c.bench_function("contains_key", |b| {
+        b.iter(|| {
+            (0..ENTRIES as u64)
+                .filter(|key| filter.contains_key(key).unwrap())
+                .count()
+        })
+    });
+    c.bench_function("serialize", |b| b.iter(|| filter.serialize().unwrap()));
+    c.bench_function("serialize_compressed", |b| {
+        b.iter(|| filter.serialize_compressed().unwrap())
+    });
+
+    let serialized = filter.serialize().unwrap();
+    c.bench_function("from_serialized", |b| {
+        b.iter(|| Filter::from_serialized(&serialized).unwrap())
+    });
+}
+
+#[cfg(feature = "blocked")]
+fn bench_blocked(c: &mut Criterion) {
+    let keys = keys();
+    let mut filter = pbloom::BlockedFilter::new_from_entries_and_fp(ENTRIES, 0.01).unwrap();
+    for key in &keys {
+        filter.add(key).unwrap();
+    }
+
+    c.bench_function("blocked_contains", |b| {
+        b.iter(|| {
+            keys.iter()
+                .filter(|key| filter.contains(key).unwrap())
+                .count()
+        })
+    });
+}
+
+#[cfg(not(feature = "blocked"))]
+fn bench_blocked(_: &mut Criterion) {}
+
+criterion_group!(benches, bench_filter, bench_blocked);
+criterion_main!(benches);
@@ -0,0 +1,197 @@
+use std::collections::VecDeque;
+use std::io::Cursor;
+
+use rmp::{decode, encode};
+
+use crate::{Filter, FilterError};
+
+/// MessagePack extension type used to tag serialized `AgingFilter`s.
+const AGING_TAG: i8 = 4;
+
+/// A filter over a sliding window, made of generational sub-filters.
+///
+/// Items are added to the newest generation and looked up in all of them. `rotate` drops the
+/// oldest generation and starts a fresh one, so an item is forgotten after surviving as many
+/// rotations as there are generations.
+#[derive(Clone)]
+pub struct AgingFilter {
+    generations: VecDeque<Filter>,
+}
+
+impl AgingFilter {
+    /// Creates a new `AgingFilter` with `generations` sub-filters of the given size in bytes and
+    /// number of hash functions.
+    pub fn new(size: usize, hash_count: u8, generations: usize) -> Self {
+        Self {
+            generations: (0..generations.max(1))
+                .map(|_| Filter::new(size, hash_count))
+                .collect(),
+        }
+    }
+
+    /// Creates a new `AgingFilter` whose generations each hold `entries` items at the desired
+    /// false positive rate.
+    ///
+    /// The false positive rate of the whole filter is up to `generations` times higher.
+    pub fn new_from_entries_and_fp(
+        entries: usize,
+        fp_rate: f64,
+        generations: usize,
+    ) -> Result<Self, &'static str> {
+        if generations == 0 {
+            return Err("Number of generations must be positive");
+        }
+        let filter = Filter::new_from_entries_and_fp(entries, fp_rate)?;
+        Ok(Self::new(filter.bits.len(), filter.hash_count, generations))
+    }
+
+    /// Returns the number of generations.
+    pub fn generations(&self) -> usize {
+        self.generations.len()
+    }
+
+    /// Returns the newest generation, which new items are added to.
+    pub fn current(&self) -> &Filter {
+        self.generations.back().unwrap()
+    }
+
+    /// Returns the mean fraction of bits set across generations.
+    pub fn fill_ratio(&self) -> f64 {
+        self.generations.iter().map(Filter::fill_ratio).sum::<f64>() / self.generations.len() as f64
+    }
+
+    /// Estimates the current false positive rate: the chance that any generation matches.
+    pub fn estimated_fp_rate(&self) -> f64 {
+        1.0 - self
+            .generations
+            .iter()
+            .map(|filter| 1.0 - filter.estimated_fp_rate())
+            .product::<f64>()
+    }
+
+    /// Adds an item to the newest generation.
+    pub fn add(&mut self, item: &[u8]) -> Result<(), FilterError> {
+        self.generations.back_mut().unwrap().add(item)
+    }
+
+    /// Checks if an item is present in any generation.
+    pub fn contains(&self, item: &[u8]) -> Result<bool, FilterError> {
+        for filter in self.generations.iter().rev() {
+            if filter.contains(item)? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Drops the oldest generation and starts a fresh, empty one.
+    pub fn rotate(&mut self) {
+        let oldest = self.generations.pop_front().unwrap();
+        self.generations
+            .push_back(Filter::new(oldest.bits.len(), oldest.hash_count));
+    }
+
+    /// Deserializes an `AgingFilter` from a byte slice.
+    pub fn from_serialized(serialized: &[u8]) -> Result<Self, FilterError> {
+        let mut reader = Cursor::new(serialized);
+
+        let meta = decode::read_ext_meta(&mut reader)?;
+        if meta.typeid != AGING_TAG {
+            return Err(FilterError::InvalidFormat("not an aging filter"));
+        }
+
+        let count = decode::read_array_len(&mut reader)? as usize;
+        if count == 0 {
+            return Err(FilterError::InvalidFormat("no generations"));
+        }
+        let mut generations = VecDeque::with_capacity(count);
+        for _ in 0..count {
+            let len = decode::read_bin_len(&mut reader)? as usize;
+            let start = reader.position() as usize;
+            let Some(generation) = serialized.get(start..start + len) else {
+                return Err(FilterError::InvalidFormat("truncated generation"));
+            };
+            generations.push_back(Filter::from_serialized(generation)?);
+            reader.set_position((start + len) as u64);
+        }
+
+        let first = &generations[0];
+        if generations
+            .iter()
+            .any(|filter| first.check_compatible(filter).is_err())
+        {
+            return Err(FilterError::InvalidFormat("generations differ in shape"));
+        }
+
+        Ok(Self { generations })
+    }
+
+    /// Serializes all generations, oldest first, into a byte vector.
+    pub fn serialize(&self) -> Result<Vec<u8>, FilterError> {
+        let mut payload = Vec::new();
+        encode::write_array_len(&mut payload, self.generations.len() as u32)?;
+        for filter in &self.generations {
+            encode::write_bin(&mut payload, &filter.serialize()?)?;
+        }
+
+        let mut buf = Vec::with_capacity(payload.len() + 6);
+        encode::write_ext_meta(&mut buf, payload.len() as u32, AGING_TAG)?;
+        buf.extend_from_slice(&payload);
+        Ok(buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rotate() {
+        let mut filter = AgingFilter::new_from_entries_and_fp(1000, 0.01, 3).unwrap();
+        assert_eq!(filter.generations(), 3);
+
+        filter.add(b"first").unwrap();
+        filter.rotate();
+        filter.add(b"second").unwrap();
+        filter.rotate();
+        filter.add(b"third").unwrap();
+
+        for key in ["first", "second", "third"] {
+            assert!(filter.contains(key.as_bytes()).unwrap(), "{}", key);
+        }
+
+        filter.rotate();
+        assert!(!filter.contains(b"first").unwrap());
+        assert!(filter.contains(b"second").unwrap());
+        assert_eq!(filter.current().count_ones(), 0);
+
+        filter.rotate();
+        filter.rotate();
+        assert!(!filter.contains(b"third").unwrap());
+    }
+
+    #[test]
+    fn test_serialize() {
+        let mut filter = AgingFilter::new(1000, 7, 2);
+        filter.add(b"hello").unwrap();
+        filter.rotate();
+        filter.add(b"world").unwrap();
+
+        let serialized = filter.serialize().unwrap();
+        let mut defilter = AgingFilter::from_serialized(&serialized).unwrap();
+        assert_eq!(defilter.generations(), 2);
+        assert!(defilter.contains(b"hello").unwrap());
+        assert!(defilter.contains(b"world").unwrap());
+
+        defilter.rotate();
+        assert!(!defilter.contains(b"hello").unwrap());
+        assert!(defilter.contains(b"world").unwrap());
+
+        for len in 0..serialized.len() {
+            assert!(AgingFilter::from_serialized(&serialized[..len]).is_err());
+        }
+
+        let plain = Filter::new(1000, 7).serialize().unwrap();
+        assert!(AgingFilter::from_serialized(&plain).is_err());
+    }
+}
@@ -0,0 +1,150 @@
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use crate::{Filter, FilterError};
+
+/// A Bloom filter that can be shared between threads and populated without a lock.
+///
+/// Bits are stored in 64-bit words and set with atomic `fetch_or`, so `add` and `contains`
+/// only need `&self`. Bit positions match `Filter` exactly, which makes converting between
+/// the two a copy of the underlying bytes.
+pub struct AtomicFilter {
+    words: Vec<AtomicU64>,
+    size: usize,
+    hash_count: u8,
+    seed: u32,
+}
+
+impl AtomicFilter {
+    /// Creates a new `AtomicFilter` with the specified size in bytes and number of hash functions.
+    pub fn new(size: usize, hash_count: u8) -> Self {
+        Self {
+            words: (0..size.div_ceil(8)).map(|_| AtomicU64::new(0)).collect(),
+            size,
+            hash_count,
+            seed: 0,
+        }
+    }
+
+    /// Adds an item to the filter.
+    pub fn add(&self, item: &[u8]) -> Result<(), FilterError> {
+        let m = (self.size * 8) as u64;
+        let (h1, h2) = Filter::hash(item, self.seed);
+
+        for i in 0..self.hash_count as u64 {
+            let index = Filter::probe_index(h1, h2, i, m);
+            self.words[index / 64].fetch_or(1 << (index % 64), Ordering::Relaxed);
+        }
+        Ok(())
+    }
+
+    /// Checks if an item is present in the filter.
+    pub fn contains(&self, item: &[u8]) -> Result<bool, FilterError> {
+        let m = (self.size * 8) as u64;
+        let (h1, h2) = Filter::hash(item, self.seed);
+
+        Ok((0..self.hash_count as u64).all(|i| {
+            let index = Filter::probe_index(h1, h2, i, m);
+            self.words[index / 64].load(Ordering::Relaxed) & (1 << (index % 64)) != 0
+        }))
+    }
+
+    /// Copies the current contents into a plain `Filter`.
+    ///
+    /// Concurrent `add` calls may or may not be reflected in the snapshot.
+    pub fn to_filter(&self) -> Filter {
+        let mut bits = Vec::with_capacity(self.words.len() * 8);
+        for word in &self.words {
+            bits.extend_from_slice(&word.load(Ordering::Relaxed).to_le_bytes());
+        }
+        bits.truncate(self.size);
+
+        Filter {
+            bits,
+            hash_count: self.hash_count,
+            seed: self.seed,
+            limit: None,
+        }
+    }
+}
+
+impl From<Filter> for AtomicFilter {
+    fn from(filter: Filter) -> Self {
+        let words = filter
+            .bits
+            .chunks(8)
+            .map(|chunk| {
+                let mut word = [0u8; 8];
+                word[..chunk.len()].copy_from_slice(chunk);
+                AtomicU64::new(u64::from_le_bytes(word))
+            })
+            .collect();
+
+        Self {
+            words,
+            size: filter.bits.len(),
+            hash_count: filter.hash_count,
+            seed: filter.seed,
+        }
+    }
+}
+
+impl From<AtomicFilter> for Filter {
+    fn from(filter: AtomicFilter) -> Self {
+        filter.to_filter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::thread;
+
+    use super::*;
+
+    #[test]
+    fn test_concurrent_add() {
+        let atomic = Arc::new(AtomicFilter::new(1199, 7));
+        let mut expected = Filter::new(1199, 7);
+        for i in 0..1000 {
+            expected.add(i.to_string().as_bytes()).unwrap();
+        }
+
+        let handles: Vec<_> = (0..4)
+            .map(|t| {
+                let atomic = Arc::clone(&atomic);
+                thread::spawn(move || {
+                    for i in (t..1000).step_by(4) {
+                        atomic.add(i.to_string().as_bytes()).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        for i in 0..1000 {
+            assert!(atomic.contains(i.to_string().as_bytes()).unwrap());
+        }
+        assert_eq!(
+            atomic.to_filter().serialize().unwrap(),
+            expected.serialize().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_conversion() {
+        let mut filter = Filter::new(1001, 7);
+        filter.add(b"hello").unwrap();
+        filter.add(b"world").unwrap();
+
+        let atomic = AtomicFilter::from(filter.clone());
+        assert!(atomic.contains(b"hello").unwrap());
+        assert!(atomic.contains(b"world").unwrap());
+        assert!(!atomic.contains(b"foo").unwrap());
+
+        let converted = Filter::from(atomic);
+        assert_eq!(converted.serialize().unwrap(), filter.serialize().unwrap());
+    }
+}
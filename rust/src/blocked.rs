@@ -0,0 +1,220 @@
+use std::io::{Cursor, Read};
+
+use rmp::{decode, encode};
+
+use crate::{Filter, FilterError};
+
+/// Size of a block in bytes, chosen to match a typical cache line.
+const BLOCK_SIZE: usize = 64;
+
+/// Number of bits in a block.
+const BLOCK_BITS: u32 = (BLOCK_SIZE * 8) as u32;
+
+/// MessagePack extension type used to tag serialized `BlockedFilter`s.
+const BLOCKED_TAG: i8 = 1;
+
+/// A cache-line-blocked Bloom filter.
+///
+/// All probes for an item land in a single 64-byte block, so a lookup touches one cache line
+/// instead of `hash_count` random ones. The price is a slightly higher false positive rate than
+/// a `Filter` of the same size, since blocks do not fill evenly.
+#[derive(Clone)]
+pub struct BlockedFilter {
+    bits: Vec<u8>,
+    hash_count: u8,
+}
+
+impl BlockedFilter {
+    /// Creates a new `BlockedFilter` with the specified size in bytes and number of hash functions.
+    ///
+    /// The size is rounded up to a whole number of 64-byte blocks.
+    pub fn new(size: usize, hash_count: u8) -> Self {
+        let blocks = size.div_ceil(BLOCK_SIZE).max(1);
+        Self {
+            bits: vec![0; blocks * BLOCK_SIZE],
+            hash_count,
+        }
+    }
+
+    /// Creates a new `BlockedFilter` based on the number of entries and desired false positive rate.
+    ///
+    /// Sizing follows `Filter::new_from_entries_and_fp`, rounded up to whole blocks.
+    pub fn new_from_entries_and_fp(entries: usize, fp_rate: f64) -> Result<Self, &'static str> {
+        let filter = Filter::new_from_entries_and_fp(entries, fp_rate)?;
+        Ok(Self::new(filter.bits.len(), filter.hash_count))
+    }
+
+    /// Deserializes a `BlockedFilter` from a byte slice.
+    pub fn from_serialized(serialized: &[u8]) -> Result<Self, FilterError> {
+        let mut reader = Cursor::new(serialized);
+
+        let meta = decode::read_ext_meta(&mut reader)?;
+        if meta.typeid != BLOCKED_TAG {
+            return Err(FilterError::InvalidFormat("not a blocked filter"));
+        }
+        if meta.size == 0 || !(meta.size as usize).is_multiple_of(BLOCK_SIZE) {
+            return Err(FilterError::InvalidFormat(
+                "size is not a whole number of blocks",
+            ));
+        }
+        if serialized.len() - (reader.position() as usize) < meta.size as usize {
+            return Err(FilterError::InvalidFormat("truncated bit array"));
+        }
+        let mut bits = vec![0u8; meta.size as usize];
+        reader.read_exact(&mut bits)?;
+
+        let hash_count = decode::read_u8(&mut reader)?;
+
+        Ok(Self { bits, hash_count })
+    }
+
+    /// Returns the size of the filter in bytes.
+    pub fn size(&self) -> usize {
+        self.bits.len()
+    }
+
+    /// Returns the number of hash functions.
+    pub fn hash_count(&self) -> u8 {
+        self.hash_count
+    }
+
+    /// Returns the fraction of bits set.
+    pub fn fill_ratio(&self) -> f64 {
+        let ones: u64 = self.bits.iter().map(|byte| byte.count_ones() as u64).sum();
+        ones as f64 / (self.bits.len() * 8) as f64
+    }
+
+    /// Estimates the current false positive rate as for a `Filter` with the same fill ratio.
+    ///
+    /// Blocks fill unevenly, so the true rate is slightly higher.
+    pub fn estimated_fp_rate(&self) -> f64 {
+        self.fill_ratio().powi(self.hash_count as i32)
+    }
+
+    /// Returns the byte offset of the block for `h1` and the in-block bit of the `i`-th probe.
+    ///
+    /// The stride is forced odd, so it is coprime with the block size and the probes of an item
+    /// are distinct bits.
+    #[cfg_attr(
+        feature = "profiling",
+        inline(never),
+        tracing::instrument(level = "trace", skip_all)
+    )]
+    fn probe(&self, h1: u64, h2: u64, i: u32) -> (usize, u32) {
+        let blocks = (self.bits.len() / BLOCK_SIZE) as u64;
+        let offset = (h1 % blocks) as usize * BLOCK_SIZE;
+        let (a, b) = (h2 as u32, (h2 >> 32) as u32 | 1);
+        (offset, a.wrapping_add(i.wrapping_mul(b)) % BLOCK_BITS)
+    }
+
+    /// Adds an item to the filter.
+    pub fn add(&mut self, item: &[u8]) -> Result<(), FilterError> {
+        let (h1, h2) = Filter::hash(item, 0);
+
+        for i in 0..self.hash_count as u32 {
+            let (offset, bit) = self.probe(h1, h2, i);
+            self.bits[offset + (bit / 8) as usize] |= 1 << (bit % 8);
+        }
+        Ok(())
+    }
+
+    /// Checks if an item is present in the filter.
+    pub fn contains(&self, item: &[u8]) -> Result<bool, FilterError> {
+        let (h1, h2) = Filter::hash(item, 0);
+
+        Ok((0..self.hash_count as u32).all(|i| {
+            let (offset, bit) = self.probe(h1, h2, i);
+            self.bits[offset + (bit / 8) as usize] & (1 << (bit % 8)) != 0
+        }))
+    }
+
+    /// Serializes the filter into a byte vector.
+    pub fn serialize(&self) -> Result<Vec<u8>, FilterError> {
+        let mut buf = Vec::with_capacity(self.bits.len() + 7);
+        encode::write_ext_meta(&mut buf, self.bits.len() as u32, BLOCKED_TAG)?;
+        buf.extend_from_slice(&self.bits);
+        encode::write_u8(&mut buf, self.hash_count)?;
+        Ok(buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn test_blocked_filter() {
+        let mut filter = BlockedFilter::new_from_entries_and_fp(1000, 0.01).unwrap();
+        assert_eq!(filter.bits.len(), 1216);
+        assert_eq!(filter.hash_count, 7);
+
+        for i in 0..1000 {
+            filter.add(i.to_string().as_bytes()).unwrap();
+        }
+        for i in 0..1000 {
+            assert!(filter.contains(i.to_string().as_bytes()).unwrap());
+        }
+
+        let false_positives = (1000..11000)
+            .filter(|i| filter.contains(i.to_string().as_bytes()).unwrap())
+            .count();
+        assert!(false_positives < 300, "{} false positives", false_positives);
+    }
+
+    #[test]
+    fn test_serialize() {
+        let mut filter = BlockedFilter::new(1000, 7);
+        filter.add(b"hello").unwrap();
+        filter.add(b"world").unwrap();
+
+        let serialized = filter.serialize().unwrap();
+        let defilter = BlockedFilter::from_serialized(&serialized).unwrap();
+
+        assert!(defilter.contains(b"hello").unwrap());
+        assert!(defilter.contains(b"world").unwrap());
+        assert!(!defilter.contains(b"foo").unwrap());
+
+        let plain = Filter::new(1024, 7).serialize().unwrap();
+        assert!(BlockedFilter::from_serialized(&plain).is_err());
+
+        let mut huge = Vec::new();
+        encode::write_ext_meta(&mut huge, u32::MAX - 63, BLOCKED_TAG).unwrap();
+        assert!(matches!(
+            BlockedFilter::from_serialized(&huge),
+            Err(FilterError::InvalidFormat("truncated bit array"))
+        ));
+    }
+
+    #[test]
+    fn test_probe_stride() {
+        let filter = BlockedFilter::new(64, 8);
+        // Strides with large power-of-two factors, down to one sharing every factor with 512.
+        for stride in [0u64, 256, 512, 1 << 31] {
+            let mut bits: Vec<u32> = (0..8).map(|i| filter.probe(0, stride << 32, i).1).collect();
+            bits.sort();
+            bits.dedup();
+            assert_eq!(bits.len(), 8, "{}", stride);
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn test_no_false_negatives(
+            keys in prop::collection::vec(prop::collection::vec(any::<u8>(), 0..32), 0..200),
+            size in 1usize..512,
+            hash_count in 1u8..16,
+        ) {
+            let mut filter = BlockedFilter::new(size, hash_count);
+            for key in &keys {
+                filter.add(key).unwrap();
+            }
+            let defilter = BlockedFilter::from_serialized(&filter.serialize().unwrap()).unwrap();
+            for key in &keys {
+                prop_assert!(filter.contains(key).unwrap());
+                prop_assert!(defilter.contains(key).unwrap());
+            }
+        }
+    }
+}
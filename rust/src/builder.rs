@@ -0,0 +1,352 @@
+use std::fmt;
+
+use crate::{optimal_params, CapacityPolicy, Filter};
+
+/// Errors that can occur when building a `Filter` with `FilterBuilder`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BuildError {
+    /// Neither a size nor expected items and a false positive rate were given.
+    MissingSize,
+    /// A size was given without a hash count or expected items to derive one from.
+    MissingHashCount,
+    /// A false positive rate was given without expected items.
+    MissingExpectedItems,
+    /// Both a size and a false positive rate were given.
+    ConflictingSize,
+    /// The expected number of items is zero.
+    InvalidExpectedItems,
+    /// The false positive rate is not strictly between 0 and 1.
+    InvalidFalsePositiveRate,
+    /// The size is zero.
+    InvalidSize,
+    /// The hash count is zero or, when derived, does not fit in a `u8`.
+    InvalidHashCount,
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let msg = match self {
+            BuildError::MissingSize => {
+                "either a size or expected items and a false positive rate are required"
+            }
+            BuildError::MissingHashCount => {
+                "a hash count or expected items are required with an explicit size"
+            }
+            BuildError::MissingExpectedItems => "a false positive rate requires expected items",
+            BuildError::ConflictingSize => "a size and a false positive rate cannot both be given",
+            BuildError::InvalidExpectedItems => "expected items must be positive",
+            BuildError::InvalidFalsePositiveRate => "false positive rate must be between 0 and 1",
+            BuildError::InvalidSize => "size must be positive",
+            BuildError::InvalidHashCount => "hash count must be between 1 and 255",
+        };
+        f.write_str(msg)
+    }
+}
+
+impl std::error::Error for BuildError {}
+
+/// The parameters a `FilterBuilder` resolved to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FilterParams {
+    /// Size of the filter in bytes.
+    pub size: usize,
+    /// Number of hash functions.
+    pub hash_count: u8,
+    /// Hash seed.
+    pub seed: u32,
+    /// Expected false positive rate at the expected number of items, if it was given.
+    pub expected_fp_rate: Option<f64>,
+}
+
+impl FilterParams {
+    /// Returns the size of the filter in bits.
+    pub fn bits(&self) -> u64 {
+        self.size as u64 * 8
+    }
+}
+
+/// Builds a `Filter` from a validated combination of parameters.
+///
+/// The size comes from either `bits`/`bytes` or `expected_items` together with
+/// `false_positive_rate`. The hash count is derived from the size and expected items unless
+/// given with `hash_count`.
+///
+/// ```
+/// use pbloom::FilterBuilder;
+///
+/// let filter = FilterBuilder::new()
+///     .expected_items(1000)
+///     .false_positive_rate(0.01)
+///     .build()
+///     .unwrap();
+/// assert_eq!(filter.size(), 1199);
+/// assert_eq!(filter.hash_count(), 7);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct FilterBuilder {
+    expected_items: Option<usize>,
+    fp_rate: Option<f64>,
+    bits: Option<u64>,
+    hash_count: Option<u8>,
+    seed: u32,
+    capacity_policy: Option<CapacityPolicy>,
+}
+
+impl FilterBuilder {
+    /// Creates a new, empty `FilterBuilder`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the number of items the filter is sized for.
+    pub fn expected_items(mut self, items: usize) -> Self {
+        self.expected_items = Some(items);
+        self
+    }
+
+    /// Sets the target false positive rate at the expected number of items.
+    pub fn false_positive_rate(mut self, fp_rate: f64) -> Self {
+        self.fp_rate = Some(fp_rate);
+        self
+    }
+
+    /// Sets the size in bits, rounded up to a whole byte.
+    pub fn bits(mut self, bits: u64) -> Self {
+        self.bits = Some(bits);
+        self
+    }
+
+    /// Sets the size in bytes.
+    pub fn bytes(mut self, bytes: usize) -> Self {
+        self.bits = Some(bytes as u64 * 8);
+        self
+    }
+
+    /// Sets the number of hash functions.
+    pub fn hash_count(mut self, hash_count: u8) -> Self {
+        self.hash_count = Some(hash_count);
+        self
+    }
+
+    /// Sets the hash seed. Filters with a non-zero seed are kept by `serialize_compressed`; only
+    /// the legacy `serialize` format rejects them.
+    pub fn seed(mut self, seed: u32) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Sets what happens when more items than expected are added. The expected number of items
+    /// is recorded as the filter's capacity either way, with `CapacityPolicy::Ignore` by default.
+    pub fn capacity_policy(mut self, policy: CapacityPolicy) -> Self {
+        self.capacity_policy = Some(policy);
+        self
+    }
+
+    /// Validates the parameters and resolves the size and hash count.
+    pub fn params(&self) -> Result<FilterParams, BuildError> {
+        if self.expected_items == Some(0) {
+            return Err(BuildError::InvalidExpectedItems);
+        }
+        if let Some(fp_rate) = self.fp_rate {
+            if !(fp_rate > 0.0 && fp_rate < 1.0) {
+                return Err(BuildError::InvalidFalsePositiveRate);
+            }
+        }
+
+        let (size, derived_hash_count) = match (self.bits, self.fp_rate, self.expected_items) {
+            (Some(_), Some(_), _) => return Err(BuildError::ConflictingSize),
+            (Some(0), None, _) => return Err(BuildError::InvalidSize),
+            (Some(bits), None, items) => {
+                let size = bits.div_ceil(8) as usize;
+                let k = items.map(|items| {
+                    ((size * 8) as f64 / items as f64 * 2.0_f64.ln())
+                        .round()
+                        .max(1.0)
+                });
+                (size, k)
+            }
+            (None, Some(fp_rate), Some(items)) => {
+                let (size, k) = optimal_params(items, fp_rate);
+                (size, Some(k as f64))
+            }
+            (None, Some(_), None) => return Err(BuildError::MissingExpectedItems),
+            (None, None, _) => return Err(BuildError::MissingSize),
+        };
+
+        let hash_count = match (self.hash_count, derived_hash_count) {
+            (Some(k), _) => k,
+            (None, Some(k)) if k <= u8::MAX as f64 => k as u8,
+            (None, Some(_)) => return Err(BuildError::InvalidHashCount),
+            (None, None) => return Err(BuildError::MissingHashCount),
+        };
+        if hash_count == 0 {
+            return Err(BuildError::InvalidHashCount);
+        }
+
+        let expected_fp_rate = self.expected_items.map(|items| {
+            let k = hash_count as f64;
+            (1.0 - (-k * items as f64 / (size * 8) as f64).exp()).powf(k)
+        });
+
+        Ok(FilterParams {
+            size,
+            hash_count,
+            seed: self.seed,
+            expected_fp_rate,
+        })
+    }
+
+    /// Builds an empty `Filter`.
+    pub fn build(&self) -> Result<Filter, BuildError> {
+        let params = self.params()?;
+        let mut filter = Filter::new(params.size, params.hash_count);
+        filter.seed = params.seed;
+        if let Some(items) = self.expected_items {
+            let policy = self.capacity_policy.unwrap_or(CapacityPolicy::Ignore);
+            filter.set_capacity(items as u64, policy);
+        }
+        Ok(filter)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_params() {
+        let builder = FilterBuilder::new;
+        let cases = vec![
+            (
+                "items and fp",
+                builder().expected_items(1000).false_positive_rate(0.01),
+                Ok((1199, 7)),
+            ),
+            (
+                "bits rounded up",
+                builder().bits(1001).hash_count(3),
+                Ok((126, 3)),
+            ),
+            ("bytes", builder().bytes(1024).hash_count(5), Ok((1024, 5))),
+            (
+                "k from size and items",
+                builder().bytes(1200).expected_items(1000),
+                Ok((1200, 7)),
+            ),
+            (
+                "explicit k wins",
+                builder()
+                    .expected_items(1000)
+                    .false_positive_rate(0.01)
+                    .hash_count(3),
+                Ok((1199, 3)),
+            ),
+            ("nothing", builder(), Err(BuildError::MissingSize)),
+            (
+                "size only",
+                builder().bytes(64),
+                Err(BuildError::MissingHashCount),
+            ),
+            (
+                "fp only",
+                builder().false_positive_rate(0.01),
+                Err(BuildError::MissingExpectedItems),
+            ),
+            (
+                "size and fp",
+                builder()
+                    .bytes(64)
+                    .expected_items(10)
+                    .false_positive_rate(0.01),
+                Err(BuildError::ConflictingSize),
+            ),
+            (
+                "zero items",
+                builder().expected_items(0).false_positive_rate(0.01),
+                Err(BuildError::InvalidExpectedItems),
+            ),
+            (
+                "fp of 1",
+                builder().expected_items(10).false_positive_rate(1.0),
+                Err(BuildError::InvalidFalsePositiveRate),
+            ),
+            (
+                "fp of NaN",
+                builder().expected_items(10).false_positive_rate(f64::NAN),
+                Err(BuildError::InvalidFalsePositiveRate),
+            ),
+            (
+                "zero size",
+                builder().bits(0).hash_count(3),
+                Err(BuildError::InvalidSize),
+            ),
+            (
+                "zero k",
+                builder().bytes(64).hash_count(0),
+                Err(BuildError::InvalidHashCount),
+            ),
+            (
+                "k too large",
+                builder().bytes(1 << 20).expected_items(1),
+                Err(BuildError::InvalidHashCount),
+            ),
+        ];
+
+        for (title, builder, expected) in cases {
+            let actual = builder
+                .params()
+                .map(|params| (params.size, params.hash_count));
+            assert_eq!(actual, expected, "{}", title);
+        }
+    }
+
+    #[test]
+    fn test_build() {
+        let builder = FilterBuilder::new()
+            .expected_items(1000)
+            .false_positive_rate(0.01);
+        let params = builder.params().unwrap();
+        assert_eq!(params.bits(), 9592);
+        assert!(params.expected_fp_rate.unwrap() < 0.0101);
+
+        let filter = builder.build().unwrap();
+        let legacy = Filter::new_from_entries_and_fp(1000, 0.01).unwrap();
+        assert_eq!(filter.serialize().unwrap(), legacy.serialize().unwrap());
+        assert_eq!(filter.capacity(), Some(1000));
+
+        let mut strict = builder
+            .clone()
+            .expected_items(2)
+            .capacity_policy(CapacityPolicy::Error)
+            .build()
+            .unwrap();
+        strict.add(b"a").unwrap();
+        strict.add(b"b").unwrap();
+        assert!(matches!(
+            strict.add(b"c"),
+            Err(crate::FilterError::CapacityExceeded(2))
+        ));
+    }
+
+    #[test]
+    fn test_seed() {
+        let builder = FilterBuilder::new().bytes(1024).hash_count(7);
+        let mut unseeded = builder.build().unwrap();
+        let mut seeded = builder.clone().seed(42).build().unwrap();
+        assert_eq!(seeded.seed(), 42);
+
+        unseeded.add(b"hello").unwrap();
+        seeded.add(b"hello").unwrap();
+        assert!(seeded.contains(b"hello").unwrap());
+        assert_ne!(seeded.bits, unseeded.bits);
+
+        assert!(matches!(
+            seeded.union(&unseeded),
+            Err(crate::FilterError::Incompatible("seeds differ"))
+        ));
+        assert!(matches!(
+            seeded.serialize(),
+            Err(crate::FilterError::Unsupported(_))
+        ));
+    }
+}
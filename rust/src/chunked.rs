@@ -0,0 +1,303 @@
+use std::io::{Cursor, Read, Write};
+
+use crc32fast::hash as crc32;
+use rmp::{decode, encode};
+
+use crate::{Filter, FilterError};
+
+/// Record tag for a segment of the bit array.
+const SEGMENT: u8 = 0;
+
+/// Record tag for the finalize record that closes a chunked stream.
+const FINALIZE: u8 = 1;
+
+/// Writes a `Filter` as a sequence of independently checksummed segments.
+///
+/// A chunked stream is a series of segment records followed by a finalize record carrying the
+/// filter parameters and a checksum of the whole bit array. Since every record is
+/// deterministic, an interrupted transfer can be resumed by scanning what was received with
+/// `ResumePoint::scan`, truncating to its offset and calling `write_from` with its segment.
+pub struct ChunkedWriter<'a> {
+    filter: &'a Filter,
+    segment_size: usize,
+}
+
+impl<'a> ChunkedWriter<'a> {
+    /// Creates a new `ChunkedWriter` splitting the filter into segments of `segment_size` bytes.
+    ///
+    /// The finalize record has no room for a seed, so seeded filters are rejected.
+    pub fn new(filter: &'a Filter, segment_size: usize) -> Result<Self, FilterError> {
+        if filter.seed != 0 {
+            return Err(FilterError::Unsupported(
+                "seeded filters cannot be serialized",
+            ));
+        }
+        Ok(Self {
+            filter,
+            segment_size: segment_size.max(1),
+        })
+    }
+
+    /// Returns the number of segments the filter is split into.
+    pub fn segment_count(&self) -> u32 {
+        self.filter.bits.len().div_ceil(self.segment_size) as u32
+    }
+
+    /// Writes the segment with the given index.
+    pub fn write_segment<W: Write>(&self, index: u32, wr: &mut W) -> Result<(), FilterError> {
+        if index >= self.segment_count() {
+            return Err(FilterError::InvalidFormat("segment index out of range"));
+        }
+        let start = index as usize * self.segment_size;
+        let end = (start + self.segment_size).min(self.filter.bits.len());
+        let payload = &self.filter.bits[start..end];
+
+        encode::write_u8(wr, SEGMENT)?;
+        encode::write_u32(wr, index)?;
+        encode::write_bin(wr, payload)?;
+        encode::write_u32(wr, crc32(payload))?;
+        Ok(())
+    }
+
+    /// Writes the finalize record.
+    pub fn write_finalize<W: Write>(&self, wr: &mut W) -> Result<(), FilterError> {
+        encode::write_u8(wr, FINALIZE)?;
+        encode::write_u32(wr, self.segment_count())?;
+        encode::write_u64(wr, self.filter.bits.len() as u64)?;
+        encode::write_u8(wr, self.filter.hash_count)?;
+        encode::write_u32(wr, crc32(&self.filter.bits))?;
+        Ok(())
+    }
+
+    /// Writes every segment starting at `start`, followed by the finalize record.
+    pub fn write_from<W: Write>(&self, start: u32, wr: &mut W) -> Result<(), FilterError> {
+        for index in start..self.segment_count() {
+            self.write_segment(index, wr)?;
+        }
+        self.write_finalize(wr)
+    }
+
+    /// Writes the whole chunked stream.
+    pub fn write_all<W: Write>(&self, wr: &mut W) -> Result<(), FilterError> {
+        self.write_from(0, wr)
+    }
+}
+
+/// The point from which an interrupted chunked stream can be resumed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResumePoint {
+    /// Index of the first segment that still needs to be written.
+    pub segment: u32,
+    /// Length of the valid prefix of the received bytes.
+    pub offset: usize,
+    /// Whether the stream already ends with a valid finalize record.
+    pub complete: bool,
+}
+
+impl ResumePoint {
+    /// Scans a possibly truncated chunked stream for its last intact segment.
+    pub fn scan(received: &[u8]) -> Self {
+        let mut reader = Cursor::new(received);
+        let mut point = Self {
+            segment: 0,
+            offset: 0,
+            complete: false,
+        };
+
+        while let Ok(record) = read_record(&mut reader) {
+            match record {
+                Record::Segment { index, .. } if index == point.segment => {
+                    point.segment += 1;
+                    point.offset = reader.position() as usize;
+                }
+                Record::Finalize { segment_count, .. } if segment_count == point.segment => {
+                    point.complete = true;
+                    break;
+                }
+                _ => break,
+            }
+        }
+        point
+    }
+}
+
+enum Record {
+    Segment {
+        index: u32,
+        payload: Vec<u8>,
+    },
+    Finalize {
+        segment_count: u32,
+        len: u64,
+        hash_count: u8,
+        checksum: u32,
+    },
+}
+
+/// Reads a single record, verifying segment checksums.
+fn read_record(reader: &mut Cursor<&[u8]>) -> Result<Record, FilterError> {
+    match decode::read_u8(reader)? {
+        SEGMENT => {
+            let index = decode::read_u32(reader)?;
+            let len = decode::read_bin_len(reader)? as usize;
+            if reader.get_ref().len() - (reader.position() as usize) < len {
+                return Err(FilterError::InvalidFormat("truncated segment"));
+            }
+            let mut payload = vec![0u8; len];
+            reader.read_exact(&mut payload)?;
+            if decode::read_u32(reader)? != crc32(&payload) {
+                return Err(FilterError::InvalidFormat("segment checksum mismatch"));
+            }
+            Ok(Record::Segment { index, payload })
+        }
+        FINALIZE => Ok(Record::Finalize {
+            segment_count: decode::read_u32(reader)?,
+            len: decode::read_u64(reader)?,
+            hash_count: decode::read_u8(reader)?,
+            checksum: decode::read_u32(reader)?,
+        }),
+        _ => Err(FilterError::InvalidFormat("unknown chunk record")),
+    }
+}
+
+impl Filter {
+    /// Deserializes a `Filter` from a complete chunked stream written by `ChunkedWriter`.
+    pub fn from_chunked(serialized: &[u8]) -> Result<Self, FilterError> {
+        let mut reader = Cursor::new(serialized);
+        let mut bits = Vec::new();
+        let mut next = 0;
+
+        loop {
+            match read_record(&mut reader)? {
+                Record::Segment { index, payload } => {
+                    if index != next {
+                        return Err(FilterError::InvalidFormat("segment out of order"));
+                    }
+                    bits.extend_from_slice(&payload);
+                    next += 1;
+                }
+                Record::Finalize {
+                    segment_count,
+                    len,
+                    hash_count,
+                    checksum,
+                } => {
+                    if segment_count != next || len != bits.len() as u64 {
+                        return Err(FilterError::InvalidFormat("missing segments"));
+                    }
+                    if bits.is_empty() {
+                        return Err(FilterError::InvalidFormat("empty bit array"));
+                    }
+                    if checksum != crc32(&bits) {
+                        return Err(FilterError::InvalidFormat("filter checksum mismatch"));
+                    }
+                    return Ok(Self {
+                        bits,
+                        hash_count,
+                        seed: 0,
+                        limit: None,
+                    });
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter() -> Filter {
+        let mut filter = Filter::new(1000, 7);
+        for i in 0..100 {
+            filter.add(i.to_string().as_bytes()).unwrap();
+        }
+        filter
+    }
+
+    #[test]
+    fn test_chunked_roundtrip() {
+        let filter = filter();
+        let mut buf = Vec::new();
+        ChunkedWriter::new(&filter, 128)
+            .unwrap()
+            .write_all(&mut buf)
+            .unwrap();
+
+        let defilter = Filter::from_chunked(&buf).unwrap();
+        assert_eq!(defilter.serialize().unwrap(), filter.serialize().unwrap());
+
+        let point = ResumePoint::scan(&buf);
+        assert_eq!(point.segment, 8);
+        assert!(point.complete);
+    }
+
+    #[test]
+    fn test_chunked_resume() {
+        let filter = filter();
+        let writer = ChunkedWriter::new(&filter, 128).unwrap();
+        let mut full = Vec::new();
+        writer.write_all(&mut full).unwrap();
+
+        // Simulate a transfer that dropped partway through the fifth segment.
+        let mut received = full[..600].to_vec();
+        let point = ResumePoint::scan(&received);
+        assert_eq!(point.segment, 4);
+        assert!(!point.complete);
+        assert!(Filter::from_chunked(&received).is_err());
+
+        received.truncate(point.offset);
+        writer.write_from(point.segment, &mut received).unwrap();
+        assert_eq!(received, full);
+        assert!(Filter::from_chunked(&received).is_ok());
+    }
+
+    #[test]
+    fn test_chunked_corruption() {
+        let filter = filter();
+        let mut buf = Vec::new();
+        ChunkedWriter::new(&filter, 128)
+            .unwrap()
+            .write_all(&mut buf)
+            .unwrap();
+
+        buf[20] ^= 0xFF;
+        assert!(Filter::from_chunked(&buf).is_err());
+        assert_eq!(ResumePoint::scan(&buf).segment, 0);
+
+        // A segment declaring a 4 GiB payload in a few bytes of input.
+        let mut huge = Vec::new();
+        encode::write_u8(&mut huge, SEGMENT).unwrap();
+        encode::write_u32(&mut huge, 0).unwrap();
+        encode::write_bin_len(&mut huge, u32::MAX).unwrap();
+        assert!(matches!(
+            Filter::from_chunked(&huge),
+            Err(FilterError::InvalidFormat("truncated segment"))
+        ));
+        assert_eq!(ResumePoint::scan(&huge).segment, 0);
+    }
+
+    #[test]
+    fn test_write_segment_out_of_range() {
+        let filter = filter();
+        let writer = ChunkedWriter::new(&filter, 128).unwrap();
+        let mut buf = Vec::new();
+        for index in [writer.segment_count(), u32::MAX] {
+            assert!(matches!(
+                writer.write_segment(index, &mut buf),
+                Err(FilterError::InvalidFormat("segment index out of range"))
+            ));
+        }
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn test_chunked_seeded() {
+        let mut filter = filter();
+        filter.seed = 42;
+        assert!(matches!(
+            ChunkedWriter::new(&filter, 128),
+            Err(FilterError::Unsupported(_))
+        ));
+    }
+}
@@ -0,0 +1,285 @@
+use std::io::Cursor;
+
+use rmp::{decode, encode};
+
+use crate::{Filter, FilterError};
+
+/// MessagePack extension type used to tag serialized `ClockFilter`s.
+const CLOCK_TAG: i8 = 6;
+
+/// A filter of recently seen items under a fixed memory budget, such as the doorkeeper of a
+/// TinyLFU cache admission policy.
+///
+/// The bits are split into regions, and a clock hand points at the one new items are added to.
+/// Every `period` insertions, or on each explicit `tick`, the hand moves to the next region and
+/// clears it, forgetting the items added a full revolution ago. Memory never grows: an item is
+/// remembered for between `regions - 1` and `regions` periods.
+#[derive(Clone)]
+pub struct ClockFilter {
+    regions: Vec<Filter>,
+    hand: usize,
+    period: u64,
+    inserted: u64,
+}
+
+impl ClockFilter {
+    /// Creates a new `ClockFilter` with `regions` regions of the given size in bytes and number
+    /// of hash functions, advancing every `period` insertions.
+    ///
+    /// A `period` of 0 only advances on `tick`.
+    pub fn new(size: usize, hash_count: u8, regions: usize, period: u64) -> Self {
+        Self {
+            regions: (0..regions.max(2))
+                .map(|_| Filter::new(size, hash_count))
+                .collect(),
+            hand: 0,
+            period,
+            inserted: 0,
+        }
+    }
+
+    /// Creates a new `ClockFilter` remembering roughly the last `entries` distinct items at the
+    /// desired false positive rate.
+    pub fn new_from_entries_and_fp(
+        entries: usize,
+        fp_rate: f64,
+        regions: usize,
+    ) -> Result<Self, &'static str> {
+        if regions < 2 {
+            return Err("Number of regions must be at least 2");
+        }
+        let per_region = entries.div_ceil(regions - 1);
+        let filter = Filter::new_from_entries_and_fp(per_region, fp_rate / regions as f64)?;
+        Ok(Self::new(
+            filter.bits.len(),
+            filter.hash_count,
+            regions,
+            per_region as u64,
+        ))
+    }
+
+    /// Returns the number of regions.
+    pub fn regions(&self) -> usize {
+        self.regions.len()
+    }
+
+    /// Returns the total size of the regions in bytes.
+    pub fn size(&self) -> usize {
+        self.regions.iter().map(Filter::size).sum()
+    }
+
+    /// Returns the number of hash functions.
+    pub fn hash_count(&self) -> u8 {
+        self.regions[0].hash_count
+    }
+
+    /// Returns the number of insertions between advances of the hand, or 0 if it only advances
+    /// on `tick`.
+    pub fn period(&self) -> u64 {
+        self.period
+    }
+
+    /// Returns the mean fraction of bits set across regions.
+    pub fn fill_ratio(&self) -> f64 {
+        self.regions.iter().map(Filter::fill_ratio).sum::<f64>() / self.regions.len() as f64
+    }
+
+    /// Estimates the current false positive rate: the chance that any region matches.
+    pub fn estimated_fp_rate(&self) -> f64 {
+        1.0 - self
+            .regions
+            .iter()
+            .map(|filter| 1.0 - filter.estimated_fp_rate())
+            .product::<f64>()
+    }
+
+    /// Adds an item to the region under the hand, advancing the hand once the period is over.
+    pub fn add(&mut self, item: &[u8]) -> Result<(), FilterError> {
+        self.regions[self.hand].add(item)?;
+        self.inserted += 1;
+        if self.period > 0 && self.inserted >= self.period {
+            self.tick();
+        }
+        Ok(())
+    }
+
+    /// Checks if an item was added within roughly the last revolution of the hand.
+    pub fn contains(&self, item: &[u8]) -> Result<bool, FilterError> {
+        let hashes = Filter::hash_key(item);
+        for i in 0..self.regions.len() {
+            // Newest region first, as recently added items are the most likely to be looked up.
+            let region = &self.regions[(self.hand + self.regions.len() - i) % self.regions.len()];
+            if region.contains_hashed(hashes)? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Moves the hand to the next region and clears it.
+    pub fn tick(&mut self) {
+        self.hand = (self.hand + 1) % self.regions.len();
+        self.regions[self.hand].bits.fill(0);
+        self.inserted = 0;
+    }
+
+    /// Clears every region.
+    pub fn clear(&mut self) {
+        for region in &mut self.regions {
+            region.bits.fill(0);
+        }
+        self.inserted = 0;
+    }
+
+    /// Deserializes a `ClockFilter` from a byte slice.
+    pub fn from_serialized(serialized: &[u8]) -> Result<Self, FilterError> {
+        let mut reader = Cursor::new(serialized);
+
+        let meta = decode::read_ext_meta(&mut reader)?;
+        if meta.typeid != CLOCK_TAG {
+            return Err(FilterError::InvalidFormat("not a clock filter"));
+        }
+
+        let hand = decode::read_u32(&mut reader)? as usize;
+        let period = decode::read_u64(&mut reader)?;
+        let inserted = decode::read_u64(&mut reader)?;
+
+        let count = decode::read_array_len(&mut reader)? as usize;
+        if count < 2 {
+            return Err(FilterError::InvalidFormat("fewer than 2 regions"));
+        }
+        if hand >= count {
+            return Err(FilterError::InvalidFormat("hand out of range"));
+        }
+        let mut regions = Vec::with_capacity(count);
+        for _ in 0..count {
+            let len = decode::read_bin_len(&mut reader)? as usize;
+            let start = reader.position() as usize;
+            let Some(region) = serialized.get(start..start + len) else {
+                return Err(FilterError::InvalidFormat("truncated region"));
+            };
+            regions.push(Filter::from_serialized(region)?);
+            reader.set_position((start + len) as u64);
+        }
+
+        let first = &regions[0];
+        if regions
+            .iter()
+            .any(|filter| first.check_compatible(filter).is_err())
+        {
+            return Err(FilterError::InvalidFormat("regions differ in shape"));
+        }
+
+        Ok(Self {
+            regions,
+            hand,
+            period,
+            inserted,
+        })
+    }
+
+    /// Serializes the clock state and all regions into a byte vector.
+    pub fn serialize(&self) -> Result<Vec<u8>, FilterError> {
+        let mut payload = Vec::new();
+        encode::write_u32(&mut payload, self.hand as u32)?;
+        encode::write_u64(&mut payload, self.period)?;
+        encode::write_u64(&mut payload, self.inserted)?;
+        encode::write_array_len(&mut payload, self.regions.len() as u32)?;
+        for region in &self.regions {
+            encode::write_bin(&mut payload, &region.serialize()?)?;
+        }
+
+        let mut buf = Vec::with_capacity(payload.len() + 6);
+        encode::write_ext_meta(&mut buf, payload.len() as u32, CLOCK_TAG)?;
+        buf.extend_from_slice(&payload);
+        Ok(buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clock() {
+        let mut filter = ClockFilter::new_from_entries_and_fp(1000, 0.01, 4).unwrap();
+        assert_eq!(filter.regions(), 4);
+        assert_eq!(filter.period(), 334);
+        let size = filter.size();
+
+        let keys = |range: std::ops::Range<u32>| range.map(|i| i.to_string().into_bytes());
+        for key in keys(0..1000) {
+            filter.add(&key).unwrap();
+        }
+        assert!(keys(0..1000).all(|key| filter.contains(&key).unwrap()));
+
+        // A full revolution later, the first period's keys are gone but the memory is the same.
+        for key in keys(1000..2000) {
+            filter.add(&key).unwrap();
+        }
+        assert_eq!(filter.size(), size);
+        assert!(keys(1000..2000).all(|key| filter.contains(&key).unwrap()));
+        let remembered = keys(0..334)
+            .filter(|key| filter.contains(key).unwrap())
+            .count();
+        assert!(remembered < 10, "{}", remembered);
+
+        let false_positives = keys(10000..20000)
+            .filter(|key| filter.contains(key).unwrap())
+            .count();
+        assert!(false_positives < 150, "{}", false_positives);
+
+        assert!(ClockFilter::new_from_entries_and_fp(1000, 0.01, 1).is_err());
+    }
+
+    #[test]
+    fn test_tick() {
+        let mut filter = ClockFilter::new(1000, 7, 3, 0);
+        filter.add(b"first").unwrap();
+        filter.tick();
+        filter.add(b"second").unwrap();
+        filter.tick();
+
+        let cases = [(0, true, true), (1, false, true), (2, false, false)];
+        for (ticks, first, second) in cases {
+            let mut filter = filter.clone();
+            for _ in 0..ticks {
+                filter.tick();
+            }
+            assert_eq!(filter.contains(b"first").unwrap(), first, "{}", ticks);
+            assert_eq!(filter.contains(b"second").unwrap(), second, "{}", ticks);
+        }
+
+        filter.clear();
+        assert!(!filter.contains(b"second").unwrap());
+    }
+
+    #[test]
+    fn test_serialize() {
+        let mut filter = ClockFilter::new(1000, 7, 3, 2);
+        for key in ["a", "b", "c"] {
+            filter.add(key.as_bytes()).unwrap();
+        }
+
+        let serialized = filter.serialize().unwrap();
+        let mut defilter = ClockFilter::from_serialized(&serialized).unwrap();
+        assert_eq!(defilter.regions(), 3);
+        assert_eq!(defilter.period(), 2);
+        for key in ["a", "b", "c"] {
+            assert!(defilter.contains(key.as_bytes()).unwrap(), "{}", key);
+        }
+
+        // The hand position and insertion count survive, so the next advance is unchanged.
+        defilter.add(b"d").unwrap();
+        defilter.tick();
+        assert!(!defilter.contains(b"a").unwrap());
+        assert!(defilter.contains(b"c").unwrap());
+
+        for len in 0..serialized.len() {
+            assert!(ClockFilter::from_serialized(&serialized[..len]).is_err());
+        }
+
+        let plain = Filter::new(1000, 7).serialize().unwrap();
+        assert!(ClockFilter::from_serialized(&plain).is_err());
+    }
+}
@@ -0,0 +1,154 @@
+//! Archived filters serialized in every format this crate has written, for checking that stored
+//! filters, such as Postgres columns, stay readable across upgrades.
+//!
+//! Each fixture was built by adding the keys `"0"`, `"1"`, ... to an empty filter, except for
+//! `v1-rle`, whose bits were set directly. Fixtures are never regenerated: a new format version
+//! adds new ones.
+
+use alloc::string::ToString;
+
+use crate::{Filter, FilterError};
+
+/// A filter serialized by a past version of the crate, with the parameters it was built with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fixture {
+    /// Short name of the format and variant, such as `legacy-bin16`.
+    pub name: &'static str,
+    /// The serialized filter.
+    pub serialized: &'static [u8],
+    /// Size of the filter in bytes.
+    pub size: usize,
+    /// Number of hash functions.
+    pub hash_count: u8,
+    /// Hash seed.
+    pub seed: u32,
+    /// Number of keys added.
+    pub keys: u32,
+    /// Number of bits set.
+    pub ones: u64,
+}
+
+/// All archived fixtures, oldest format first.
+pub static FIXTURES: &[Fixture] = &[
+    Fixture {
+        name: "legacy-empty",
+        serialized: include_bytes!("../fixtures/legacy-empty.pbloom"),
+        size: 16,
+        hash_count: 1,
+        seed: 0,
+        keys: 0,
+        ones: 0,
+    },
+    Fixture {
+        name: "legacy-bin8",
+        serialized: include_bytes!("../fixtures/legacy-bin8.pbloom"),
+        size: 64,
+        hash_count: 3,
+        seed: 0,
+        keys: 20,
+        ones: 58,
+    },
+    Fixture {
+        name: "legacy-bin16",
+        serialized: include_bytes!("../fixtures/legacy-bin16.pbloom"),
+        size: 1199,
+        hash_count: 7,
+        seed: 0,
+        keys: 500,
+        ones: 2945,
+    },
+    Fixture {
+        name: "v1-raw",
+        serialized: include_bytes!("../fixtures/v1-raw.pbloom"),
+        size: 1199,
+        hash_count: 7,
+        seed: 0,
+        keys: 500,
+        ones: 2945,
+    },
+    Fixture {
+        name: "v1-sparse",
+        serialized: include_bytes!("../fixtures/v1-sparse.pbloom"),
+        size: 1199,
+        hash_count: 7,
+        seed: 0,
+        keys: 10,
+        ones: 69,
+    },
+    Fixture {
+        name: "v1-seeded",
+        serialized: include_bytes!("../fixtures/v1-seeded.pbloom"),
+        size: 1199,
+        hash_count: 7,
+        seed: 42,
+        keys: 10,
+        ones: 70,
+    },
+    Fixture {
+        name: "v1-rle",
+        serialized: include_bytes!("../fixtures/v1-rle.pbloom"),
+        size: 64,
+        hash_count: 3,
+        seed: 0,
+        keys: 0,
+        ones: 64,
+    },
+];
+
+impl Fixture {
+    /// Deserializes the fixture, checking it matches the parameters and keys it was built with.
+    pub fn load(&self) -> Result<Filter, FilterError> {
+        let filter = Filter::from_serialized(self.serialized)?;
+        if filter.size() != self.size
+            || filter.hash_count() != self.hash_count
+            || filter.seed() != self.seed
+            || filter.count_ones() != self.ones
+        {
+            return Err(FilterError::InvalidFormat("fixture parameters differ"));
+        }
+        for i in 0..self.keys {
+            if !filter.contains(i.to_string().as_bytes())? {
+                return Err(FilterError::InvalidFormat("fixture is missing keys"));
+            }
+        }
+        Ok(filter)
+    }
+}
+
+/// Loads every fixture, returning the name of the first that fails along with its error.
+pub fn validate_all() -> Result<(), (&'static str, FilterError)> {
+    for fixture in FIXTURES {
+        fixture.load().map_err(|err| (fixture.name, err))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fixtures() {
+        validate_all().unwrap();
+
+        for fixture in FIXTURES {
+            let filter = fixture.load().unwrap();
+            let upgraded = Filter::upgrade_in_place(fixture.serialized.to_vec()).unwrap();
+            let reloaded = Filter::from_serialized(&upgraded).unwrap();
+            assert_eq!(reloaded.bits, filter.bits, "{}", fixture.name);
+            assert_eq!(reloaded.seed(), fixture.seed, "{}", fixture.name);
+            if fixture.name.starts_with("v1-") {
+                assert_eq!(upgraded, fixture.serialized, "{}", fixture.name);
+            }
+        }
+
+        // Upgrading a legacy filter gives exactly what the current version writes.
+        let legacy = FIXTURES.iter().find(|f| f.name == "legacy-bin16").unwrap();
+        let current = FIXTURES.iter().find(|f| f.name == "v1-raw").unwrap();
+        assert_eq!(
+            Filter::upgrade_in_place(legacy.serialized.to_vec()).unwrap(),
+            current.serialized
+        );
+        assert!(Filter::upgrade_in_place(b"garbage".to_vec()).is_err());
+    }
+}
@@ -0,0 +1,474 @@
+//! Tools for checking whether a key encoding is safe to use at a given filter size, for
+//! auditing that a filter still contains the keys it was built from, for comparing the false
+//! positives seen in production with the expected rate, and for explaining a single lookup.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::{Filter, FilterError};
+
+/// A group of keys that map to exactly the same bits.
+///
+/// Adding any one of them makes every other key in the group a false positive, whatever else
+/// the filter contains.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Collision {
+    /// The colliding keys, in the order they were sampled.
+    pub keys: Vec<Vec<u8>>,
+    /// The sorted, deduplicated bit indexes the keys probe.
+    pub probes: Vec<usize>,
+}
+
+/// Seed for the hash that picks which keys an audit samples.
+const AUDIT_SEED: u32 = 0x6175_6474;
+
+/// Maximum number of missing keys kept in an `AuditReport`.
+const MAX_MISSING_SAMPLES: usize = 16;
+
+/// The outcome of `Filter::audit`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AuditReport {
+    /// Number of source keys read.
+    pub scanned: u64,
+    /// Number of keys checked against the filter.
+    pub sampled: u64,
+    /// Number of sampled keys the filter reported as absent.
+    pub missing: u64,
+    /// Up to 16 of the missing keys, in source order.
+    pub missing_samples: Vec<Vec<u8>>,
+}
+
+impl AuditReport {
+    /// Returns whether every sampled key was present.
+    pub fn passed(&self) -> bool {
+        self.missing == 0
+    }
+}
+
+/// Number of key patterns kept in a `FalsePositiveReport`.
+const TOP_PATTERNS: usize = 10;
+
+/// Maximum length of a key pattern, in characters.
+const MAX_PATTERN_LEN: usize = 32;
+
+/// Running counts of lookups that the source of truth confirmed absent.
+///
+/// Record every confirmed negative with `record_negative` and every confirmed false positive
+/// with `Filter::record_false_positive`, then compare the observed rate with the expected one
+/// using `Filter::false_positive_report`.
+#[derive(Debug, Clone, Default)]
+pub struct FalsePositiveLog {
+    negatives: u64,
+    false_positives: u64,
+    patterns: HashMap<String, u64>,
+}
+
+impl FalsePositiveLog {
+    /// Creates a new, empty `FalsePositiveLog`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records a lookup of an absent key that the filter correctly reported as absent.
+    pub fn record_negative(&mut self) {
+        self.negatives += 1;
+    }
+
+    /// Returns the number of confirmed false positives recorded.
+    pub fn false_positives(&self) -> u64 {
+        self.false_positives
+    }
+
+    /// Returns the number of lookups of absent keys recorded, including false positives.
+    pub fn lookups(&self) -> u64 {
+        self.negatives + self.false_positives
+    }
+}
+
+/// Returns the shape of a key: runs of digits become `#`, and other bytes are kept.
+///
+/// Keys that are not UTF-8 are grouped by length only.
+fn key_pattern(key: &[u8]) -> String {
+    let Ok(key) = std::str::from_utf8(key) else {
+        return format!("<{} bytes>", key.len());
+    };
+    let mut pattern = String::new();
+    for c in key.chars() {
+        if pattern.chars().count() >= MAX_PATTERN_LEN {
+            pattern.push('…');
+            break;
+        }
+        if !c.is_ascii_digit() {
+            pattern.push(c);
+        } else if !pattern.ends_with('#') {
+            pattern.push('#');
+        }
+    }
+    pattern
+}
+
+/// The outcome of `Filter::false_positive_report`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FalsePositiveReport {
+    /// Number of lookups of absent keys recorded.
+    pub lookups: u64,
+    /// Number of those lookups the filter wrongly reported as present.
+    pub false_positives: u64,
+    /// Fraction of lookups that were false positives, or 0 without lookups.
+    pub observed_fp_rate: f64,
+    /// False positive rate expected from the filter's current fill.
+    pub expected_fp_rate: f64,
+    /// Up to 10 key patterns with the most false positives, most frequent first.
+    pub top_patterns: Vec<(String, u64)>,
+}
+
+impl FalsePositiveReport {
+    /// Returns how many times higher the observed rate is than the expected one, or `None` when
+    /// no false positives are expected, e.g. for an empty filter.
+    pub fn divergence(&self) -> Option<f64> {
+        if self.expected_fp_rate == 0.0 {
+            return None;
+        }
+        Some(self.observed_fp_rate / self.expected_fp_rate)
+    }
+}
+
+/// One probe of a lookup explained by `Filter::explain`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Probe {
+    /// The bit index probed.
+    pub index: usize,
+    /// Whether the bit is set.
+    pub set: bool,
+}
+
+/// The outcome of `Filter::explain`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Explanation {
+    /// Number of bits in the filter.
+    pub bits: u64,
+    /// The key's probes, in probe order. Probes may repeat an index.
+    pub probes: Vec<Probe>,
+    /// Whether the filter reports the key as present, which it does if every probed bit is set.
+    pub present: bool,
+    /// False positive rate expected from the filter's current fill.
+    pub estimated_fp_rate: f64,
+}
+
+impl Explanation {
+    /// Returns the number of probes that found their bit set.
+    pub fn bits_found(&self) -> usize {
+        self.probes.iter().filter(|probe| probe.set).count()
+    }
+}
+
+impl fmt::Display for Explanation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "probes: {} over {} bits", self.probes.len(), self.bits)?;
+        for (i, probe) in self.probes.iter().enumerate() {
+            let state = if probe.set { "set" } else { "clear" };
+            writeln!(f, "  probe {}: bit {} {}", i, probe.index, state)?;
+        }
+        writeln!(f, "bits found: {}/{}", self.bits_found(), self.probes.len())?;
+        let verdict = if self.present {
+            "possibly present"
+        } else {
+            "absent"
+        };
+        writeln!(f, "verdict: {}", verdict)?;
+        write!(f, "estimated fp rate: {:.6}", self.estimated_fp_rate)
+    }
+}
+
+impl Filter {
+    /// Checks that a sample of the keys the filter was built from are all present.
+    ///
+    /// A Bloom filter has no false negatives, so any missing key means the filter was corrupted,
+    /// truncated or built from different keys or with a different encoding. Roughly
+    /// `sample_rate` of the source keys are checked; the sample is chosen by hashing each key, so
+    /// repeated audits of the same source check the same keys.
+    pub fn audit<I>(&self, source: I, sample_rate: f64) -> Result<AuditReport, FilterError>
+    where
+        I: IntoIterator<Item = Vec<u8>>,
+    {
+        let threshold = (sample_rate.clamp(0.0, 1.0) * u64::MAX as f64) as u64;
+        let mut report = AuditReport::default();
+
+        for key in source {
+            report.scanned += 1;
+            let (sample, _) = Self::hash(&key, AUDIT_SEED);
+            if sample > threshold {
+                continue;
+            }
+
+            report.sampled += 1;
+            if !self.contains(&key)? {
+                report.missing += 1;
+                if report.missing_samples.len() < MAX_MISSING_SAMPLES {
+                    report.missing_samples.push(key);
+                }
+            }
+        }
+        Ok(report)
+    }
+
+    /// Records a key the source of truth confirmed absent but the filter reported as present.
+    ///
+    /// Returns `false`, without recording anything, if the filter does not report the key as
+    /// present, since it is then not a false positive of this filter.
+    pub fn record_false_positive(
+        &self,
+        log: &mut FalsePositiveLog,
+        key: &[u8],
+    ) -> Result<bool, FilterError> {
+        if !self.contains(key)? {
+            return Ok(false);
+        }
+        log.false_positives += 1;
+        *log.patterns.entry(key_pattern(key)).or_default() += 1;
+        Ok(true)
+    }
+
+    /// Compares the false positives recorded in `log` with the rate expected from this filter.
+    ///
+    /// A rate well above the expected one usually means the keys are not independent under the
+    /// hash, for example because of a lossy key encoding; the top patterns show which keys.
+    pub fn false_positive_report(&self, log: &FalsePositiveLog) -> FalsePositiveReport {
+        let lookups = log.lookups();
+        let observed_fp_rate = if lookups == 0 {
+            0.0
+        } else {
+            log.false_positives as f64 / lookups as f64
+        };
+
+        let mut top_patterns: Vec<(String, u64)> = log
+            .patterns
+            .iter()
+            .map(|(pattern, &count)| (pattern.clone(), count))
+            .collect();
+        top_patterns.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        top_patterns.truncate(TOP_PATTERNS);
+
+        FalsePositiveReport {
+            lookups,
+            false_positives: log.false_positives,
+            observed_fp_rate,
+            expected_fp_rate: self.estimated_fp_rate(),
+            top_patterns,
+        }
+    }
+
+    /// Explains a lookup of `item`: which bits it probes, which of them are set, and the verdict.
+    ///
+    /// Its `Display` output is a human-readable breakdown meant for debugging.
+    pub fn explain(&self, item: &[u8]) -> Explanation {
+        let m = (self.bits.len() * 8) as u64;
+        let (h1, h2) = Self::hash(item, self.seed);
+
+        let probes: Vec<Probe> = (0..self.hash_count as u64)
+            .map(|i| {
+                let index = Self::probe_index(h1, h2, i, m);
+                let set = self.test_bit(index);
+                Probe { index, set }
+            })
+            .collect();
+        Explanation {
+            bits: m,
+            present: probes.iter().all(|probe| probe.set),
+            probes,
+            estimated_fp_rate: self.estimated_fp_rate(),
+        }
+    }
+
+    /// Returns the sorted, deduplicated bit indexes probed for an item.
+    fn probe_set(&self, item: &[u8]) -> Vec<usize> {
+        let m = (self.bits.len() * 8) as u64;
+        let (h1, h2) = Self::hash(item, self.seed);
+
+        let mut probes: Vec<usize> = (0..self.hash_count as u64)
+            .map(|i| Self::probe_index(h1, h2, i, m))
+            .collect();
+        probes.sort_unstable();
+        probes.dedup();
+        probes
+    }
+}
+
+/// Finds sample keys whose full probe sets collide in `filter`.
+///
+/// Only the filter's size and number of hash functions matter; its contents are ignored.
+/// Duplicate sample keys are not reported as collisions.
+pub fn find_colliding_keys<I, K>(filter: &Filter, keys: I) -> Result<Vec<Collision>, FilterError>
+where
+    I: IntoIterator<Item = K>,
+    K: AsRef<[u8]>,
+{
+    let mut groups: HashMap<Vec<usize>, Vec<Vec<u8>>> = HashMap::new();
+    let mut order = Vec::new();
+
+    for key in keys {
+        let key = key.as_ref();
+        let probes = filter.probe_set(key);
+        let group = groups.entry(probes.clone()).or_insert_with(|| {
+            order.push(probes);
+            Vec::new()
+        });
+        if !group.iter().any(|existing| existing == key) {
+            group.push(key.to_vec());
+        }
+    }
+
+    Ok(order
+        .into_iter()
+        .filter_map(|probes| {
+            let keys = groups.remove(&probes)?;
+            (keys.len() > 1).then_some(Collision { keys, probes })
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_colliding_keys() {
+        // With 16 bits and one probe, 100 keys must share probe sets.
+        let tiny = Filter::new(2, 1);
+        let keys: Vec<String> = (0..100).map(|i| i.to_string()).collect();
+        let collisions = find_colliding_keys(&tiny, &keys).unwrap();
+        assert!(!collisions.is_empty());
+        for collision in &collisions {
+            assert_eq!(collision.probes.len(), 1);
+            let mut filter = tiny.clone();
+            filter.add(&collision.keys[0]).unwrap();
+            for key in &collision.keys {
+                assert!(filter.contains(key).unwrap());
+            }
+        }
+        // At most one key per bit can be left without a partner.
+        let total: usize = collisions.iter().map(|c| c.keys.len()).sum();
+        assert!(total >= 100 - 16);
+
+        let large = Filter::new(1199, 7);
+        assert!(find_colliding_keys(&large, &keys).unwrap().is_empty());
+        assert!(find_colliding_keys(&large, ["a", "a"]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_audit() {
+        let keys = || (0..10000).map(|i: u32| i.to_string().into_bytes());
+        let mut filter = Filter::new_from_entries_and_fp(10000, 0.01).unwrap();
+        for key in keys() {
+            filter.add(&key).unwrap();
+        }
+
+        let report = filter.audit(keys(), 0.1).unwrap();
+        assert!(report.passed());
+        assert_eq!(report.scanned, 10000);
+        assert!((800..1200).contains(&report.sampled), "{}", report.sampled);
+        assert_eq!(filter.audit(keys(), 0.1).unwrap(), report);
+
+        assert_eq!(filter.audit(keys(), 1.0).unwrap().sampled, 10000);
+        assert_eq!(filter.audit(keys(), 0.0).unwrap().sampled, 0);
+
+        // Zero the second half of the bits, as a truncated copy would.
+        let half = filter.bits.len() / 2;
+        filter.bits[half..].fill(0);
+        let report = filter.audit(keys(), 1.0).unwrap();
+        assert!(!report.passed());
+        assert!(report.missing > 9000);
+        assert_eq!(report.missing_samples.len(), MAX_MISSING_SAMPLES);
+    }
+
+    #[test]
+    fn test_key_pattern() {
+        let cases = vec![
+            ("digits", b"user:12345".to_vec(), "user:#"),
+            ("mixed", b"a1b22c333".to_vec(), "a#b#c#"),
+            ("empty", b"".to_vec(), ""),
+            ("binary", vec![0xff, 0xfe, 0x00], "<3 bytes>"),
+            (
+                "long",
+                b"abcdefghijklmnopqrstuvwxyz0123456789abcdef".to_vec(),
+                "abcdefghijklmnopqrstuvwxyz#abcde…",
+            ),
+        ];
+
+        for (title, key, expected) in cases {
+            assert_eq!(key_pattern(&key), expected, "{}", title);
+        }
+    }
+
+    #[test]
+    fn test_false_positive_report() {
+        let mut filter = Filter::new_from_entries_and_fp(1000, 0.01).unwrap();
+        for i in 0..1000 {
+            filter.add(format!("user:{}", i).as_bytes()).unwrap();
+        }
+
+        let mut log = FalsePositiveLog::new();
+        assert_eq!(filter.false_positive_report(&log).observed_fp_rate, 0.0);
+        log.record_negative();
+        let empty = Filter::new_from_entries_and_fp(1000, 0.01).unwrap();
+        assert_eq!(empty.false_positive_report(&log).divergence(), None);
+        let mut log = FalsePositiveLog::new();
+
+        // Not a false positive, since the filter reports the key as absent.
+        assert!(!filter.record_false_positive(&mut log, b"absent").unwrap());
+
+        for i in 0..100000 {
+            let key = format!("order:{}", i);
+            if filter.contains(key.as_bytes()).unwrap() {
+                assert!(filter
+                    .record_false_positive(&mut log, key.as_bytes())
+                    .unwrap());
+            } else {
+                log.record_negative();
+            }
+        }
+        assert!(filter.record_false_positive(&mut log, b"user:1").unwrap());
+
+        let report = filter.false_positive_report(&log);
+        assert_eq!(report.lookups, 100001);
+        assert_eq!(report.false_positives, log.false_positives());
+        let divergence = report.divergence().unwrap();
+        assert!((0.5..2.0).contains(&divergence), "{}", divergence);
+        assert_eq!(report.top_patterns[0].0, "order:#");
+        assert_eq!(report.top_patterns[1], ("user:#".to_string(), 1));
+    }
+
+    #[test]
+    fn test_explain() {
+        let mut filter = Filter::new(16, 3);
+        filter.add(b"hello").unwrap();
+
+        let explanation = filter.explain(b"hello");
+        assert!(explanation.present);
+        assert_eq!(explanation.bits, 128);
+        assert_eq!(explanation.probes.len(), 3);
+        assert_eq!(explanation.bits_found(), 3);
+        assert_eq!(explanation.estimated_fp_rate, filter.estimated_fp_rate());
+
+        let cases: &[&[u8]] = &[b"hello", b"world", b"", b"\xff\x00"];
+        for &key in cases {
+            let explanation = filter.explain(key);
+            assert_eq!(
+                explanation.present,
+                filter.contains(key).unwrap(),
+                "{:?}",
+                key
+            );
+            assert!(explanation.probes.iter().all(|probe| probe.index < 128));
+        }
+
+        let text = Filter::new(16, 2).explain(b"hello").to_string();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 6, "{}", text);
+        assert_eq!(lines[0], "probes: 2 over 128 bits");
+        assert!(lines[1].starts_with("  probe 0: bit ") && lines[1].ends_with(" clear"));
+        assert_eq!(lines[3], "bits found: 0/2");
+        assert_eq!(lines[4], "verdict: absent");
+        assert_eq!(lines[5], "estimated fp rate: 0.000000");
+    }
+}
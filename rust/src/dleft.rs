@@ -0,0 +1,363 @@
+use std::io::{Cursor, Read};
+
+use rmp::{decode, encode};
+
+use crate::{Filter, FilterError};
+
+/// Number of subtables, i.e. candidate buckets per item.
+const TABLES: usize = 4;
+
+/// Number of cells in a bucket.
+const CELLS: usize = 8;
+
+/// Fraction of cells expected to be occupied at the sizing capacity.
+const LOAD: f64 = 0.75;
+
+/// MessagePack extension type used to tag serialized `DLeftCountingFilter`s.
+const DLEFT_TAG: i8 = 3;
+
+/// Number of distinct fingerprints stored in a cell.
+const FINGERPRINTS: u64 = 1 << 16;
+
+/// Starting points for the multipliers of the per-subtable permutations.
+const MULTIPLIERS: [u64; TABLES] = [
+    0x9e37_79b9_7f4a_7c15,
+    0xc2b2_ae3d_27d4_eb4f,
+    0x1656_67b1_9e37_79f9,
+    0xd6e8_feb8_6659_fd93,
+];
+
+fn gcd(mut a: u64, mut b: u64) -> u64 {
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    a
+}
+
+/// A d-left counting Bloom filter.
+///
+/// Items are stored as 16-bit fingerprints with an 8-bit counter in one of four candidate
+/// buckets, picking the least loaded one and the leftmost on ties, and can be removed.
+///
+/// Each item has a single true fingerprint, which a different permutation per subtable splits
+/// into a bucket and the stored fingerprint. Two items sharing a bucket and stored fingerprint
+/// in any subtable therefore have the same true fingerprint, so removing one never removes a
+/// different item's cell.
+///
+/// Counters saturate at 255, after which the fingerprint is never removed.
+#[derive(Clone)]
+pub struct DLeftCountingFilter {
+    fingerprints: Vec<u16>,
+    counts: Vec<u8>,
+    buckets: usize,
+    multipliers: [u64; TABLES],
+}
+
+impl DLeftCountingFilter {
+    /// Creates a new `DLeftCountingFilter` with the given number of buckets per subtable.
+    pub fn new(buckets: usize) -> Self {
+        let buckets = buckets.max(1);
+        Self::with_cells(
+            buckets,
+            vec![0; TABLES * buckets * CELLS],
+            vec![0; TABLES * buckets * CELLS],
+        )
+    }
+
+    fn with_cells(buckets: usize, fingerprints: Vec<u16>, counts: Vec<u8>) -> Self {
+        // Multiplying by a number coprime with the domain size permutes the domain.
+        let domain = buckets as u64 * FINGERPRINTS;
+        let multipliers = MULTIPLIERS.map(|start| {
+            let mut multiplier = start % domain;
+            while gcd(multiplier, domain) != 1 {
+                multiplier += 1;
+            }
+            multiplier
+        });
+        Self {
+            fingerprints,
+            counts,
+            buckets,
+            multipliers,
+        }
+    }
+
+    /// Creates a new `DLeftCountingFilter` sized to hold `entries` distinct items.
+    ///
+    /// The false positive rate at capacity is about 0.04%, growing linearly with the load.
+    pub fn new_from_entries(entries: usize) -> Result<Self, &'static str> {
+        if entries == 0 {
+            return Err("Number of entries must be positive");
+        }
+        let buckets = (entries as f64 / (TABLES * CELLS) as f64 / LOAD).ceil() as usize;
+        Ok(Self::new(buckets))
+    }
+
+    /// Returns the number of buckets per subtable.
+    pub fn buckets(&self) -> usize {
+        self.buckets
+    }
+
+    /// Returns the size of the filter in bytes.
+    pub fn size(&self) -> usize {
+        self.fingerprints.len() * 2 + self.counts.len()
+    }
+
+    /// Returns the number of occupied cells.
+    pub fn len(&self) -> usize {
+        self.counts.iter().filter(|&&count| count > 0).count()
+    }
+
+    /// Returns whether the filter is empty.
+    pub fn is_empty(&self) -> bool {
+        self.counts.iter().all(|&count| count == 0)
+    }
+
+    /// Returns the fraction of cells occupied.
+    pub fn fill_ratio(&self) -> f64 {
+        self.len() as f64 / self.counts.len() as f64
+    }
+
+    /// Estimates the current false positive rate: the chance that one of the occupied cells in
+    /// an item's candidate buckets holds the same fingerprint.
+    pub fn estimated_fp_rate(&self) -> f64 {
+        (TABLES * CELLS) as f64 * self.fill_ratio() / FINGERPRINTS as f64
+    }
+
+    /// Computes the candidate bucket offset and stored fingerprint in each subtable for an item.
+    fn locate(&self, item: &[u8]) -> [(usize, u16); TABLES] {
+        let domain = self.buckets as u64 * FINGERPRINTS;
+        let (h1, _) = Filter::hash(item, 0);
+        let true_fingerprint = h1 % domain;
+
+        let mut candidates = [(0, 0); TABLES];
+        for (table, candidate) in candidates.iter_mut().enumerate() {
+            let permuted = (true_fingerprint as u128 * self.multipliers[table] as u128
+                % domain as u128) as u64;
+            let bucket = (permuted / FINGERPRINTS) as usize;
+            *candidate = (
+                (table * self.buckets + bucket) * CELLS,
+                (permuted % FINGERPRINTS) as u16,
+            );
+        }
+        candidates
+    }
+
+    /// Returns the index of the cell holding an item's fingerprint, if any.
+    fn find(&self, candidates: &[(usize, u16); TABLES]) -> Option<usize> {
+        candidates.iter().find_map(|&(offset, fingerprint)| {
+            (offset..offset + CELLS)
+                .find(|&cell| self.counts[cell] > 0 && self.fingerprints[cell] == fingerprint)
+        })
+    }
+
+    /// Adds an item to the filter.
+    ///
+    /// Fails with `FilterError::Full` if all candidate buckets are full.
+    pub fn add(&mut self, item: &[u8]) -> Result<(), FilterError> {
+        let candidates = self.locate(item);
+
+        if let Some(cell) = self.find(&candidates) {
+            self.counts[cell] = self.counts[cell].saturating_add(1);
+            return Ok(());
+        }
+
+        let mut best: Option<(usize, usize, u16)> = None;
+        for &(offset, fingerprint) in &candidates {
+            let cells = &self.counts[offset..offset + CELLS];
+            let load = cells.iter().filter(|&&count| count > 0).count();
+            if load < CELLS && best.is_none_or(|(best_load, _, _)| load < best_load) {
+                let free = cells.iter().position(|&count| count == 0).unwrap();
+                best = Some((load, offset + free, fingerprint));
+            }
+        }
+
+        let (_, cell, fingerprint) = best.ok_or(FilterError::Full)?;
+        self.fingerprints[cell] = fingerprint;
+        self.counts[cell] = 1;
+        Ok(())
+    }
+
+    /// Removes one occurrence of an item, returning whether it was present.
+    ///
+    /// Removing an item that was never added may remove another item with the same fingerprint.
+    pub fn remove(&mut self, item: &[u8]) -> Result<bool, FilterError> {
+        match self.find(&self.locate(item)) {
+            Some(cell) => {
+                if self.counts[cell] < u8::MAX {
+                    self.counts[cell] -= 1;
+                }
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Checks if an item is present in the filter.
+    pub fn contains(&self, item: &[u8]) -> Result<bool, FilterError> {
+        Ok(self.find(&self.locate(item)).is_some())
+    }
+
+    /// Returns how many times an item was added, minus removals.
+    ///
+    /// May overcount on a fingerprint collision, and saturates at 255.
+    pub fn count(&self, item: &[u8]) -> Result<u8, FilterError> {
+        Ok(self
+            .find(&self.locate(item))
+            .map_or(0, |cell| self.counts[cell]))
+    }
+
+    /// Deserializes a `DLeftCountingFilter` from a byte slice.
+    pub fn from_serialized(serialized: &[u8]) -> Result<Self, FilterError> {
+        let mut reader = Cursor::new(serialized);
+
+        let meta = decode::read_ext_meta(&mut reader)?;
+        if meta.typeid != DLEFT_TAG {
+            return Err(FilterError::InvalidFormat("not a d-left counting filter"));
+        }
+
+        let buckets = decode::read_u32(&mut reader)? as usize;
+        let cells_len = decode::read_bin_len(&mut reader)? as usize;
+        if buckets == 0 || cells_len != TABLES * buckets * CELLS * 3 {
+            return Err(FilterError::InvalidFormat(
+                "cell count does not match buckets",
+            ));
+        }
+        if serialized.len() - (reader.position() as usize) < cells_len {
+            return Err(FilterError::InvalidFormat("truncated cells"));
+        }
+        let mut cells = vec![0u8; cells_len];
+        reader.read_exact(&mut cells)?;
+
+        let (fingerprints, counts) = cells
+            .chunks_exact(3)
+            .map(|cell| (u16::from_be_bytes([cell[0], cell[1]]), cell[2]))
+            .unzip();
+
+        Ok(Self::with_cells(buckets, fingerprints, counts))
+    }
+
+    /// Serializes the filter into a byte vector.
+    ///
+    /// Each cell is written as a big-endian `u16` fingerprint followed by its `u8` count.
+    pub fn serialize(&self) -> Result<Vec<u8>, FilterError> {
+        let mut cells = Vec::with_capacity(self.counts.len() * 3);
+        for (fingerprint, count) in self.fingerprints.iter().zip(&self.counts) {
+            cells.extend_from_slice(&fingerprint.to_be_bytes());
+            cells.push(*count);
+        }
+
+        let mut payload = Vec::with_capacity(cells.len() + 10);
+        encode::write_u32(&mut payload, self.buckets as u32)?;
+        encode::write_bin(&mut payload, &cells)?;
+
+        let mut buf = Vec::with_capacity(payload.len() + 6);
+        encode::write_ext_meta(&mut buf, payload.len() as u32, DLEFT_TAG)?;
+        buf.extend_from_slice(&payload);
+        Ok(buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn test_dleft_filter() {
+        let mut filter = DLeftCountingFilter::new_from_entries(1000).unwrap();
+        assert_eq!(filter.buckets(), 42);
+
+        for i in 0..1000 {
+            filter.add(i.to_string().as_bytes()).unwrap();
+        }
+        assert_eq!(filter.len(), 1000);
+        for i in 0..1000 {
+            assert!(filter.contains(i.to_string().as_bytes()).unwrap());
+        }
+
+        let false_positives = (1000..101000)
+            .filter(|i| filter.contains(i.to_string().as_bytes()).unwrap())
+            .count();
+        assert!(false_positives < 100, "{} false positives", false_positives);
+
+        for i in 0..500 {
+            assert!(filter.remove(i.to_string().as_bytes()).unwrap());
+        }
+        assert_eq!(filter.len(), 500);
+        for i in 500..1000 {
+            assert!(filter.contains(i.to_string().as_bytes()).unwrap());
+        }
+        assert!(!filter.remove(b"missing").unwrap());
+    }
+
+    #[test]
+    fn test_count() {
+        let mut filter = DLeftCountingFilter::new(16);
+        for _ in 0..3 {
+            filter.add(b"hello").unwrap();
+        }
+        assert_eq!(filter.count(b"hello").unwrap(), 3);
+
+        filter.remove(b"hello").unwrap();
+        assert_eq!(filter.count(b"hello").unwrap(), 2);
+
+        filter.remove(b"hello").unwrap();
+        filter.remove(b"hello").unwrap();
+        assert!(!filter.contains(b"hello").unwrap());
+        assert!(filter.is_empty());
+    }
+
+    #[test]
+    fn test_full() {
+        let mut filter = DLeftCountingFilter::new(1);
+        for i in 0..TABLES * CELLS {
+            filter.add(i.to_string().as_bytes()).unwrap();
+        }
+        assert!(matches!(filter.add(b"one more"), Err(FilterError::Full)));
+    }
+
+    #[test]
+    fn test_serialize() {
+        let mut filter = DLeftCountingFilter::new(16);
+        filter.add(b"hello").unwrap();
+        filter.add(b"hello").unwrap();
+        filter.add(b"world").unwrap();
+
+        let serialized = filter.serialize().unwrap();
+        let defilter = DLeftCountingFilter::from_serialized(&serialized).unwrap();
+
+        assert_eq!(defilter.count(b"hello").unwrap(), 2);
+        assert_eq!(defilter.count(b"world").unwrap(), 1);
+        assert!(!defilter.contains(b"foo").unwrap());
+
+        for len in 0..serialized.len() {
+            assert!(DLeftCountingFilter::from_serialized(&serialized[..len]).is_err());
+        }
+
+        let plain = Filter::new(1000, 7).serialize().unwrap();
+        assert!(DLeftCountingFilter::from_serialized(&plain).is_err());
+    }
+
+    proptest! {
+        #[test]
+        fn test_no_false_negatives(
+            keys in prop::collection::vec(prop::collection::vec(any::<u8>(), 0..32), 0..200),
+            removed in 0usize..200,
+        ) {
+            let mut filter = DLeftCountingFilter::new_from_entries(200).unwrap();
+            for key in &keys {
+                filter.add(key).unwrap();
+            }
+            // Removing one copy of a key must keep every other added key present.
+            let removed = removed.min(keys.len());
+            for key in &keys[..removed] {
+                prop_assert!(filter.remove(key).unwrap());
+            }
+            for key in &keys[removed..] {
+                prop_assert!(filter.contains(key).unwrap());
+            }
+        }
+    }
+}
@@ -0,0 +1,490 @@
+//! A self-describing envelope shared by every filter structure, and `load_any` to read any of
+//! them back without knowing the structure in advance.
+//!
+//! An envelope is the magic bytes `PBLM` followed by MessagePack values: the structure's
+//! `FilterKind` tag, the envelope version, the structure's size and number of hash functions,
+//! the structure serialized in its own format as `bin`, and a CRC32 of everything before it.
+
+use core::fmt;
+
+use crc32fast::hash as crc32;
+use rmp::decode::Bytes;
+use rmp::encode::ByteBuf;
+use rmp::{decode, encode};
+
+#[cfg(feature = "aging")]
+use crate::AgingFilter;
+#[cfg(feature = "blocked")]
+use crate::BlockedFilter;
+#[cfg(feature = "clock")]
+use crate::ClockFilter;
+#[cfg(feature = "dleft")]
+use crate::DLeftCountingFilter;
+#[cfg(feature = "range")]
+use crate::RangedFilter;
+use crate::{versioned, Filter, FilterError};
+
+/// Bytes every envelope starts with.
+pub const MAGIC: [u8; 4] = *b"PBLM";
+
+/// Current version of the envelope.
+const ENVELOPE_VERSION: u8 = 1;
+
+/// The structure stored in an envelope.
+///
+/// Tags match the MessagePack extension types of the structures' own formats.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FilterKind {
+    Filter,
+    Blocked,
+    Ranged,
+    DLeft,
+    Aging,
+    Clock,
+}
+
+impl FilterKind {
+    const ALL: [FilterKind; 6] = [
+        FilterKind::Filter,
+        FilterKind::Blocked,
+        FilterKind::Ranged,
+        FilterKind::DLeft,
+        FilterKind::Aging,
+        FilterKind::Clock,
+    ];
+
+    /// Returns the tag identifying the structure in an envelope.
+    pub fn tag(self) -> u8 {
+        match self {
+            FilterKind::Filter => 0,
+            FilterKind::Blocked => 1,
+            FilterKind::Ranged => 2,
+            FilterKind::DLeft => 3,
+            FilterKind::Aging => 4,
+            FilterKind::Clock => 6,
+        }
+    }
+
+    /// Returns the kind with the given envelope tag.
+    pub fn from_tag(tag: u8) -> Option<Self> {
+        Self::ALL.into_iter().find(|kind| kind.tag() == tag)
+    }
+
+    /// Returns a short lowercase name for the structure, such as `blocked`.
+    pub fn name(self) -> &'static str {
+        match self {
+            FilterKind::Filter => "filter",
+            FilterKind::Blocked => "blocked",
+            FilterKind::Ranged => "ranged",
+            FilterKind::DLeft => "dleft",
+            FilterKind::Aging => "aging",
+            FilterKind::Clock => "clock",
+        }
+    }
+}
+
+impl fmt::Display for FilterKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Statistics every structure reports, from `AnyFilter::stats`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Stats {
+    /// Which structure this is.
+    pub kind: FilterKind,
+    /// Size of the structure in bytes.
+    pub size: usize,
+    /// Number of hash functions, or 0 for d-left filters, which store fingerprints.
+    pub hash_count: u8,
+    /// Fraction of bits set, or of cells occupied for d-left filters.
+    pub fill_ratio: f64,
+    /// Estimated current false positive rate.
+    pub estimated_fp_rate: f64,
+}
+
+/// Any filter structure, as returned by `load_any`.
+///
+/// Variants exist for the structures whose features are enabled.
+#[derive(Clone)]
+pub enum AnyFilter {
+    Filter(Filter),
+    #[cfg(feature = "blocked")]
+    Blocked(BlockedFilter),
+    #[cfg(feature = "range")]
+    Ranged(RangedFilter),
+    #[cfg(feature = "dleft")]
+    DLeft(DLeftCountingFilter),
+    #[cfg(feature = "aging")]
+    Aging(AgingFilter),
+    #[cfg(feature = "clock")]
+    Clock(ClockFilter),
+}
+
+impl AnyFilter {
+    /// Returns which structure this is.
+    pub fn kind(&self) -> FilterKind {
+        match self {
+            AnyFilter::Filter(_) => FilterKind::Filter,
+            #[cfg(feature = "blocked")]
+            AnyFilter::Blocked(_) => FilterKind::Blocked,
+            #[cfg(feature = "range")]
+            AnyFilter::Ranged(_) => FilterKind::Ranged,
+            #[cfg(feature = "dleft")]
+            AnyFilter::DLeft(_) => FilterKind::DLeft,
+            #[cfg(feature = "aging")]
+            AnyFilter::Aging(_) => FilterKind::Aging,
+            #[cfg(feature = "clock")]
+            AnyFilter::Clock(_) => FilterKind::Clock,
+        }
+    }
+
+    /// Returns the size in bytes and number of hash functions recorded in the envelope, as
+    /// reported by `stats`.
+    fn params(&self) -> (u64, u8) {
+        let stats = self.stats();
+        (stats.size as u64, stats.hash_count)
+    }
+
+    /// Returns the plain `Filter`, if this is one.
+    pub fn as_filter(&self) -> Option<&Filter> {
+        match self {
+            AnyFilter::Filter(filter) => Some(filter),
+            #[allow(unreachable_patterns)]
+            _ => None,
+        }
+    }
+
+    /// Adds an item to the structure.
+    pub fn add(&mut self, item: &[u8]) -> Result<(), FilterError> {
+        match self {
+            AnyFilter::Filter(filter) => filter.add(item),
+            #[cfg(feature = "blocked")]
+            AnyFilter::Blocked(filter) => filter.add(item),
+            #[cfg(feature = "range")]
+            AnyFilter::Ranged(filter) => filter.add(item),
+            #[cfg(feature = "dleft")]
+            AnyFilter::DLeft(filter) => filter.add(item),
+            #[cfg(feature = "aging")]
+            AnyFilter::Aging(filter) => filter.add(item),
+            #[cfg(feature = "clock")]
+            AnyFilter::Clock(filter) => filter.add(item),
+        }
+    }
+
+    /// Checks if an item is present in the structure.
+    pub fn contains(&self, item: &[u8]) -> Result<bool, FilterError> {
+        match self {
+            AnyFilter::Filter(filter) => filter.contains(item),
+            #[cfg(feature = "blocked")]
+            AnyFilter::Blocked(filter) => filter.contains(item),
+            #[cfg(feature = "range")]
+            AnyFilter::Ranged(filter) => filter.contains(item),
+            #[cfg(feature = "dleft")]
+            AnyFilter::DLeft(filter) => filter.contains(item),
+            #[cfg(feature = "aging")]
+            AnyFilter::Aging(filter) => filter.contains(item),
+            #[cfg(feature = "clock")]
+            AnyFilter::Clock(filter) => filter.contains(item),
+        }
+    }
+
+    /// Returns the size, fill and estimated false positive rate of the structure.
+    pub fn stats(&self) -> Stats {
+        let (size, hash_count, fill_ratio, estimated_fp_rate) = match self {
+            AnyFilter::Filter(filter) => (
+                filter.size(),
+                filter.hash_count(),
+                filter.fill_ratio(),
+                filter.estimated_fp_rate(),
+            ),
+            #[cfg(feature = "blocked")]
+            AnyFilter::Blocked(filter) => (
+                filter.size(),
+                filter.hash_count(),
+                filter.fill_ratio(),
+                filter.estimated_fp_rate(),
+            ),
+            #[cfg(feature = "range")]
+            AnyFilter::Ranged(filter) => (
+                filter.filter().size(),
+                filter.filter().hash_count(),
+                filter.filter().fill_ratio(),
+                filter.filter().estimated_fp_rate(),
+            ),
+            #[cfg(feature = "dleft")]
+            AnyFilter::DLeft(filter) => (
+                filter.size(),
+                0,
+                filter.fill_ratio(),
+                filter.estimated_fp_rate(),
+            ),
+            #[cfg(feature = "aging")]
+            AnyFilter::Aging(filter) => (
+                filter.current().size() * filter.generations(),
+                filter.current().hash_count(),
+                filter.fill_ratio(),
+                filter.estimated_fp_rate(),
+            ),
+            #[cfg(feature = "clock")]
+            AnyFilter::Clock(filter) => (
+                filter.size(),
+                filter.hash_count(),
+                filter.fill_ratio(),
+                filter.estimated_fp_rate(),
+            ),
+        };
+        Stats {
+            kind: self.kind(),
+            size,
+            hash_count,
+            fill_ratio,
+            estimated_fp_rate,
+        }
+    }
+
+    /// Serializes the structure in its own format, without an envelope.
+    ///
+    /// `load_any` reads this back too, but `to_envelope` also records the structure's kind and
+    /// checksums the result.
+    pub fn serialize(&self) -> Result<Vec<u8>, FilterError> {
+        match self {
+            AnyFilter::Filter(filter) => filter.serialize_compressed(),
+            #[cfg(feature = "blocked")]
+            AnyFilter::Blocked(filter) => filter.serialize(),
+            #[cfg(feature = "range")]
+            AnyFilter::Ranged(filter) => filter.serialize(),
+            #[cfg(feature = "dleft")]
+            AnyFilter::DLeft(filter) => filter.serialize(),
+            #[cfg(feature = "aging")]
+            AnyFilter::Aging(filter) => filter.serialize(),
+            #[cfg(feature = "clock")]
+            AnyFilter::Clock(filter) => filter.serialize(),
+        }
+    }
+
+    /// Deserializes a structure of the given kind from its own format.
+    fn from_payload(kind: FilterKind, payload: &[u8]) -> Result<Self, FilterError> {
+        match kind {
+            FilterKind::Filter => Ok(AnyFilter::Filter(Filter::from_serialized(payload)?)),
+            #[cfg(feature = "blocked")]
+            FilterKind::Blocked => Ok(AnyFilter::Blocked(BlockedFilter::from_serialized(payload)?)),
+            #[cfg(feature = "range")]
+            FilterKind::Ranged => Ok(AnyFilter::Ranged(RangedFilter::from_serialized(payload)?)),
+            #[cfg(feature = "dleft")]
+            FilterKind::DLeft => Ok(AnyFilter::DLeft(DLeftCountingFilter::from_serialized(
+                payload,
+            )?)),
+            #[cfg(feature = "aging")]
+            FilterKind::Aging => Ok(AnyFilter::Aging(AgingFilter::from_serialized(payload)?)),
+            #[cfg(feature = "clock")]
+            FilterKind::Clock => Ok(AnyFilter::Clock(ClockFilter::from_serialized(payload)?)),
+            #[allow(unreachable_patterns)]
+            _ => Err(FilterError::Unsupported("filter kind not enabled")),
+        }
+    }
+
+    /// Serializes the structure inside an envelope, for `load_any` to read back.
+    pub fn to_envelope(&self) -> Result<Vec<u8>, FilterError> {
+        let (size, hash_count) = self.params();
+        let payload = self.serialize()?;
+
+        let mut buf = ByteBuf::with_capacity(payload.len() + 32);
+        buf.as_mut_vec().extend_from_slice(&MAGIC);
+        encode::write_u8(&mut buf, self.kind().tag())?;
+        encode::write_u8(&mut buf, ENVELOPE_VERSION)?;
+        encode::write_u64(&mut buf, size)?;
+        encode::write_u8(&mut buf, hash_count)?;
+        encode::write_bin(&mut buf, &payload)?;
+        let checksum = crc32(buf.as_slice());
+        encode::write_u32(&mut buf, checksum)?;
+        Ok(buf.into_vec())
+    }
+}
+
+impl From<Filter> for AnyFilter {
+    fn from(filter: Filter) -> Self {
+        AnyFilter::Filter(filter)
+    }
+}
+
+/// Returns whether `serialized` starts with the envelope magic.
+pub fn is_envelope(serialized: &[u8]) -> bool {
+    serialized.starts_with(&MAGIC)
+}
+
+/// Returns the kind of a structure serialized in its own format, from its MessagePack header.
+fn bare_kind(serialized: &[u8]) -> Result<FilterKind, FilterError> {
+    if !versioned::is_extension(serialized) {
+        return Ok(FilterKind::Filter);
+    }
+    let meta = decode::read_ext_meta(&mut Bytes::new(serialized))?;
+    match meta.typeid {
+        versioned::VERSIONED_TAG => Ok(FilterKind::Filter),
+        tag => u8::try_from(tag)
+            .ok()
+            .and_then(FilterKind::from_tag)
+            .filter(|&kind| kind != FilterKind::Filter)
+            .ok_or(FilterError::Unsupported("unknown filter kind")),
+    }
+}
+
+/// Deserializes any filter structure, from an envelope or from the structure's own format.
+///
+/// Stored filters written before envelopes existed are recognized by their format's header.
+/// Envelopes are checked against their checksum and recorded parameters.
+pub fn load_any(serialized: &[u8]) -> Result<AnyFilter, FilterError> {
+    if !is_envelope(serialized) {
+        return AnyFilter::from_payload(bare_kind(serialized)?, serialized);
+    }
+
+    let Some((body, checksum)) = serialized.split_last_chunk::<5>() else {
+        return Err(FilterError::InvalidFormat("truncated envelope"));
+    };
+    if checksum[0] != 0xce || crc32(body) != u32::from_be_bytes(checksum[1..].try_into().unwrap()) {
+        return Err(FilterError::InvalidFormat("envelope checksum mismatch"));
+    }
+
+    let mut reader = Bytes::new(&body[MAGIC.len()..]);
+    let tag = decode::read_u8(&mut reader)?;
+    if decode::read_u8(&mut reader)? != ENVELOPE_VERSION {
+        return Err(FilterError::Unsupported("unknown envelope version"));
+    }
+    let size = decode::read_u64(&mut reader)?;
+    let hash_count = decode::read_u8(&mut reader)?;
+    let len = decode::read_bin_len(&mut reader)? as usize;
+    let payload = reader.remaining_slice();
+    if payload.len() != len {
+        return Err(FilterError::InvalidFormat("envelope payload size mismatch"));
+    }
+
+    let kind = FilterKind::from_tag(tag).ok_or(FilterError::Unsupported("unknown filter kind"))?;
+    let filter = AnyFilter::from_payload(kind, payload)?;
+    if filter.params() != (size, hash_count) {
+        return Err(FilterError::InvalidFormat("envelope parameters mismatch"));
+    }
+    Ok(filter)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filled(mut filter: Filter) -> Filter {
+        for key in ["a", "b", "c"] {
+            filter.add(key.as_bytes()).unwrap();
+        }
+        filter
+    }
+
+    #[test]
+    fn test_envelope() {
+        let mut seeded = Filter::new(100, 3);
+        seeded.seed = 42;
+        let cases: Vec<AnyFilter> = vec![
+            AnyFilter::Filter(filled(Filter::new(100, 3))),
+            AnyFilter::Filter(filled(seeded)),
+            #[cfg(feature = "blocked")]
+            AnyFilter::Blocked(BlockedFilter::new(128, 3)),
+            #[cfg(feature = "range")]
+            AnyFilter::Ranged(RangedFilter::new(filled(Filter::new(100, 3)))),
+            #[cfg(feature = "dleft")]
+            AnyFilter::DLeft(DLeftCountingFilter::new(16)),
+            #[cfg(feature = "aging")]
+            AnyFilter::Aging(AgingFilter::new(100, 3, 2)),
+            #[cfg(feature = "clock")]
+            AnyFilter::Clock(ClockFilter::new(100, 3, 2, 10)),
+        ];
+
+        for filter in cases {
+            let envelope = filter.to_envelope().unwrap();
+            assert!(is_envelope(&envelope));
+            let loaded = load_any(&envelope).unwrap();
+            assert_eq!(loaded.kind(), filter.kind());
+            assert_eq!(loaded.params(), filter.params());
+            assert_eq!(
+                loaded.to_envelope().unwrap(),
+                envelope,
+                "{:?}",
+                filter.kind()
+            );
+
+            // Structures stored without an envelope are recognized too.
+            let bare = load_any(&filter.serialize().unwrap()).unwrap();
+            assert_eq!(bare.kind(), filter.kind());
+
+            for len in 0..envelope.len() {
+                assert!(load_any(&envelope[..len]).is_err(), "{:?}", filter.kind());
+            }
+            let mut corrupted = envelope.clone();
+            *corrupted.last_mut().unwrap() ^= 1;
+            assert!(load_any(&corrupted).is_err());
+        }
+
+        let legacy = filled(Filter::new(100, 3)).serialize().unwrap();
+        assert_eq!(load_any(&legacy).unwrap().kind(), FilterKind::Filter);
+    }
+
+    #[test]
+    fn test_any_filter() {
+        let cases: Vec<(AnyFilter, usize, u8)> = vec![
+            (AnyFilter::Filter(Filter::new(1000, 7)), 1000, 7),
+            #[cfg(feature = "blocked")]
+            (AnyFilter::Blocked(BlockedFilter::new(1000, 7)), 1024, 7),
+            #[cfg(feature = "range")]
+            (
+                AnyFilter::Ranged(RangedFilter::new(Filter::new(1000, 7))),
+                1000,
+                7,
+            ),
+            #[cfg(feature = "dleft")]
+            (AnyFilter::DLeft(DLeftCountingFilter::new(16)), 1536, 0),
+            #[cfg(feature = "aging")]
+            (AnyFilter::Aging(AgingFilter::new(1000, 7, 3)), 3000, 7),
+            #[cfg(feature = "clock")]
+            (AnyFilter::Clock(ClockFilter::new(1000, 7, 3, 0)), 3000, 7),
+        ];
+
+        for (mut filter, size, hash_count) in cases {
+            let kind = filter.kind();
+            let empty = filter.stats();
+            assert_eq!(empty.kind, kind);
+            assert_eq!(
+                (empty.size, empty.hash_count),
+                (size, hash_count),
+                "{}",
+                kind
+            );
+            assert_eq!(filter.params(), (size as u64, hash_count), "{}", kind);
+            assert_eq!(empty.fill_ratio, 0.0, "{}", kind);
+            assert_eq!(empty.estimated_fp_rate, 0.0, "{}", kind);
+
+            for i in 0..100 {
+                filter.add(i.to_string().as_bytes()).unwrap();
+            }
+            assert!((0..100).all(|i| filter.contains(i.to_string().as_bytes()).unwrap()));
+            let stats = filter.stats();
+            assert!(stats.fill_ratio > 0.0 && stats.fill_ratio < 1.0, "{}", kind);
+            assert!(
+                stats.estimated_fp_rate > 0.0 && stats.estimated_fp_rate < 0.01,
+                "{}: {}",
+                kind,
+                stats.estimated_fp_rate
+            );
+
+            assert_eq!(filter.as_filter().is_some(), kind == FilterKind::Filter);
+            let reloaded = load_any(&filter.serialize().unwrap()).unwrap();
+            assert_eq!(reloaded.stats(), stats);
+        }
+    }
+
+    #[test]
+    fn test_filter_kind() {
+        for kind in FilterKind::ALL {
+            assert_eq!(FilterKind::from_tag(kind.tag()), Some(kind));
+            assert_eq!(kind.to_string(), kind.name());
+        }
+        assert_eq!(FilterKind::from_tag(5), None);
+    }
+}
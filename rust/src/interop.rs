@@ -0,0 +1,440 @@
+//! Readers and writers for Bloom filters produced by other libraries: RedisBloom and Go's
+//! willf/bloom.
+//!
+//! Foreign filters use their own hashing and probe placement, so their bits cannot be
+//! reinterpreted as a pbloom `Filter` without the original keys. Instead, each format gets a
+//! filter type that reproduces the source system's hashing exactly, which means `contains`
+//! answers the same way the source would.
+//!
+//! For the same reason there is no `Filter::from_redis_bloom` or `Filter::from_willf_bloom`:
+//! RedisBloom hashes with MurmurHash64A and scales by chaining sub-filters, and willf/bloom
+//! derives four base hashes from Murmur3 and places probes differently, so a key set in their
+//! bits is at other positions in a `Filter`. Converting requires re-adding the original keys.
+
+use std::io::{Cursor, Read};
+
+use crate::{Filter, FilterError};
+
+/// A filter in the format of the Go `willf/bloom` (now `bits-and-blooms/bloom`) library.
+#[derive(Clone)]
+pub struct WillfFilter {
+    words: Vec<u64>,
+    m: u64,
+    k: u64,
+}
+
+impl WillfFilter {
+    /// Creates a new `WillfFilter` with `m` bits and `k` hash functions. Like willf/bloom, an
+    /// `m` of 0 is raised to 1.
+    pub fn new(m: u64, k: u64) -> Self {
+        let m = m.max(1);
+        Self {
+            words: vec![0; m.div_ceil(64) as usize],
+            m,
+            k,
+        }
+    }
+
+    /// Reads a filter written by `BloomFilter.WriteTo` or `MarshalBinary`.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, FilterError> {
+        let mut reader = Cursor::new(bytes);
+        let m = read_u64_be(&mut reader)?;
+        let k = read_u64_be(&mut reader)?;
+        let length = read_u64_be(&mut reader)?;
+        if m == 0 || length < m {
+            return Err(FilterError::InvalidFormat(
+                "bitset is smaller than the filter",
+            ));
+        }
+
+        let count = length.div_ceil(64) as usize;
+        if bytes.len() - (reader.position() as usize) < count * 8 {
+            return Err(FilterError::InvalidFormat("truncated bitset"));
+        }
+        let mut words = Vec::with_capacity(count);
+        for _ in 0..count {
+            words.push(read_u64_be(&mut reader)?);
+        }
+
+        Ok(Self { words, m, k })
+    }
+
+    /// Writes the filter in the `BloomFilter.WriteTo` format.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(24 + self.words.len() * 8);
+        buf.extend_from_slice(&self.m.to_be_bytes());
+        buf.extend_from_slice(&self.k.to_be_bytes());
+        buf.extend_from_slice(&(self.words.len() as u64 * 64).min(self.m).to_be_bytes());
+        for word in &self.words {
+            buf.extend_from_slice(&word.to_be_bytes());
+        }
+        buf
+    }
+
+    /// Computes the four base hashes: Murmur3 of the item and of the item followed by a `1` byte.
+    fn base_hashes(item: &[u8]) -> [u64; 4] {
+        let (h1, h2) = Filter::hash(item, 0);
+        let mut extended = Vec::with_capacity(item.len() + 1);
+        extended.extend_from_slice(item);
+        extended.push(1);
+        let (h3, h4) = Filter::hash(&extended, 0);
+        [h1, h2, h3, h4]
+    }
+
+    fn location(&self, h: &[u64; 4], i: u64) -> usize {
+        let hash = h[(i % 2) as usize]
+            .wrapping_add(i.wrapping_mul(h[2 + (((i + (i % 2)) % 4) / 2) as usize]));
+        (hash % self.m) as usize
+    }
+
+    /// Adds an item to the filter.
+    pub fn add(&mut self, item: &[u8]) -> Result<(), FilterError> {
+        let h = Self::base_hashes(item);
+        for i in 0..self.k {
+            let index = self.location(&h, i);
+            self.words[index / 64] |= 1 << (index % 64);
+        }
+        Ok(())
+    }
+
+    /// Checks if an item is present in the filter.
+    pub fn contains(&self, item: &[u8]) -> Result<bool, FilterError> {
+        let h = Self::base_hashes(item);
+        Ok((0..self.k).all(|i| {
+            let index = self.location(&h, i);
+            self.words[index / 64] & (1 << (index % 64)) != 0
+        }))
+    }
+}
+
+/// Set on RedisBloom filters that hash with 64-bit MurmurHash64A.
+const REDIS_OPT_FORCE64: u32 = 4;
+
+/// Size of a serialized RedisBloom chain header, excluding links.
+const REDIS_HEADER_SIZE: usize = 20;
+
+/// Size of a serialized RedisBloom link descriptor.
+const REDIS_LINK_SIZE: usize = 53;
+
+/// A single sub-filter of a RedisBloom scalable chain.
+#[derive(Clone)]
+struct RedisLink {
+    bits: Vec<u8>,
+    bit_count: u64,
+    size: u64,
+    error: f64,
+    bpe: f64,
+    hashes: u32,
+    entries: u64,
+    n2: u8,
+}
+
+impl RedisLink {
+    /// Returns the modulus used to map hashes to bits.
+    fn modulus(&self) -> u64 {
+        if self.n2 > 0 {
+            1 << self.n2
+        } else {
+            self.bit_count
+        }
+    }
+}
+
+/// A scalable filter in the RedisBloom `BF.SCANDUMP` format.
+#[derive(Clone)]
+pub struct RedisBloomFilter {
+    size: u64,
+    options: u32,
+    growth: u32,
+    links: Vec<RedisLink>,
+}
+
+impl RedisBloomFilter {
+    /// Reads a filter from the concatenation of all `BF.SCANDUMP` chunks, in iteration order.
+    ///
+    /// Only filters using 64-bit hashing, the default since RedisBloom 2.0, are supported.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, FilterError> {
+        let mut reader = Cursor::new(bytes);
+        let size = read_u64_le(&mut reader)?;
+        let nfilters = read_u32_le(&mut reader)?;
+        let options = read_u32_le(&mut reader)?;
+        let growth = read_u32_le(&mut reader)?;
+        if options & REDIS_OPT_FORCE64 == 0 {
+            return Err(FilterError::InvalidFormat(
+                "32-bit RedisBloom hashing is not supported",
+            ));
+        }
+        if bytes.len() < REDIS_HEADER_SIZE + nfilters as usize * REDIS_LINK_SIZE {
+            return Err(FilterError::InvalidFormat("truncated RedisBloom header"));
+        }
+
+        let mut links = Vec::with_capacity(nfilters as usize);
+        let mut lengths = Vec::with_capacity(nfilters as usize);
+        for _ in 0..nfilters {
+            lengths.push(read_u64_le(&mut reader)?);
+            let link = RedisLink {
+                bits: Vec::new(),
+                bit_count: read_u64_le(&mut reader)?,
+                size: read_u64_le(&mut reader)?,
+                error: f64::from_bits(read_u64_le(&mut reader)?),
+                bpe: f64::from_bits(read_u64_le(&mut reader)?),
+                hashes: read_u32_le(&mut reader)?,
+                entries: read_u64_le(&mut reader)?,
+                n2: read_u8(&mut reader)?,
+            };
+            if link.n2 >= 64 {
+                return Err(FilterError::InvalidFormat(
+                    "link modulus exponent too large",
+                ));
+            }
+            links.push(link);
+        }
+
+        for (link, len) in links.iter_mut().zip(lengths) {
+            if link.modulus() == 0 || link.modulus().div_ceil(8) > len {
+                return Err(FilterError::InvalidFormat(
+                    "link is smaller than its bit count",
+                ));
+            }
+            if bytes.len() - (reader.position() as usize) < len as usize {
+                return Err(FilterError::InvalidFormat("truncated RedisBloom data"));
+            }
+            link.bits = vec![0; len as usize];
+            reader.read_exact(&mut link.bits)?;
+        }
+
+        Ok(Self {
+            size,
+            options,
+            growth,
+            links,
+        })
+    }
+
+    /// Writes the filter as a header chunk followed by the data of every link.
+    ///
+    /// The result is accepted by `from_bytes`. To load it with `BF.LOADCHUNK`, send the first
+    /// `header_len` bytes as iterator 1 and the remainder as data chunks.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(
+            self.header_len() + self.links.iter().map(|l| l.bits.len()).sum::<usize>(),
+        );
+        buf.extend_from_slice(&self.size.to_le_bytes());
+        buf.extend_from_slice(&(self.links.len() as u32).to_le_bytes());
+        buf.extend_from_slice(&self.options.to_le_bytes());
+        buf.extend_from_slice(&self.growth.to_le_bytes());
+        for link in &self.links {
+            buf.extend_from_slice(&(link.bits.len() as u64).to_le_bytes());
+            buf.extend_from_slice(&link.bit_count.to_le_bytes());
+            buf.extend_from_slice(&link.size.to_le_bytes());
+            buf.extend_from_slice(&link.error.to_bits().to_le_bytes());
+            buf.extend_from_slice(&link.bpe.to_bits().to_le_bytes());
+            buf.extend_from_slice(&link.hashes.to_le_bytes());
+            buf.extend_from_slice(&link.entries.to_le_bytes());
+            buf.push(link.n2);
+        }
+        for link in &self.links {
+            buf.extend_from_slice(&link.bits);
+        }
+        buf
+    }
+
+    /// Returns the length of the header chunk produced by `to_bytes`.
+    pub fn header_len(&self) -> usize {
+        REDIS_HEADER_SIZE + self.links.len() * REDIS_LINK_SIZE
+    }
+
+    /// Computes RedisBloom's pair of 64-bit hashes for an item.
+    fn hash(item: &[u8]) -> (u64, u64) {
+        let a = murmur64a(item, 0xc6a4a7935bd1e995);
+        (a, murmur64a(item, a))
+    }
+
+    fn link_contains(link: &RedisLink, a: u64, b: u64) -> bool {
+        let m = link.modulus();
+        (0..link.hashes as u64).all(|i| {
+            let index = (a.wrapping_add(i.wrapping_mul(b)) % m) as usize;
+            link.bits[index / 8] & (1 << (index % 8)) != 0
+        })
+    }
+
+    /// Adds an item to the newest link.
+    ///
+    /// Unlike RedisBloom, the chain is not grown once the link reaches its capacity.
+    pub fn add(&mut self, item: &[u8]) -> Result<(), FilterError> {
+        let (a, b) = Self::hash(item);
+        if self
+            .links
+            .iter()
+            .any(|link| Self::link_contains(link, a, b))
+        {
+            return Ok(());
+        }
+        let link = self
+            .links
+            .last_mut()
+            .ok_or(FilterError::InvalidFormat("filter has no links"))?;
+        let m = link.modulus();
+        for i in 0..link.hashes as u64 {
+            let index = (a.wrapping_add(i.wrapping_mul(b)) % m) as usize;
+            link.bits[index / 8] |= 1 << (index % 8);
+        }
+        link.size += 1;
+        self.size += 1;
+        Ok(())
+    }
+
+    /// Checks if an item is present in any link of the filter.
+    pub fn contains(&self, item: &[u8]) -> Result<bool, FilterError> {
+        let (a, b) = Self::hash(item);
+        Ok(self
+            .links
+            .iter()
+            .any(|link| Self::link_contains(link, a, b)))
+    }
+}
+
+/// MurmurHash64A, as used by RedisBloom.
+fn murmur64a(data: &[u8], seed: u64) -> u64 {
+    const M: u64 = 0xc6a4a7935bd1e995;
+    const R: u32 = 47;
+
+    let mut h = seed ^ (data.len() as u64).wrapping_mul(M);
+
+    let mut chunks = data.chunks_exact(8);
+    for chunk in &mut chunks {
+        let mut k = u64::from_le_bytes(chunk.try_into().unwrap());
+        k = k.wrapping_mul(M);
+        k ^= k >> R;
+        k = k.wrapping_mul(M);
+        h ^= k;
+        h = h.wrapping_mul(M);
+    }
+
+    let tail = chunks.remainder();
+    if !tail.is_empty() {
+        for (i, byte) in tail.iter().enumerate() {
+            h ^= (*byte as u64) << (8 * i);
+        }
+        h = h.wrapping_mul(M);
+    }
+
+    h ^= h >> R;
+    h = h.wrapping_mul(M);
+    h ^= h >> R;
+    h
+}
+
+fn read_u8(reader: &mut Cursor<&[u8]>) -> Result<u8, FilterError> {
+    let mut buf = [0; 1];
+    reader.read_exact(&mut buf)?;
+    Ok(buf[0])
+}
+
+fn read_u32_le(reader: &mut Cursor<&[u8]>) -> Result<u32, FilterError> {
+    let mut buf = [0; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u64_le(reader: &mut Cursor<&[u8]>) -> Result<u64, FilterError> {
+    let mut buf = [0; 8];
+    reader.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+fn read_u64_be(reader: &mut Cursor<&[u8]>) -> Result<u64, FilterError> {
+    let mut buf = [0; 8];
+    reader.read_exact(&mut buf)?;
+    Ok(u64::from_be_bytes(buf))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_willf_roundtrip() {
+        let mut filter = WillfFilter::new(1000, 7);
+        filter.add(b"hello").unwrap();
+        filter.add(b"world").unwrap();
+
+        let bytes = filter.to_bytes();
+        assert_eq!(bytes.len(), 24 + 16 * 8);
+        assert_eq!(&bytes[..8], &1000u64.to_be_bytes());
+
+        let defilter = WillfFilter::from_bytes(&bytes).unwrap();
+        assert!(defilter.contains(b"hello").unwrap());
+        assert!(defilter.contains(b"world").unwrap());
+        assert!(!defilter.contains(b"foo").unwrap());
+        assert!(WillfFilter::from_bytes(&bytes[..100]).is_err());
+
+        let mut empty = WillfFilter::new(0, 3);
+        empty.add(b"hello").unwrap();
+        assert!(empty.contains(b"hello").unwrap());
+        assert_eq!(&empty.to_bytes()[..8], &1u64.to_be_bytes());
+    }
+
+    fn redis_filter() -> RedisBloomFilter {
+        RedisBloomFilter {
+            size: 0,
+            options: REDIS_OPT_FORCE64,
+            growth: 2,
+            links: vec![RedisLink {
+                bits: vec![0; 128],
+                bit_count: 1024,
+                size: 0,
+                error: 0.01,
+                bpe: 9.585,
+                hashes: 7,
+                entries: 100,
+                n2: 10,
+            }],
+        }
+    }
+
+    #[test]
+    fn test_redis_roundtrip() {
+        let mut filter = redis_filter();
+        filter.add(b"hello").unwrap();
+        filter.add(b"world").unwrap();
+
+        let bytes = filter.to_bytes();
+        assert_eq!(filter.header_len(), 73);
+        assert_eq!(bytes.len(), 73 + 128);
+
+        let defilter = RedisBloomFilter::from_bytes(&bytes).unwrap();
+        assert_eq!(defilter.size, 2);
+        assert!(defilter.contains(b"hello").unwrap());
+        assert!(defilter.contains(b"world").unwrap());
+        assert!(!defilter.contains(b"foo").unwrap());
+        assert!(RedisBloomFilter::from_bytes(&bytes[..150]).is_err());
+
+        // The first link's n2 exponent is the last byte of its descriptor.
+        for n2 in [64, u8::MAX] {
+            let mut corrupt = bytes.clone();
+            corrupt[REDIS_HEADER_SIZE + REDIS_LINK_SIZE - 1] = n2;
+            assert!(
+                matches!(
+                    RedisBloomFilter::from_bytes(&corrupt),
+                    Err(FilterError::InvalidFormat(
+                        "link modulus exponent too large"
+                    ))
+                ),
+                "{}",
+                n2
+            );
+        }
+    }
+
+    #[test]
+    fn test_murmur64a() {
+        // SMHasher verification value for MurmurHash64A.
+        let key: Vec<u8> = (0..=255).collect();
+        let mut hashes = Vec::with_capacity(256 * 8);
+        for i in 0..256 {
+            hashes.extend_from_slice(&murmur64a(&key[..i], 256 - i as u64).to_le_bytes());
+        }
+        assert_eq!(murmur64a(&hashes, 0) as u32, 0x1F0D3804);
+    }
+}
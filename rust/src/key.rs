@@ -0,0 +1,220 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use crate::{Filter, FilterError};
+
+/// Canonical byte encoding for keys.
+///
+/// Filters are portable only if every producer hashes the same bytes for the same key, so this
+/// trait pins the encoding down for common key types:
+///
+/// - Strings are encoded as UTF-8 and byte slices as-is.
+/// - Integers are widened to 64 bits and encoded big-endian, so `42u8` and `42i64` match.
+/// - IP addresses are encoded as their 4 or 16 octets.
+/// - UUIDs are encoded as their 16 bytes, and fixed-size byte arrays as-is.
+/// - Timestamps are encoded as microseconds since the Unix epoch, as an `i64`.
+/// - Tuples encode each element prefixed by its length as a big-endian `u32`.
+pub trait KeyEncode {
+    /// Appends the canonical encoding of the key to `buf`.
+    fn encode_key(&self, buf: &mut Vec<u8>);
+
+    /// Returns the canonical encoding of the key.
+    fn to_key_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        self.encode_key(&mut buf);
+        buf
+    }
+}
+
+impl<T: KeyEncode + ?Sized> KeyEncode for &T {
+    fn encode_key(&self, buf: &mut Vec<u8>) {
+        (**self).encode_key(buf)
+    }
+}
+
+impl KeyEncode for [u8] {
+    fn encode_key(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(self)
+    }
+}
+
+impl KeyEncode for Vec<u8> {
+    fn encode_key(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(self)
+    }
+}
+
+impl<const N: usize> KeyEncode for [u8; N] {
+    fn encode_key(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(self)
+    }
+}
+
+impl KeyEncode for str {
+    fn encode_key(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(self.as_bytes())
+    }
+}
+
+impl KeyEncode for String {
+    fn encode_key(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(self.as_bytes())
+    }
+}
+
+macro_rules! impl_key_encode_int {
+    ($wide:ty => $($ty:ty),*) => {
+        $(
+            impl KeyEncode for $ty {
+                fn encode_key(&self, buf: &mut Vec<u8>) {
+                    buf.extend_from_slice(&(*self as $wide).to_be_bytes())
+                }
+            }
+        )*
+    };
+}
+
+impl_key_encode_int!(i64 => i8, i16, i32, i64, isize);
+impl_key_encode_int!(u64 => u8, u16, u32, u64, usize);
+
+impl KeyEncode for Ipv4Addr {
+    fn encode_key(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.octets())
+    }
+}
+
+impl KeyEncode for Ipv6Addr {
+    fn encode_key(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.octets())
+    }
+}
+
+impl KeyEncode for IpAddr {
+    fn encode_key(&self, buf: &mut Vec<u8>) {
+        match self {
+            IpAddr::V4(addr) => addr.encode_key(buf),
+            IpAddr::V6(addr) => addr.encode_key(buf),
+        }
+    }
+}
+
+#[cfg(feature = "uuid")]
+impl KeyEncode for uuid::Uuid {
+    fn encode_key(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(self.as_bytes())
+    }
+}
+
+#[cfg(feature = "std")]
+impl KeyEncode for std::time::SystemTime {
+    fn encode_key(&self, buf: &mut Vec<u8>) {
+        let micros = match self.duration_since(std::time::UNIX_EPOCH) {
+            Ok(after) => after.as_micros() as i64,
+            Err(err) => -(err.duration().as_micros() as i64),
+        };
+        micros.encode_key(buf)
+    }
+}
+
+/// Appends `key` prefixed by the length of its encoding.
+fn encode_prefixed<K: KeyEncode + ?Sized>(key: &K, buf: &mut Vec<u8>) {
+    let start = buf.len();
+    buf.extend_from_slice(&[0; 4]);
+    key.encode_key(buf);
+    let len = (buf.len() - start - 4) as u32;
+    buf[start..start + 4].copy_from_slice(&len.to_be_bytes());
+}
+
+macro_rules! impl_key_encode_tuple {
+    ($($name:ident),+) => {
+        impl<$($name: KeyEncode),+> KeyEncode for ($($name,)+) {
+            #[allow(non_snake_case)]
+            fn encode_key(&self, buf: &mut Vec<u8>) {
+                let ($($name,)+) = self;
+                $(encode_prefixed($name, buf);)+
+            }
+        }
+    };
+}
+
+impl_key_encode_tuple!(A, B);
+impl_key_encode_tuple!(A, B, C);
+impl_key_encode_tuple!(A, B, C, D);
+
+impl Filter {
+    /// Adds a key to the filter using its canonical encoding.
+    pub fn add_key<K: KeyEncode + ?Sized>(&mut self, key: &K) -> Result<(), FilterError> {
+        self.add(&key.to_key_bytes())
+    }
+
+    /// Checks if a key is present in the filter using its canonical encoding.
+    pub fn contains_key<K: KeyEncode + ?Sized>(&self, key: &K) -> Result<bool, FilterError> {
+        self.contains(&key.to_key_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hex_literal::hex;
+
+    use super::*;
+
+    #[test]
+    fn test_key_encoding() {
+        let cases: Vec<(&str, Vec<u8>, Vec<u8>)> = vec![
+            ("str", "hello".to_key_bytes(), hex!("68656c6c6f").to_vec()),
+            ("u8", 42u8.to_key_bytes(), hex!("000000000000002a").to_vec()),
+            (
+                "i64",
+                42i64.to_key_bytes(),
+                hex!("000000000000002a").to_vec(),
+            ),
+            (
+                "i32",
+                (-1i32).to_key_bytes(),
+                hex!("ffffffffffffffff").to_vec(),
+            ),
+            (
+                "ipv4",
+                "10.0.0.1".parse::<IpAddr>().unwrap().to_key_bytes(),
+                hex!("0a000001").to_vec(),
+            ),
+            (
+                "tuple",
+                ("a", 1u8).to_key_bytes(),
+                hex!("0000000161 00000008 0000000000000001").to_vec(),
+            ),
+            ("array", [1u8, 2].to_key_bytes(), hex!("0102").to_vec()),
+        ];
+
+        for (title, actual, expected) in cases {
+            assert_eq!(actual, expected, "{}", title);
+        }
+
+        #[cfg(feature = "std")]
+        {
+            use std::time::{Duration, UNIX_EPOCH};
+
+            let after = UNIX_EPOCH + Duration::from_micros(1_500_000);
+            assert_eq!(after.to_key_bytes(), 1_500_000i64.to_key_bytes());
+            let before = UNIX_EPOCH - Duration::from_micros(1);
+            assert_eq!(before.to_key_bytes(), (-1i64).to_key_bytes());
+        }
+
+        assert_ne!(("ab", "c").to_key_bytes(), ("a", "bc").to_key_bytes());
+    }
+
+    #[test]
+    fn test_add_key() {
+        let mut filter = Filter::new(1000, 7);
+        filter.add_key("hello").unwrap();
+        filter.add_key(&42u32).unwrap();
+        filter.add_key(&("tenant", 7u64)).unwrap();
+
+        assert!(filter.contains(b"hello").unwrap());
+        assert!(filter.contains_key(&42i64).unwrap());
+        assert!(filter.contains_key(&("tenant", 7u8)).unwrap());
+        assert!(!filter.contains_key(&("tenant", 8u8)).unwrap());
+    }
+}
@@ -1,50 +1,244 @@
-use std::io::{Cursor, Read};
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
 
-use murmur3::murmur3_x64_128;
+extern crate alloc;
+
+use alloc::vec;
+use alloc::vec::Vec;
+use core::convert::Infallible;
+use core::fmt;
+
+use rmp::decode::bytes::BytesReadError;
+use rmp::decode::Bytes;
+use rmp::encode::ByteBuf;
 use rmp::{decode, encode};
 
+#[cfg(feature = "aging")]
+mod aging;
+#[cfg(feature = "atomic")]
+mod atomic;
+#[cfg(feature = "blocked")]
+mod blocked;
+#[cfg(feature = "std")]
+mod builder;
+#[cfg(feature = "chunked")]
+mod chunked;
+#[cfg(feature = "clock")]
+mod clock;
+#[cfg(feature = "compat")]
+pub mod compat;
+#[cfg(feature = "diagnostics")]
+pub mod diagnostics;
+#[cfg(feature = "dleft")]
+mod dleft;
+#[cfg(feature = "envelope")]
+pub mod envelope;
+#[cfg(feature = "interop")]
+pub mod interop;
+mod key;
+#[cfg(feature = "merge")]
+mod merge;
+#[cfg(feature = "migrating")]
+mod migrating;
+mod murmur;
+#[cfg(feature = "query")]
+mod query;
+#[cfg(feature = "range")]
+mod range;
+#[cfg(feature = "serde")]
+mod serde_impl;
+#[cfg(feature = "store")]
+pub mod store;
+#[cfg(feature = "streaming")]
+mod streaming;
+#[cfg(feature = "swap")]
+mod swap;
+#[cfg(feature = "tiered")]
+mod tiered;
+#[cfg(feature = "tinylfu")]
+mod tinylfu;
+mod versioned;
+
+#[cfg(feature = "aging")]
+pub use aging::AgingFilter;
+#[cfg(feature = "atomic")]
+pub use atomic::AtomicFilter;
+#[cfg(feature = "blocked")]
+pub use blocked::BlockedFilter;
+#[cfg(feature = "std")]
+pub use builder::{BuildError, FilterBuilder, FilterParams};
+#[cfg(feature = "chunked")]
+pub use chunked::{ChunkedWriter, ResumePoint};
+#[cfg(feature = "clock")]
+pub use clock::ClockFilter;
+#[cfg(feature = "dleft")]
+pub use dleft::DLeftCountingFilter;
+#[cfg(feature = "envelope")]
+pub use envelope::{load_any, AnyFilter};
+pub use key::KeyEncode;
+#[cfg(feature = "merge")]
+pub use merge::{
+    MergeCoordinator, MergeMessage, MergeParticipant, MergeTransport, StreamTransport,
+};
+#[cfg(feature = "migrating")]
+pub use migrating::MigratingFilter;
+#[cfg(feature = "query")]
+pub use query::Query;
+#[cfg(feature = "range")]
+pub use range::RangedFilter;
+#[cfg(feature = "streaming")]
+pub use streaming::StreamingBuilder;
+#[cfg(feature = "swap")]
+pub use swap::SwappableFilter;
+#[cfg(feature = "tiered")]
+pub use tiered::{MmapFilter, TieredFilter};
+#[cfg(feature = "tinylfu")]
+pub use tinylfu::TinyLfu;
+
 /// A Bloom filter implementation.
+#[derive(Clone)]
 pub struct Filter {
     bits: Vec<u8>,
     hash_count: u8,
+    seed: u32,
+    limit: Option<CapacityLimit>,
+}
+
+/// What happens when more distinct items are added to a `Filter` than it was sized for.
+#[derive(Debug, Clone, Copy)]
+pub enum CapacityPolicy {
+    /// Keep adding items, letting the false positive rate grow past the target.
+    Ignore,
+    /// Keep adding items, calling the hook with the capacity on the first item past it, for
+    /// example to log a warning.
+    Warn(fn(u64)),
+    /// Reject items past the capacity with `FilterError::CapacityExceeded`.
+    Error,
+}
+
+/// A capacity recorded on a `Filter`, with the number of distinct items added since.
+#[derive(Clone)]
+struct CapacityLimit {
+    capacity: u64,
+    policy: CapacityPolicy,
+    added: u64,
+    warned: bool,
+}
+
+/// Precomputed hashes of a key, for checking it against many filters without rehashing.
+///
+/// Only filters with the seed the hashes were computed with accept them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyHashes {
+    h1: u64,
+    h2: u64,
+    seed: u32,
+}
+
+/// The answer to a membership query, with the chance that a positive one is wrong.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Membership {
+    /// The key was never added.
+    DefinitelyNot,
+    /// The key was probably added; `est_fp` is the filter's estimated false positive rate.
+    Maybe { est_fp: f64 },
 }
 
 /// Errors that can occur when creating a `Filter` from serialized data.
 #[derive(Debug)]
 pub enum FilterError {
+    #[cfg(feature = "std")]
     DecodeError(decode::ValueReadError),
+    #[cfg(feature = "std")]
     EncodeError(encode::ValueWriteError),
+    #[cfg(feature = "std")]
     IOError(std::io::Error),
+    InvalidFormat(&'static str),
+    Incompatible(&'static str),
+    Full,
+    Unsupported(&'static str),
+    CapacityExceeded(u64),
+}
+
+impl fmt::Display for FilterError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            #[cfg(feature = "std")]
+            FilterError::DecodeError(err) => write!(f, "decode error: {}", err),
+            #[cfg(feature = "std")]
+            FilterError::EncodeError(err) => write!(f, "encode error: {}", err),
+            #[cfg(feature = "std")]
+            FilterError::IOError(err) => write!(f, "io error: {}", err),
+            FilterError::InvalidFormat(msg) => write!(f, "invalid format: {}", msg),
+            FilterError::Incompatible(msg) => write!(f, "incompatible filters: {}", msg),
+            FilterError::Full => write!(f, "filter is full"),
+            FilterError::Unsupported(msg) => write!(f, "unsupported: {}", msg),
+            FilterError::CapacityExceeded(capacity) => {
+                write!(f, "filter is over its capacity of {} items", capacity)
+            }
+        }
+    }
 }
 
+#[cfg(feature = "std")]
+impl std::error::Error for FilterError {}
+
+#[cfg(feature = "std")]
 impl From<decode::ValueReadError> for FilterError {
     fn from(err: decode::ValueReadError) -> Self {
         FilterError::DecodeError(err)
     }
 }
 
+impl From<decode::ValueReadError<BytesReadError>> for FilterError {
+    fn from(err: decode::ValueReadError<BytesReadError>) -> Self {
+        match err {
+            decode::ValueReadError::TypeMismatch(_) => {
+                FilterError::InvalidFormat("unexpected MessagePack type")
+            }
+            _ => FilterError::InvalidFormat("truncated input"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
 impl From<std::io::Error> for FilterError {
     fn from(err: std::io::Error) -> Self {
         FilterError::IOError(err)
     }
 }
 
+#[cfg(feature = "std")]
 impl From<encode::ValueWriteError> for FilterError {
     fn from(err: encode::ValueWriteError) -> Self {
         FilterError::EncodeError(err)
     }
 }
 
+impl From<encode::ValueWriteError<Infallible>> for FilterError {
+    fn from(err: encode::ValueWriteError<Infallible>) -> Self {
+        match err {
+            encode::ValueWriteError::InvalidMarkerWrite(err)
+            | encode::ValueWriteError::InvalidDataWrite(err) => match err {},
+        }
+    }
+}
+
 impl Filter {
-    /// Creates a new `Filter` with the specified size in bytes and number of hash functions.
+    /// Creates a new `Filter` with the specified size in bytes and number of hash functions. A
+    /// size of 0 is raised to 1.
     pub fn new(size: usize, hash_count: u8) -> Self {
         Self {
-            bits: vec![0; size],
+            bits: vec![0; size.max(1)],
             hash_count,
+            seed: 0,
+            limit: None,
         }
     }
 
     /// Creates a new `Filter` based on the number of entries and desired false positive rate.
+    ///
+    /// The number of entries is recorded as the filter's capacity, with `CapacityPolicy::Ignore`.
+    #[cfg(feature = "std")]
     pub fn new_from_entries_and_fp(entries: usize, fp_rate: f64) -> Result<Self, &'static str> {
         if entries == 0 {
             return Err("Number of entries must be positive");
@@ -53,81 +247,390 @@ impl Filter {
             return Err("False positive rate must be between 0 and 1");
         }
 
-        // Calculate m: number of bits
-        let m = -(entries as f64 * fp_rate.ln()) / (2.0_f64.ln().powi(2));
-        // Round m up to the nearest multiple of 8
-        let m = (m / 8.0).ceil() * 8.0;
-        let size = m as usize / 8;
+        let (size, k) = optimal_params(entries, fp_rate);
+        Ok(Self::new(size, k).with_recorded_capacity(entries as u64))
+    }
+
+    /// Creates a new `Filter` for the desired false positive rate using at most `max_hash_count`
+    /// hash functions.
+    ///
+    /// Each hash function is a random memory access on lookup, so capping it trades a larger
+    /// filter for lower latency. If the optimal count is already within the cap, this is the same
+    /// as `new_from_entries_and_fp`.
+    #[cfg(feature = "std")]
+    pub fn new_for_latency(
+        entries: usize,
+        fp_rate: f64,
+        max_hash_count: u8,
+    ) -> Result<Self, &'static str> {
+        if max_hash_count == 0 {
+            return Err("Number of hash functions must be positive");
+        }
+        let filter = Self::new_from_entries_and_fp(entries, fp_rate)?;
+        if filter.hash_count <= max_hash_count {
+            return Ok(filter);
+        }
+
+        // Smallest m with (1 - e^(-kn/m))^k <= p for the capped k
+        let k = max_hash_count as f64;
+        let m = -(k * entries as f64) / (1.0 - fp_rate.powf(1.0 / k)).ln();
+        let size = (m / 8.0).ceil() as usize;
 
-        // Calculate k: number of hash functions
-        let k = ((m / entries as f64) * 2.0_f64.ln()).round() as u8;
+        Ok(Self::new(size, max_hash_count).with_recorded_capacity(entries as u64))
+    }
 
-        Ok(Self {
-            bits: vec![0; size],
-            hash_count: k,
-        })
+    /// Records the capacity the filter was sized for, with `CapacityPolicy::Ignore`.
+    #[cfg(feature = "std")]
+    fn with_recorded_capacity(mut self, capacity: u64) -> Self {
+        self.limit = Some(CapacityLimit {
+            capacity,
+            policy: CapacityPolicy::Ignore,
+            added: 0,
+            warned: false,
+        });
+        self
+    }
+
+    /// Returns the number of distinct items the filter was sized for, if it was recorded.
+    ///
+    /// Filters built from a number of entries record it; deserialized filters do not.
+    pub fn capacity(&self) -> Option<u64> {
+        self.limit.as_ref().map(|limit| limit.capacity)
+    }
+
+    /// Sets the number of distinct items the filter was sized for, and what to do when more are
+    /// added.
+    ///
+    /// Items already in the filter count towards the capacity through `estimated_items`. After
+    /// that, an added item counts if the filter did not already report it as present, so adding
+    /// the same item again is free.
+    #[cfg(feature = "std")]
+    pub fn set_capacity(&mut self, capacity: u64, policy: CapacityPolicy) {
+        self.limit = Some(CapacityLimit {
+            capacity,
+            policy,
+            added: self.estimated_items().round() as u64,
+            warned: false,
+        });
+    }
+
+    /// Counts an item about to be added under the capacity policy, failing if it must be rejected.
+    fn count_added(&mut self, hashes: KeyHashes) -> Result<(), FilterError> {
+        let Some(limit) = &self.limit else {
+            return Ok(());
+        };
+        if matches!(limit.policy, CapacityPolicy::Ignore) || self.contains_hashed(hashes)? {
+            return Ok(());
+        }
+
+        let limit = self.limit.as_mut().unwrap();
+        if limit.added >= limit.capacity {
+            match limit.policy {
+                CapacityPolicy::Ignore => {}
+                CapacityPolicy::Warn(hook) => {
+                    if !limit.warned {
+                        limit.warned = true;
+                        hook(limit.capacity);
+                    }
+                }
+                CapacityPolicy::Error => return Err(FilterError::CapacityExceeded(limit.capacity)),
+            }
+        }
+        limit.added += 1;
+        Ok(())
     }
 
     /// Deserializes a `Filter` from a byte slice.
+    ///
+    /// Accepts both the legacy format written by `serialize` and the versioned format written by
+    /// `serialize_compressed`.
     pub fn from_serialized(serialized: &[u8]) -> Result<Self, FilterError> {
-        let mut reader = Cursor::new(serialized);
+        if versioned::is_extension(serialized) {
+            return Self::from_versioned(serialized);
+        }
 
-        let bits_len = decode::read_bin_len(&mut reader)?;
-        let mut bits = vec![0u8; bits_len as usize];
-        reader.read_exact(&mut bits)?;
+        let mut reader = Bytes::new(serialized);
 
-        let hash_count = decode::read_u8(&mut reader)?;
+        let bits_len = decode::read_bin_len(&mut reader)? as usize;
+        if bits_len == 0 {
+            return Err(FilterError::InvalidFormat("empty bit array"));
+        }
+        let remaining = reader.remaining_slice();
+        if remaining.len() < bits_len {
+            return Err(FilterError::InvalidFormat("truncated bit array"));
+        }
+        let (bits, rest) = remaining.split_at(bits_len);
 
-        Ok(Self { bits, hash_count })
+        let hash_count = decode::read_u8(&mut Bytes::new(rest))?;
+
+        Ok(Self {
+            bits: bits.to_vec(),
+            hash_count,
+            seed: 0,
+            limit: None,
+        })
     }
 
     /// Computes two 64-bit hashes for the given item using Murmur3.
-    fn hash(item: &[u8]) -> Result<(u64, u64), std::io::Error> {
-        let hash = murmur3_x64_128(&mut Cursor::new(item), 0)?;
-        Ok(((hash & 0xFFFF_FFFF_FFFF_FFFF) as u64, (hash >> 64) as u64))
+    #[cfg_attr(
+        feature = "profiling",
+        inline(never),
+        tracing::instrument(level = "trace", skip_all)
+    )]
+    fn hash(item: &[u8], seed: u32) -> (u64, u64) {
+        murmur::murmur3_x64_128(item, seed)
+    }
+
+    /// Computes the bit index of the `i`-th probe in a filter of `m` bits.
+    #[cfg_attr(
+        feature = "profiling",
+        inline(never),
+        tracing::instrument(level = "trace", skip_all)
+    )]
+    fn probe_index(h1: u64, h2: u64, i: u64, m: u64) -> usize {
+        (h1.wrapping_add(i.wrapping_mul(h2)) % m) as usize
     }
 
     /// Adds an item to the filter.
     pub fn add(&mut self, item: &[u8]) -> Result<(), FilterError> {
+        self.add_hashed(Self::hash_key_with_seed(item, self.seed))
+    }
+
+    /// Checks if an item is present in the filter.
+    pub fn contains(&self, item: &[u8]) -> Result<bool, FilterError> {
+        self.contains_hashed(Self::hash_key_with_seed(item, self.seed))
+    }
+
+    /// Checks if an item is present in the filter, returning the estimated false positive rate
+    /// with a positive answer so callers can weight it.
+    ///
+    /// The estimate counts the bits set, so positive answers cost a pass over the filter.
+    #[cfg(feature = "std")]
+    pub fn contains_with_confidence(&self, item: &[u8]) -> Result<Membership, FilterError> {
+        if !self.contains(item)? {
+            return Ok(Membership::DefinitelyNot);
+        }
+        Ok(Membership::Maybe {
+            est_fp: self.estimated_fp_rate(),
+        })
+    }
+
+    /// Hashes a key once for use with `add_hashed` and `contains_hashed` on unseeded filters.
+    pub fn hash_key(item: &[u8]) -> KeyHashes {
+        Self::hash_key_with_seed(item, 0)
+    }
+
+    /// Hashes a key once for use with filters built with the given seed.
+    pub fn hash_key_with_seed(item: &[u8], seed: u32) -> KeyHashes {
+        let (h1, h2) = Self::hash(item, seed);
+        KeyHashes { h1, h2, seed }
+    }
+
+    /// Adds a key from its precomputed hashes.
+    pub fn add_hashed(&mut self, hashes: KeyHashes) -> Result<(), FilterError> {
+        if hashes.seed != self.seed {
+            return Err(FilterError::Incompatible("seeds differ"));
+        }
+        self.count_added(hashes)?;
         let m = (self.bits.len() * 8) as u64;
-        let (h1, h2) = Self::hash(item)?;
 
         for i in 0..self.hash_count as u64 {
-            let index = (h1.wrapping_add(i.wrapping_mul(h2)) % m as u64) as usize;
-            self.bits[index / 8] |= 1 << (index % 8);
+            let index = Self::probe_index(hashes.h1, hashes.h2, i, m);
+            self.set_bit(index);
         }
         Ok(())
     }
 
-    /// Checks if an item is present in the filter.
-    pub fn contains(&self, item: &[u8]) -> Result<bool, FilterError> {
+    /// Checks if a key is present from its precomputed hashes.
+    pub fn contains_hashed(&self, hashes: KeyHashes) -> Result<bool, FilterError> {
+        if hashes.seed != self.seed {
+            return Err(FilterError::Incompatible("seeds differ"));
+        }
         let m = (self.bits.len() * 8) as u64;
-        let (h1, h2) = Self::hash(item)?;
 
         Ok((0..self.hash_count as u64).all(|i| {
-            let index = (h1.wrapping_add(i.wrapping_mul(h2)) % m) as usize;
-            self.bits[index / 8] & (1 << (index % 8)) != 0
+            let index = Self::probe_index(hashes.h1, hashes.h2, i, m);
+            self.test_bit(index)
         }))
     }
 
+    /// Sets the bit at `index`, the memory access of an insertion.
+    #[cfg_attr(
+        feature = "profiling",
+        inline(never),
+        tracing::instrument(level = "trace", skip_all)
+    )]
+    fn set_bit(&mut self, index: usize) {
+        self.bits[index / 8] |= 1 << (index % 8);
+    }
+
+    /// Returns whether the bit at `index` is set, the memory access of a lookup.
+    #[cfg_attr(
+        feature = "profiling",
+        inline(never),
+        tracing::instrument(level = "trace", skip_all)
+    )]
+    fn test_bit(&self, index: usize) -> bool {
+        self.bits[index / 8] & (1 << (index % 8)) != 0
+    }
+
+    /// Checks if an item is present in any of `filters`, hashing it only once per seed.
+    pub fn contains_in_any(filters: &[&Filter], item: &[u8]) -> Result<bool, FilterError> {
+        let mut hashes: Option<KeyHashes> = None;
+        for filter in filters {
+            let key = match hashes {
+                Some(key) if key.seed == filter.seed => key,
+                _ => *hashes.insert(Self::hash_key_with_seed(item, filter.seed)),
+            };
+            if filter.contains_hashed(key)? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Checks a batch of items, returning a packed bitset of the results.
+    ///
+    /// Bit `i % 64` of word `i / 64` is set if the `i`-th item may be present. Bits are
+    /// least-significant first, matching Arrow validity bitmaps, so the result can be combined
+    /// with other predicate masks using plain bitwise operations.
+    pub fn contains_many_bitset<I, K>(&self, items: I) -> Result<Vec<u64>, FilterError>
+    where
+        I: IntoIterator<Item = K>,
+        K: AsRef<[u8]>,
+    {
+        let items = items.into_iter();
+        let mut words = Vec::with_capacity(items.size_hint().0.div_ceil(64));
+        for (i, item) in items.enumerate() {
+            if i % 64 == 0 {
+                words.push(0);
+            }
+            if self.contains(item.as_ref())? {
+                words[i / 64] |= 1 << (i % 64);
+            }
+        }
+        Ok(words)
+    }
+
+    /// Returns the size of the filter in bytes.
+    pub fn size(&self) -> usize {
+        self.bits.len()
+    }
+
+    /// Returns the number of hash functions.
+    pub fn hash_count(&self) -> u8 {
+        self.hash_count
+    }
+
+    /// Returns the hash seed. Filters built with `Filter::new` use seed 0.
+    pub fn seed(&self) -> u32 {
+        self.seed
+    }
+
+    /// Returns the number of bits set.
+    pub fn count_ones(&self) -> u64 {
+        self.bits.iter().map(|byte| byte.count_ones() as u64).sum()
+    }
+
+    /// Returns the fraction of bits set.
+    pub fn fill_ratio(&self) -> f64 {
+        self.count_ones() as f64 / (self.bits.len() * 8) as f64
+    }
+
+    /// Estimates the number of distinct items added, based on the number of bits set.
+    #[cfg(feature = "std")]
+    pub fn estimated_items(&self) -> f64 {
+        let m = (self.bits.len() * 8) as f64;
+        -(m / self.hash_count as f64) * (1.0 - self.fill_ratio()).ln()
+    }
+
+    /// Estimates the current false positive rate, based on the number of bits set.
+    #[cfg(feature = "std")]
+    pub fn estimated_fp_rate(&self) -> f64 {
+        self.fill_ratio().powi(self.hash_count as i32)
+    }
+
+    /// Checks that `other` has the same size and number of hash functions.
+    fn check_compatible(&self, other: &Filter) -> Result<(), FilterError> {
+        if self.bits.len() != other.bits.len() {
+            return Err(FilterError::Incompatible("sizes differ"));
+        }
+        if self.hash_count != other.hash_count {
+            return Err(FilterError::Incompatible("hash counts differ"));
+        }
+        if self.seed != other.seed {
+            return Err(FilterError::Incompatible("seeds differ"));
+        }
+        Ok(())
+    }
+
+    /// Merges `other` into this filter, so it contains items from both.
+    pub fn union(&mut self, other: &Filter) -> Result<(), FilterError> {
+        self.check_compatible(other)?;
+        for (a, b) in self.bits.iter_mut().zip(&other.bits) {
+            *a |= b;
+        }
+        Ok(())
+    }
+
+    /// Intersects this filter with `other`, so it only contains items present in both.
+    ///
+    /// The result may report more false positives than a filter built from the intersection.
+    pub fn intersect(&mut self, other: &Filter) -> Result<(), FilterError> {
+        self.check_compatible(other)?;
+        for (a, b) in self.bits.iter_mut().zip(&other.bits) {
+            *a &= b;
+        }
+        Ok(())
+    }
+
     /// Serializes the filter into a byte vector.
+    ///
+    /// The format has no room for a seed, so seeded filters are rejected; use
+    /// `serialize_compressed` for those.
     pub fn serialize(&self) -> Result<Vec<u8>, FilterError> {
-        let mut buf = Vec::with_capacity(self.bits.len() + 1);
+        if self.seed != 0 {
+            return Err(FilterError::Unsupported(
+                "seeded filters cannot be serialized",
+            ));
+        }
+        let mut buf = ByteBuf::with_capacity(self.bits.len() + 7);
         encode::write_bin(&mut buf, &self.bits)?;
         encode::write_u8(&mut buf, self.hash_count)?;
-        Ok(buf)
+        Ok(buf.into_vec())
     }
 }
 
+/// Computes the size in bytes and number of hash functions for `entries` items at `fp_rate`.
+#[cfg(feature = "std")]
+fn optimal_params(entries: usize, fp_rate: f64) -> (usize, u8) {
+    // Calculate m: number of bits
+    let m = -(entries as f64 * fp_rate.ln()) / (2.0_f64.ln().powi(2));
+    // Round m up to the nearest multiple of 8
+    let m = (m / 8.0).ceil() * 8.0;
+    let size = m as usize / 8;
+
+    // Calculate k: number of hash functions
+    let k = ((m / entries as f64) * 2.0_f64.ln()).round() as u8;
+
+    (size, k)
+}
+
 #[cfg(test)]
 mod tests {
 
+    #[cfg(feature = "std")]
+    use core::sync::atomic::{AtomicU64, Ordering};
+
     use hex_literal::hex;
+    #[cfg(feature = "std")]
+    use proptest::prelude::*;
     use sha2::{Digest, Sha256};
 
     use super::*;
 
     #[test]
+    #[cfg(feature = "std")]
     fn test_new_from_entries_and_fp() {
         let cases = vec![
             ("entries=1000, fp=0.01", 1000, 0.01, 1199, 7),
@@ -141,6 +644,31 @@ mod tests {
         }
     }
 
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_new_for_latency() {
+        let cases = vec![
+            ("within cap", 1000, 0.01, 10, 1199, 7),
+            ("capped", 1000, 0.01, 3, 1546, 3),
+            ("single hash", 1000, 0.01, 1, 12438, 1),
+        ];
+
+        for (title, entries, fp_rate, max_hash_count, expected_bits_len, expected_hash_count) in
+            cases
+        {
+            let filter = Filter::new_for_latency(entries, fp_rate, max_hash_count).unwrap();
+            assert_eq!(filter.bits.len(), expected_bits_len, "{}", title);
+            assert_eq!(filter.hash_count, expected_hash_count, "{}", title);
+
+            let m = (filter.bits.len() * 8) as f64;
+            let k = filter.hash_count as f64;
+            let expected_fp = (1.0 - (-k * entries as f64 / m).exp()).powf(k);
+            assert!(expected_fp <= fp_rate * 1.01, "{}: {}", title, expected_fp);
+        }
+
+        assert!(Filter::new_for_latency(1000, 0.01, 0).is_err());
+    }
+
     #[test]
     fn test_filter() {
         let mut filter = Filter::new(1000, 7);
@@ -149,12 +677,101 @@ mod tests {
         filter.add(b"foo").unwrap();
         filter.add(b"bar").unwrap();
 
-        assert_eq!(filter.contains(b"hello").unwrap(), true);
-        assert_eq!(filter.contains(b"world").unwrap(), true);
-        assert_eq!(filter.contains(b"foo").unwrap(), true);
-        assert_eq!(filter.contains(b"bar").unwrap(), true);
-        assert_eq!(filter.contains(b"baz").unwrap(), false);
-        assert_eq!(filter.contains(b"qux").unwrap(), false);
+        assert!(filter.contains(b"hello").unwrap());
+        assert!(filter.contains(b"world").unwrap());
+        assert!(filter.contains(b"foo").unwrap());
+        assert!(filter.contains(b"bar").unwrap());
+        assert!(!filter.contains(b"baz").unwrap());
+        assert!(!filter.contains(b"qux").unwrap());
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_contains_with_confidence() {
+        let mut filter = Filter::new(1000, 7);
+        assert_eq!(
+            filter.contains_with_confidence(b"hello").unwrap(),
+            Membership::DefinitelyNot
+        );
+
+        for i in 0..100 {
+            filter.add(i.to_string().as_bytes()).unwrap();
+        }
+        let est_fp = filter.estimated_fp_rate();
+        assert!(est_fp > 0.0 && est_fp < 1e-4, "{}", est_fp);
+        let cases: [(&[u8], Membership); 2] = [
+            (b"42", Membership::Maybe { est_fp }),
+            (b"hello", Membership::DefinitelyNot),
+        ];
+        for (key, expected) in cases {
+            assert_eq!(
+                filter.contains_with_confidence(key).unwrap(),
+                expected,
+                "{:?}",
+                key
+            );
+        }
+
+        // The estimate grows as the filter fills up.
+        for i in 100..1000 {
+            filter.add(i.to_string().as_bytes()).unwrap();
+        }
+        match filter.contains_with_confidence(b"42").unwrap() {
+            Membership::Maybe { est_fp: fuller } => assert!(fuller > est_fp * 100.0, "{}", fuller),
+            Membership::DefinitelyNot => panic!("false negative"),
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_capacity() {
+        static WARNINGS: AtomicU64 = AtomicU64::new(0);
+        fn warn(capacity: u64) {
+            assert_eq!(capacity, 100);
+            WARNINGS.fetch_add(1, Ordering::Relaxed);
+        }
+
+        let cases = [
+            (CapacityPolicy::Ignore, 150, 0),
+            (CapacityPolicy::Warn(warn), 150, 1),
+            (CapacityPolicy::Error, 100, 0),
+        ];
+        for (policy, added, warnings) in cases {
+            WARNINGS.store(0, Ordering::Relaxed);
+            let mut filter = Filter::new_from_entries_and_fp(1000, 0.01).unwrap();
+            assert_eq!(filter.capacity(), Some(1000));
+            filter.set_capacity(100, policy);
+
+            let mut accepted = 0;
+            for i in 0..150 {
+                // Adding an item again does not count towards the capacity.
+                for _ in 0..2 {
+                    match filter.add(i.to_string().as_bytes()) {
+                        Ok(()) => {}
+                        Err(FilterError::CapacityExceeded(100)) => continue,
+                        Err(err) => panic!("{:?}", err),
+                    }
+                }
+                if filter.contains(i.to_string().as_bytes()).unwrap() {
+                    accepted += 1;
+                }
+            }
+            assert_eq!(accepted, added, "{:?}", policy);
+            assert_eq!(WARNINGS.load(Ordering::Relaxed), warnings, "{:?}", policy);
+        }
+
+        // Items already in the filter count towards a capacity set later.
+        let mut filter = Filter::new(1199, 7);
+        for i in 0..100 {
+            filter.add(i.to_string().as_bytes()).unwrap();
+        }
+        filter.set_capacity(100, CapacityPolicy::Error);
+        assert!(filter.add(b"0").is_ok());
+        let accepted = (0..10)
+            .take_while(|i| filter.add(format!("new:{}", i).as_bytes()).is_ok())
+            .count();
+        assert!(accepted <= 2, "{}", accepted);
+        assert_eq!(Filter::new(1199, 7).capacity(), None);
     }
 
     #[test]
@@ -168,12 +785,150 @@ mod tests {
         let serialized = filter.serialize().unwrap();
         let defilter = Filter::from_serialized(&serialized).unwrap();
 
-        assert_eq!(defilter.contains(b"hello").unwrap(), true);
-        assert_eq!(defilter.contains(b"world").unwrap(), true);
-        assert_eq!(defilter.contains(b"foo").unwrap(), true);
-        assert_eq!(defilter.contains(b"bar").unwrap(), true);
-        assert_eq!(defilter.contains(b"baz").unwrap(), false);
-        assert_eq!(defilter.contains(b"qux").unwrap(), false);
+        assert!(defilter.contains(b"hello").unwrap());
+        assert!(defilter.contains(b"world").unwrap());
+        assert!(defilter.contains(b"foo").unwrap());
+        assert!(defilter.contains(b"bar").unwrap());
+        assert!(!defilter.contains(b"baz").unwrap());
+        assert!(!defilter.contains(b"qux").unwrap());
+
+        assert!(Filter::from_serialized(&serialized[..500]).is_err());
+        assert!(Filter::from_serialized(&serialized[..serialized.len() - 1]).is_err());
+    }
+
+    #[cfg(feature = "profiling")]
+    #[test]
+    fn test_profiling_spans() {
+        use std::sync::{Arc, Mutex};
+
+        use tracing::span::{Attributes, Id, Record};
+        use tracing::{Event, Metadata, Subscriber};
+
+        /// Records the names of the spans created.
+        struct Spans(Arc<Mutex<Vec<&'static str>>>);
+
+        impl Subscriber for Spans {
+            fn enabled(&self, _: &Metadata<'_>) -> bool {
+                true
+            }
+            fn new_span(&self, span: &Attributes<'_>) -> Id {
+                let mut names = self.0.lock().unwrap();
+                names.push(span.metadata().name());
+                Id::from_u64(names.len() as u64)
+            }
+            fn record(&self, _: &Id, _: &Record<'_>) {}
+            fn record_follows_from(&self, _: &Id, _: &Id) {}
+            fn event(&self, _: &Event<'_>) {}
+            fn enter(&self, _: &Id) {}
+            fn exit(&self, _: &Id) {}
+        }
+
+        let names = Arc::new(Mutex::new(Vec::new()));
+        let mut filter = Filter::new(1000, 2);
+        tracing::subscriber::with_default(Spans(names.clone()), || {
+            filter.add(b"hello").unwrap();
+            filter.contains(b"hello").unwrap();
+        });
+
+        let names = names.lock().unwrap();
+        for name in [
+            "hash",
+            "murmur3_x64_128",
+            "probe_index",
+            "set_bit",
+            "test_bit",
+        ] {
+            assert!(names.contains(&name), "{}: {:?}", name, names);
+        }
+    }
+
+    #[test]
+    fn test_empty_bit_array() {
+        assert_eq!(Filter::new(0, 3).size(), 1);
+        assert!(matches!(
+            Filter::from_serialized(&hex!("c400 cc03")),
+            Err(FilterError::InvalidFormat("empty bit array"))
+        ));
+    }
+
+    #[test]
+    fn test_contains_many_bitset() {
+        let mut filter = Filter::new(1000, 7);
+        for i in (0..130).step_by(3) {
+            filter.add(i.to_string().as_bytes()).unwrap();
+        }
+
+        let keys: Vec<String> = (0..130).map(|i| i.to_string()).collect();
+        let bitset = filter.contains_many_bitset(&keys).unwrap();
+        assert_eq!(bitset.len(), 3);
+        for (i, key) in keys.iter().enumerate() {
+            let bit = bitset[i / 64] & (1 << (i % 64)) != 0;
+            assert_eq!(bit, filter.contains(key.as_bytes()).unwrap(), "{}", key);
+        }
+        assert_eq!(bitset[2] >> 2, 0);
+    }
+
+    #[test]
+    fn test_hashed() {
+        let mut a = Filter::new(1000, 7);
+        let mut b = Filter::new(512, 3);
+        let hashes = Filter::hash_key(b"hello");
+
+        a.add_hashed(hashes).unwrap();
+        assert!(a.contains(b"hello").unwrap());
+        assert!(a.contains_hashed(hashes).unwrap());
+        assert!(!b.contains_hashed(hashes).unwrap());
+
+        b.add(b"world").unwrap();
+        assert!(b.contains_hashed(Filter::hash_key(b"world")).unwrap());
+
+        let mut seeded = Filter::new(1000, 7);
+        seeded.seed = 42;
+        assert!(matches!(
+            seeded.contains_hashed(hashes),
+            Err(FilterError::Incompatible("seeds differ"))
+        ));
+        seeded
+            .add_hashed(Filter::hash_key_with_seed(b"seeded", 42))
+            .unwrap();
+        assert!(seeded.contains(b"seeded").unwrap());
+
+        let cases = vec![
+            ("first", b"hello".as_slice(), true),
+            ("second", b"world", true),
+            ("seeded", b"seeded", true),
+            ("none", b"foo", false),
+        ];
+        for (title, item, expected) in cases {
+            let actual = Filter::contains_in_any(&[&a, &b, &seeded], item).unwrap();
+            assert_eq!(actual, expected, "{}", title);
+        }
+        assert!(!Filter::contains_in_any(&[], b"hello").unwrap());
+    }
+
+    #[test]
+    fn test_union_intersect() {
+        let mut a = Filter::new(1000, 7);
+        a.add(b"hello").unwrap();
+        a.add(b"both").unwrap();
+        let mut b = Filter::new(1000, 7);
+        b.add(b"world").unwrap();
+        b.add(b"both").unwrap();
+
+        let mut union = a.clone();
+        union.union(&b).unwrap();
+        assert!(union.contains(b"hello").unwrap());
+        assert!(union.contains(b"world").unwrap());
+        assert!(union.contains(b"both").unwrap());
+
+        let mut intersection = a.clone();
+        intersection.intersect(&b).unwrap();
+        assert!(!intersection.contains(b"hello").unwrap());
+        assert!(!intersection.contains(b"world").unwrap());
+        assert!(intersection.contains(b"both").unwrap());
+
+        assert!(a.union(&Filter::new(500, 7)).is_err());
+        assert!(a.intersect(&Filter::new(1000, 5)).is_err());
     }
 
     #[test]
@@ -204,9 +959,128 @@ mod tests {
         let b = s.as_bytes();
         assert_eq!(b, hex!("68656c6c6f"));
 
-        // Since hash returns a Result, we need to handle it
-        let (h1, h2) = Filter::hash(b).unwrap();
+        let (h1, h2) = Filter::hash(b, 0);
         assert_eq!(h1, 0xcbd8a7b341bd9b02);
         assert_eq!(h2, 0x5b1e906a48ae1d19);
     }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_golden_vectors() {
+        let vectors: Vec<serde_json::Value> =
+            serde_json::from_str(include_str!("../../testdata/golden.json")).unwrap();
+
+        for vector in vectors {
+            let variant = vector["variant"].as_str().unwrap();
+            let size = vector["size"].as_u64().unwrap() as usize;
+            let hash_count = vector["hash_count"].as_u64().unwrap_or(0) as u8;
+            let keys: Vec<String> = (0..vector["keys"].as_u64().unwrap())
+                .map(|i| i.to_string())
+                .collect();
+
+            let serialized = match variant {
+                "plain" | "compressed" => {
+                    let mut filter = Filter::new(size, hash_count);
+                    for key in &keys {
+                        filter.add(key.as_bytes()).unwrap();
+                    }
+                    if variant == "plain" {
+                        filter.serialize().unwrap()
+                    } else {
+                        filter.serialize_compressed().unwrap()
+                    }
+                }
+                "blocked" if !cfg!(feature = "blocked") => continue,
+                #[cfg(feature = "blocked")]
+                "blocked" => {
+                    let mut filter = BlockedFilter::new(size, hash_count);
+                    for key in &keys {
+                        filter.add(key.as_bytes()).unwrap();
+                    }
+                    filter.serialize().unwrap()
+                }
+                "dleft" if !cfg!(feature = "dleft") => continue,
+                #[cfg(feature = "dleft")]
+                "dleft" => {
+                    let mut filter = DLeftCountingFilter::new(size);
+                    for key in &keys {
+                        filter.add(key.as_bytes()).unwrap();
+                    }
+                    filter.serialize().unwrap()
+                }
+                _ => panic!("unknown golden vector variant {}", variant),
+            };
+
+            assert_eq!(
+                hex::encode(Sha256::digest(&serialized)),
+                vector["sha256"].as_str().unwrap(),
+                "{}",
+                vector
+            );
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_fp_rate() {
+        let cases = vec![
+            (100, 0.1),
+            (100, 0.01),
+            (1000, 0.1),
+            (1000, 0.01),
+            (1000, 0.001),
+            (10000, 0.01),
+            (10000, 0.001),
+        ];
+        let lookups = 100000;
+
+        for (entries, fp_rate) in cases {
+            let mut filter = Filter::new_from_entries_and_fp(entries, fp_rate).unwrap();
+            for i in 0..entries {
+                filter.add(format!("key:{}", i).as_bytes()).unwrap();
+            }
+
+            let false_positives = (0..lookups)
+                .filter(|i| filter.contains(format!("absent:{}", i).as_bytes()).unwrap())
+                .count();
+            let observed = false_positives as f64 / lookups as f64;
+            assert!(
+                observed <= fp_rate * 1.5,
+                "entries={}, fp={}: {}",
+                entries,
+                fp_rate,
+                observed
+            );
+        }
+    }
+
+    #[cfg(feature = "std")]
+    proptest! {
+        #[test]
+        fn test_no_false_negatives(
+            keys in prop::collection::vec(prop::collection::vec(any::<u8>(), 0..32), 0..200),
+            size in 1usize..512,
+            hash_count in 1u8..16,
+            seed in any::<u32>(),
+        ) {
+            let mut filter = Filter::new(size, hash_count);
+            filter.seed = seed;
+            for key in &keys {
+                filter.add(key).unwrap();
+            }
+
+            let defilter = Filter::from_serialized(&filter.serialize_compressed().unwrap()).unwrap();
+            prop_assert_eq!(&defilter.bits, &filter.bits);
+            prop_assert_eq!(defilter.seed, seed);
+            if seed == 0 {
+                let defilter = Filter::from_serialized(&filter.serialize().unwrap()).unwrap();
+                prop_assert_eq!(&defilter.bits, &filter.bits);
+            }
+
+            for key in &keys {
+                prop_assert!(filter.contains(key).unwrap());
+                prop_assert!(defilter.contains(key).unwrap());
+            }
+        }
+    }
 }
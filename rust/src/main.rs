@@ -0,0 +1,198 @@
+use std::error::Error;
+use std::fs;
+use std::io::{self, BufRead};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use clap::{Parser, Subcommand};
+use pbloom::{load_any, Filter};
+
+/// Build and query portable bloom filters.
+#[derive(Parser)]
+#[command(name = "pbloom", version)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Create an empty filter sized for the given entries and false positive rate.
+    Create {
+        /// Expected number of entries.
+        #[arg(long)]
+        entries: usize,
+        /// Target false positive rate.
+        #[arg(long)]
+        fp: f64,
+        /// Cap on the number of hash functions, trading a larger filter for faster lookups.
+        #[arg(long)]
+        max_hashes: Option<u8>,
+        /// Output file.
+        #[arg(short, long)]
+        output: PathBuf,
+    },
+    /// Add keys read from stdin, one per line, to a filter in place.
+    Add {
+        /// Filter file.
+        filter: PathBuf,
+    },
+    /// Check whether a key may be present. Exits with 1 if it is definitely absent.
+    Check {
+        /// Filter file.
+        filter: PathBuf,
+        /// Key to look up.
+        key: String,
+    },
+    /// Merge two filters with the same parameters into one.
+    Merge {
+        /// First filter file.
+        a: PathBuf,
+        /// Second filter file.
+        b: PathBuf,
+        /// Output file.
+        #[arg(short, long)]
+        output: PathBuf,
+    },
+    /// Print the parameters and fill statistics of any filter structure.
+    Info {
+        /// Filter file.
+        filter: PathBuf,
+    },
+}
+
+fn load(path: &Path) -> Result<Filter, Box<dyn Error>> {
+    let bytes = fs::read(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+    Ok(Filter::from_serialized(&bytes).map_err(|err| format!("{}: {}", path.display(), err))?)
+}
+
+fn save(path: &Path, filter: &Filter) -> Result<(), Box<dyn Error>> {
+    fs::write(path, filter.serialize()?).map_err(|err| format!("{}: {}", path.display(), err))?;
+    Ok(())
+}
+
+/// Runs a command, reading keys for `add` from `input`.
+fn run(cli: Cli, input: impl BufRead) -> Result<ExitCode, Box<dyn Error>> {
+    match cli.command {
+        Command::Create {
+            entries,
+            fp,
+            max_hashes,
+            output,
+        } => {
+            let filter = match max_hashes {
+                Some(max_hashes) => Filter::new_for_latency(entries, fp, max_hashes)?,
+                None => Filter::new_from_entries_and_fp(entries, fp)?,
+            };
+            save(&output, &filter)?;
+        }
+        Command::Add { filter: path } => {
+            let mut filter = load(&path)?;
+            for line in input.lines() {
+                filter.add(line?.as_bytes())?;
+            }
+            save(&path, &filter)?;
+        }
+        Command::Check { filter, key } => {
+            if !load(&filter)?.contains(key.as_bytes())? {
+                println!("absent");
+                return Ok(ExitCode::FAILURE);
+            }
+            println!("maybe present");
+        }
+        Command::Merge { a, b, output } => {
+            let mut merged = load(&a)?;
+            merged.union(&load(&b)?)?;
+            save(&output, &merged)?;
+        }
+        Command::Info { filter: path } => {
+            let bytes = fs::read(&path).map_err(|err| format!("{}: {}", path.display(), err))?;
+            let filter = load_any(&bytes).map_err(|err| format!("{}: {}", path.display(), err))?;
+            let stats = filter.stats();
+            println!("kind:            {}", stats.kind);
+            println!("size:            {} bytes", stats.size);
+            println!("hash functions:  {}", stats.hash_count);
+            println!("fill ratio:      {:.4}", stats.fill_ratio);
+            println!("estimated fp:    {:.6}", stats.estimated_fp_rate);
+            if let Some(filter) = filter.as_filter() {
+                println!("bits set:        {}", filter.count_ones());
+                println!("estimated items: {:.0}", filter.estimated_items());
+            }
+        }
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+fn main() -> ExitCode {
+    match run(Cli::parse(), io::stdin().lock()) {
+        Ok(code) => code,
+        Err(err) => {
+            eprintln!("pbloom: {}", err);
+            ExitCode::from(2)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+
+    fn pbloom(args: &[&str], input: &str) -> Result<ExitCode, Box<dyn Error>> {
+        let cli = Cli::parse_from([&["pbloom"], args].concat());
+        run(cli, input.as_bytes())
+    }
+
+    #[test]
+    fn test_cli() {
+        let dir = env::temp_dir().join(format!("pbloom-cli-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = |name: &str| dir.join(name).to_str().unwrap().to_string();
+        let (a, b, merged) = (path("a"), path("b"), path("merged"));
+
+        for (output, keys) in [(&a, "hello\nworld\n"), (&b, "other\n")] {
+            let create = ["create", "--entries", "1000", "--fp", "0.01", "-o", output];
+            assert_eq!(pbloom(&create, "").unwrap(), ExitCode::SUCCESS);
+            assert_eq!(pbloom(&["add", output], keys).unwrap(), ExitCode::SUCCESS);
+        }
+        assert!(load(Path::new(&a)).unwrap().contains(b"world").unwrap());
+        assert!(!load(Path::new(&a)).unwrap().contains(b"other").unwrap());
+
+        let merge = ["merge", a.as_str(), b.as_str(), "-o", merged.as_str()];
+        assert_eq!(pbloom(&merge, "").unwrap(), ExitCode::SUCCESS);
+
+        let cases = vec![
+            ("present", a.as_str(), "hello", ExitCode::SUCCESS),
+            ("absent", a.as_str(), "missing", ExitCode::FAILURE),
+            ("merged from a", merged.as_str(), "hello", ExitCode::SUCCESS),
+            ("merged from b", merged.as_str(), "other", ExitCode::SUCCESS),
+            (
+                "merged absent",
+                merged.as_str(),
+                "missing",
+                ExitCode::FAILURE,
+            ),
+        ];
+        for (title, filter, key, expected) in cases {
+            assert_eq!(
+                pbloom(&["check", filter, key], "").unwrap(),
+                expected,
+                "{}",
+                title
+            );
+        }
+
+        assert_eq!(pbloom(&["info", &merged], "").unwrap(), ExitCode::SUCCESS);
+
+        let missing = path("missing");
+        let err = pbloom(&["check", &missing, "hello"], "").unwrap_err();
+        assert!(err.to_string().starts_with(&missing), "{}", err);
+        fs::write(&missing, b"not a filter").unwrap();
+        assert!(pbloom(&["info", &missing], "").is_err());
+        let create = ["create", "--entries", "0", "--fp", "0.01", "-o", &missing];
+        assert!(pbloom(&create, "").is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
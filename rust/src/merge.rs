@@ -0,0 +1,471 @@
+use std::io::{Read, Write};
+
+use crc32fast::{hash as crc32, Hasher};
+use rmp::{decode, encode};
+
+use crate::{Filter, FilterError};
+
+/// Message tags on the wire.
+const HELLO: u8 = 0;
+const ACCEPT: u8 = 1;
+const REJECT: u8 = 2;
+const CHUNK: u8 = 3;
+const DONE: u8 = 4;
+const MERGED: u8 = 5;
+
+/// A message of the merge protocol.
+///
+/// A participant opens with `Hello`, carrying a fingerprint of its filter's parameters, and the
+/// coordinator answers `Accept` or `Reject`. The participant then uploads its bits in order as
+/// `Chunk`s and closes with `Done`, carrying a checksum of all uploaded bits. The coordinator
+/// answers `Merged` with a checksum of the combined filter.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MergeMessage {
+    Hello {
+        fingerprint: u64,
+    },
+    Accept,
+    Reject,
+    Chunk {
+        offset: u64,
+        bits: Vec<u8>,
+        crc: u32,
+    },
+    Done {
+        checksum: u32,
+    },
+    Merged {
+        checksum: u32,
+    },
+}
+
+impl MergeMessage {
+    /// Writes the message as MessagePack.
+    pub fn write<W: Write>(&self, wr: &mut W) -> Result<(), FilterError> {
+        match self {
+            MergeMessage::Hello { fingerprint } => {
+                encode::write_u8(wr, HELLO)?;
+                encode::write_u64(wr, *fingerprint)?;
+            }
+            MergeMessage::Accept => encode::write_u8(wr, ACCEPT)?,
+            MergeMessage::Reject => encode::write_u8(wr, REJECT)?,
+            MergeMessage::Chunk { offset, bits, crc } => {
+                encode::write_u8(wr, CHUNK)?;
+                encode::write_u64(wr, *offset)?;
+                encode::write_bin(wr, bits)?;
+                encode::write_u32(wr, *crc)?;
+            }
+            MergeMessage::Done { checksum } => {
+                encode::write_u8(wr, DONE)?;
+                encode::write_u32(wr, *checksum)?;
+            }
+            MergeMessage::Merged { checksum } => {
+                encode::write_u8(wr, MERGED)?;
+                encode::write_u32(wr, *checksum)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads a message written by `write`.
+    ///
+    /// Chunks of more than `max_chunk` bytes are rejected before their bits are allocated, since
+    /// the length comes from the other side.
+    pub fn read<R: Read>(rd: &mut R, max_chunk: usize) -> Result<Self, FilterError> {
+        Ok(match decode::read_u8(rd)? {
+            HELLO => MergeMessage::Hello {
+                fingerprint: decode::read_u64(rd)?,
+            },
+            ACCEPT => MergeMessage::Accept,
+            REJECT => MergeMessage::Reject,
+            CHUNK => {
+                let offset = decode::read_u64(rd)?;
+                let len = decode::read_bin_len(rd)? as usize;
+                if len > max_chunk {
+                    return Err(FilterError::InvalidFormat("chunk too large"));
+                }
+                let mut bits = vec![0u8; len];
+                rd.read_exact(&mut bits)?;
+                let crc = decode::read_u32(rd)?;
+                MergeMessage::Chunk { offset, bits, crc }
+            }
+            DONE => MergeMessage::Done {
+                checksum: decode::read_u32(rd)?,
+            },
+            MERGED => MergeMessage::Merged {
+                checksum: decode::read_u32(rd)?,
+            },
+            _ => return Err(FilterError::InvalidFormat("unknown merge message")),
+        })
+    }
+}
+
+/// A bidirectional channel between a coordinator and one participant.
+pub trait MergeTransport {
+    /// Sends a message to the other side.
+    fn send(&mut self, message: &MergeMessage) -> Result<(), FilterError>;
+
+    /// Waits for the next message from the other side, which may be a chunk of at most
+    /// `max_chunk` bytes.
+    fn recv(&mut self, max_chunk: usize) -> Result<MergeMessage, FilterError>;
+}
+
+/// A `MergeTransport` over any byte stream, such as a `TcpStream`.
+pub struct StreamTransport<S> {
+    stream: S,
+}
+
+impl<S: Read + Write> StreamTransport<S> {
+    /// Creates a new `StreamTransport` over `stream`.
+    pub fn new(stream: S) -> Self {
+        Self { stream }
+    }
+
+    /// Returns the underlying stream.
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+}
+
+impl<S: Read + Write> MergeTransport for StreamTransport<S> {
+    fn send(&mut self, message: &MergeMessage) -> Result<(), FilterError> {
+        message.write(&mut self.stream)?;
+        Ok(self.stream.flush()?)
+    }
+
+    fn recv(&mut self, max_chunk: usize) -> Result<MergeMessage, FilterError> {
+        MergeMessage::read(&mut self.stream, max_chunk)
+    }
+}
+
+/// Returns a fingerprint of the parameters two filters must share to be merged.
+fn fingerprint(filter: &Filter) -> u64 {
+    let mut params = Vec::with_capacity(13);
+    params.extend_from_slice(&(filter.bits.len() as u64).to_be_bytes());
+    params.push(filter.hash_count);
+    params.extend_from_slice(&filter.seed.to_be_bytes());
+    Filter::hash(&params, 0).0
+}
+
+/// The receiving side of the merge protocol, combining the filters of many participants into
+/// one.
+///
+/// Each participant is served to completion by `serve`, typically one per connection. Chunks are
+/// ORed in as soon as their checksum is verified, so a failed upload leaves only bits the
+/// participant really has set, and retrying it is harmless.
+pub struct MergeCoordinator {
+    filter: Filter,
+    fingerprint: u64,
+}
+
+impl MergeCoordinator {
+    /// Creates a new `MergeCoordinator` merging into `filter`.
+    pub fn new(filter: Filter) -> Self {
+        let fingerprint = fingerprint(&filter);
+        Self {
+            filter,
+            fingerprint,
+        }
+    }
+
+    /// Returns the filter merged so far.
+    pub fn filter(&self) -> &Filter {
+        &self.filter
+    }
+
+    /// Returns the merged filter.
+    pub fn into_filter(self) -> Filter {
+        self.filter
+    }
+
+    /// Runs the protocol with one participant, merging its filter.
+    pub fn serve<T: MergeTransport>(&mut self, transport: &mut T) -> Result<(), FilterError> {
+        match transport.recv(0)? {
+            MergeMessage::Hello { fingerprint } if fingerprint == self.fingerprint => {
+                transport.send(&MergeMessage::Accept)?;
+            }
+            MergeMessage::Hello { .. } => {
+                transport.send(&MergeMessage::Reject)?;
+                return Err(FilterError::Incompatible("parameters differ"));
+            }
+            _ => return Err(FilterError::InvalidFormat("expected hello")),
+        }
+
+        let mut received = Hasher::new();
+        let mut next = 0;
+        loop {
+            match transport.recv(self.filter.bits.len() - next)? {
+                MergeMessage::Chunk { offset, bits, crc } => {
+                    if offset != next as u64 || next + bits.len() > self.filter.bits.len() {
+                        return Err(FilterError::InvalidFormat("chunk out of order"));
+                    }
+                    if crc != crc32(&bits) {
+                        return Err(FilterError::InvalidFormat("chunk checksum mismatch"));
+                    }
+                    for (a, b) in self.filter.bits[next..].iter_mut().zip(&bits) {
+                        *a |= b;
+                    }
+                    received.update(&bits);
+                    next += bits.len();
+                }
+                MergeMessage::Done { checksum } => {
+                    if next != self.filter.bits.len() {
+                        return Err(FilterError::InvalidFormat("missing chunks"));
+                    }
+                    if checksum != received.finalize() {
+                        return Err(FilterError::InvalidFormat("filter checksum mismatch"));
+                    }
+                    return transport.send(&MergeMessage::Merged {
+                        checksum: crc32(&self.filter.bits),
+                    });
+                }
+                _ => return Err(FilterError::InvalidFormat("expected chunk or done")),
+            }
+        }
+    }
+}
+
+/// The sending side of the merge protocol, uploading a filter to a `MergeCoordinator`.
+pub struct MergeParticipant<'a> {
+    filter: &'a Filter,
+    chunk_size: usize,
+}
+
+impl<'a> MergeParticipant<'a> {
+    /// Creates a new `MergeParticipant` uploading `filter` in chunks of `chunk_size` bytes.
+    pub fn new(filter: &'a Filter, chunk_size: usize) -> Self {
+        Self {
+            filter,
+            chunk_size: chunk_size.max(1),
+        }
+    }
+
+    /// Runs the protocol, returning the checksum of the merged filter reported by the
+    /// coordinator.
+    pub fn run<T: MergeTransport>(&self, transport: &mut T) -> Result<u32, FilterError> {
+        transport.send(&MergeMessage::Hello {
+            fingerprint: fingerprint(self.filter),
+        })?;
+        match transport.recv(0)? {
+            MergeMessage::Accept => {}
+            MergeMessage::Reject => return Err(FilterError::Incompatible("parameters differ")),
+            _ => return Err(FilterError::InvalidFormat("expected accept or reject")),
+        }
+
+        for (i, bits) in self.filter.bits.chunks(self.chunk_size).enumerate() {
+            transport.send(&MergeMessage::Chunk {
+                offset: (i * self.chunk_size) as u64,
+                bits: bits.to_vec(),
+                crc: crc32(bits),
+            })?;
+        }
+        transport.send(&MergeMessage::Done {
+            checksum: crc32(&self.filter.bits),
+        })?;
+
+        match transport.recv(0)? {
+            MergeMessage::Merged { checksum } => Ok(checksum),
+            _ => Err(FilterError::InvalidFormat("expected merged")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use std::sync::mpsc::{channel, Receiver, Sender};
+    use std::thread;
+
+    use super::*;
+
+    /// One end of an in-memory transport, passing messages through their wire encoding.
+    struct ChannelTransport {
+        tx: Sender<Vec<u8>>,
+        rx: Receiver<Vec<u8>>,
+    }
+
+    impl MergeTransport for ChannelTransport {
+        fn send(&mut self, message: &MergeMessage) -> Result<(), FilterError> {
+            let mut buf = Vec::new();
+            message.write(&mut buf)?;
+            self.tx.send(buf).unwrap();
+            Ok(())
+        }
+
+        fn recv(&mut self, max_chunk: usize) -> Result<MergeMessage, FilterError> {
+            let buf = self
+                .rx
+                .recv()
+                .map_err(|_| FilterError::InvalidFormat("closed"))?;
+            MergeMessage::read(&mut Cursor::new(buf), max_chunk)
+        }
+    }
+
+    fn pair() -> (ChannelTransport, ChannelTransport) {
+        let (a_tx, b_rx) = channel();
+        let (b_tx, a_rx) = channel();
+        (
+            ChannelTransport { tx: a_tx, rx: a_rx },
+            ChannelTransport { tx: b_tx, rx: b_rx },
+        )
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut coordinator = MergeCoordinator::new(Filter::new(1000, 7));
+        let mut expected = Filter::new(1000, 7);
+
+        for node in 0..3 {
+            let mut filter = Filter::new(1000, 7);
+            for i in 0..100 {
+                filter.add(format!("{}:{}", node, i).as_bytes()).unwrap();
+            }
+            expected.union(&filter).unwrap();
+
+            let (mut a, mut b) = pair();
+            let participant = thread::spawn(move || MergeParticipant::new(&filter, 64).run(&mut b));
+            coordinator.serve(&mut a).unwrap();
+            assert_eq!(participant.join().unwrap().unwrap(), crc32(&expected.bits));
+        }
+
+        assert_eq!(coordinator.into_filter().bits, expected.bits);
+    }
+
+    #[test]
+    fn test_merge_rejected() {
+        let cases = vec![
+            ("size", Filter::new(500, 7)),
+            ("hash count", Filter::new(1000, 5)),
+            (
+                "seed",
+                Filter {
+                    bits: vec![0; 1000],
+                    hash_count: 7,
+                    seed: 1,
+                    limit: None,
+                },
+            ),
+        ];
+
+        for (title, filter) in cases {
+            let mut coordinator = MergeCoordinator::new(Filter::new(1000, 7));
+            let (mut a, mut b) = pair();
+            let participant = thread::spawn(move || MergeParticipant::new(&filter, 64).run(&mut b));
+            assert!(coordinator.serve(&mut a).is_err(), "{}", title);
+            assert!(
+                matches!(
+                    participant.join().unwrap(),
+                    Err(FilterError::Incompatible(_))
+                ),
+                "{}",
+                title
+            );
+        }
+    }
+
+    #[test]
+    fn test_merge_corrupted() {
+        let mut filter = Filter::new(1000, 7);
+        filter.add(b"hello").unwrap();
+        let bits = filter.bits[..64].to_vec();
+
+        let cases = vec![
+            (
+                "bad chunk checksum",
+                vec![MergeMessage::Chunk {
+                    offset: 0,
+                    bits: bits.clone(),
+                    crc: 0,
+                }],
+            ),
+            (
+                "out of order",
+                vec![MergeMessage::Chunk {
+                    offset: 64,
+                    crc: crc32(&bits),
+                    bits: bits.clone(),
+                }],
+            ),
+            (
+                "missing chunks",
+                vec![
+                    MergeMessage::Chunk {
+                        offset: 0,
+                        crc: crc32(&bits),
+                        bits: bits.clone(),
+                    },
+                    MergeMessage::Done {
+                        checksum: crc32(&bits),
+                    },
+                ],
+            ),
+            (
+                "bad final checksum",
+                vec![
+                    MergeMessage::Chunk {
+                        offset: 0,
+                        crc: crc32(&filter.bits),
+                        bits: filter.bits.clone(),
+                    },
+                    MergeMessage::Done { checksum: 0 },
+                ],
+            ),
+        ];
+
+        for (title, messages) in cases {
+            let mut coordinator = MergeCoordinator::new(Filter::new(1000, 7));
+            let (mut a, mut b) = pair();
+            b.send(&MergeMessage::Hello {
+                fingerprint: fingerprint(&filter),
+            })
+            .unwrap();
+            for message in &messages {
+                b.send(message).unwrap();
+            }
+            assert!(coordinator.serve(&mut a).is_err(), "{}", title);
+        }
+    }
+
+    #[test]
+    fn test_stream_transport() {
+        let message = MergeMessage::Chunk {
+            offset: 128,
+            bits: vec![1, 2, 3],
+            crc: 42,
+        };
+        let mut transport = StreamTransport::new(Cursor::new(Vec::new()));
+        transport.send(&message).unwrap();
+        transport.send(&MergeMessage::Accept).unwrap();
+
+        let mut transport = StreamTransport::new(Cursor::new(transport.into_inner().into_inner()));
+        assert_eq!(transport.recv(3).unwrap(), message);
+        assert_eq!(transport.recv(0).unwrap(), MergeMessage::Accept);
+        assert!(transport.recv(0).is_err());
+    }
+
+    #[test]
+    fn test_read_chunk_limit() {
+        // A chunk header declaring 4 GiB of bits, followed by nothing.
+        let mut buf = Vec::new();
+        encode::write_u8(&mut buf, CHUNK).unwrap();
+        encode::write_u64(&mut buf, 0).unwrap();
+        encode::write_bin_len(&mut buf, u32::MAX).unwrap();
+        assert!(matches!(
+            MergeMessage::read(&mut Cursor::new(&buf), 1000),
+            Err(FilterError::InvalidFormat("chunk too large"))
+        ));
+
+        let chunk = MergeMessage::Chunk {
+            offset: 0,
+            bits: vec![1; 64],
+            crc: 0,
+        };
+        let mut buf = Vec::new();
+        chunk.write(&mut buf).unwrap();
+        assert!(MergeMessage::read(&mut Cursor::new(&buf), 63).is_err());
+        assert_eq!(
+            MergeMessage::read(&mut Cursor::new(&buf), 64).unwrap(),
+            chunk
+        );
+    }
+}
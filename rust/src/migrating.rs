@@ -0,0 +1,113 @@
+use crate::{Filter, FilterError};
+
+/// A filter being migrated to new parameters, seed or format without a gap in coverage.
+///
+/// Until `cutover`, every key is written to both the old and the new filter, and lookups check
+/// both. Keys added before the migration started only need to be backfilled into the new filter
+/// before cutting over; lookups never miss them in the meantime.
+#[derive(Clone)]
+pub struct MigratingFilter {
+    old: Option<Filter>,
+    new: Filter,
+}
+
+impl MigratingFilter {
+    /// Starts migrating from `old` to `new`.
+    pub fn new(old: Filter, new: Filter) -> Self {
+        Self {
+            old: Some(old),
+            new,
+        }
+    }
+
+    /// Returns the filter being migrated from, or `None` after `cutover`.
+    pub fn old(&self) -> Option<&Filter> {
+        self.old.as_ref()
+    }
+
+    /// Returns the filter being migrated to.
+    pub fn new_filter(&self) -> &Filter {
+        &self.new
+    }
+
+    /// Returns a mutable reference to the filter being migrated to, for backfilling it.
+    pub fn new_filter_mut(&mut self) -> &mut Filter {
+        &mut self.new
+    }
+
+    /// Returns whether writes still go to both filters.
+    pub fn is_migrating(&self) -> bool {
+        self.old.is_some()
+    }
+
+    /// Adds an item to both filters, or only the new one after `cutover`.
+    pub fn add(&mut self, item: &[u8]) -> Result<(), FilterError> {
+        self.new.add(item)?;
+        if let Some(old) = &mut self.old {
+            old.add(item)?;
+        }
+        Ok(())
+    }
+
+    /// Checks if an item is present in either filter, or only the new one after `cutover`.
+    pub fn contains(&self, item: &[u8]) -> Result<bool, FilterError> {
+        if self.new.contains(item)? {
+            return Ok(true);
+        }
+        match &self.old {
+            Some(old) => old.contains(item),
+            None => Ok(false),
+        }
+    }
+
+    /// Stops writing to and reading from the old filter, returning it.
+    ///
+    /// Call this once the new filter has been backfilled with every key added before the
+    /// migration started. Returns `None` if the migration was already cut over.
+    pub fn cutover(&mut self) -> Option<Filter> {
+        self.old.take()
+    }
+
+    /// Returns the new filter, dropping the old one.
+    pub fn into_new(self) -> Filter {
+        self.new
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_migrating_filter() {
+        let mut old = Filter::new(64, 3);
+        old.add(b"legacy").unwrap();
+        let mut new = Filter::new(1199, 7);
+        new.seed = 42;
+
+        let mut filter = MigratingFilter::new(old, new);
+        filter.add(b"live").unwrap();
+        assert!(filter.is_migrating());
+        assert!(filter.old().unwrap().contains(b"live").unwrap());
+        assert!(filter.new_filter().contains(b"live").unwrap());
+
+        // Before the backfill, legacy keys are only found through the old filter.
+        let cases: [(&[u8], bool); 3] = [(b"legacy", true), (b"live", true), (b"missing", false)];
+        for (key, expected) in cases {
+            assert_eq!(filter.contains(key).unwrap(), expected, "{:?}", key);
+        }
+        assert!(!filter.new_filter().contains(b"legacy").unwrap());
+
+        filter.new_filter_mut().add(b"legacy").unwrap();
+        let old = filter.cutover().unwrap();
+        assert!(old.contains(b"live").unwrap());
+        assert!(filter.cutover().is_none());
+
+        filter.add(b"after").unwrap();
+        assert!(!old.contains(b"after").unwrap());
+        for (key, expected) in cases {
+            assert_eq!(filter.contains(key).unwrap(), expected, "{:?}", key);
+        }
+        assert_eq!(filter.into_new().seed(), 42);
+    }
+}
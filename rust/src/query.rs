@@ -0,0 +1,173 @@
+use alloc::vec::Vec;
+
+use crate::{Filter, FilterError, KeyEncode, KeyHashes};
+
+/// Number of distinct seeds whose hashes a lookup keeps; keys are rehashed for any others.
+const HASH_CACHE_SIZE: usize = 4;
+
+/// A membership check across several filters, such as a global filter, a per-shard filter and a
+/// recent-window filter.
+///
+/// A key matches if every `all_of` filter contains it and, for each `any_of` group, at least one
+/// filter of the group does. Filters are probed in the order they were given, stopping as soon
+/// as the outcome is known, and the key is hashed only once per distinct seed.
+///
+/// ```
+/// # use pbloom::{Filter, Query};
+/// let mut global = Filter::new(64, 3);
+/// let shard = Filter::new(64, 3);
+/// let mut recent = Filter::new(64, 3);
+/// global.add(b"key").unwrap();
+/// recent.add(b"key").unwrap();
+///
+/// let query = Query::all_of([&global]).any_of([&shard, &recent]);
+/// assert!(query.contains(b"key").unwrap());
+/// ```
+#[derive(Clone, Default)]
+pub struct Query<'a> {
+    all: Vec<&'a Filter>,
+    any: Vec<Vec<&'a Filter>>,
+}
+
+impl<'a> Query<'a> {
+    /// Creates a query that matches every key.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a query matching keys present in every one of `filters`.
+    pub fn all_of<I>(filters: I) -> Self
+    where
+        I: IntoIterator<Item = &'a Filter>,
+    {
+        Self {
+            all: filters.into_iter().collect(),
+            any: Vec::new(),
+        }
+    }
+
+    /// Further requires keys to be present in at least one of `filters`.
+    ///
+    /// Each call adds a separate group. An empty group matches no key.
+    pub fn any_of<I>(mut self, filters: I) -> Self
+    where
+        I: IntoIterator<Item = &'a Filter>,
+    {
+        self.any.push(filters.into_iter().collect());
+        self
+    }
+
+    /// Checks if an item matches the query.
+    pub fn contains(&self, item: &[u8]) -> Result<bool, FilterError> {
+        let mut hashes = HashCache::new(item);
+        for filter in &self.all {
+            if !filter.contains_hashed(hashes.get(filter.seed))? {
+                return Ok(false);
+            }
+        }
+        for group in &self.any {
+            let mut found = false;
+            for filter in group {
+                if filter.contains_hashed(hashes.get(filter.seed))? {
+                    found = true;
+                    break;
+                }
+            }
+            if !found {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Checks if a key matches the query, using its canonical encoding.
+    pub fn contains_key<K: KeyEncode + ?Sized>(&self, key: &K) -> Result<bool, FilterError> {
+        self.contains(&key.to_key_bytes())
+    }
+}
+
+/// Hashes of one item, computed lazily for each seed the lookup needs.
+struct HashCache<'k> {
+    item: &'k [u8],
+    hashes: [Option<KeyHashes>; HASH_CACHE_SIZE],
+}
+
+impl<'k> HashCache<'k> {
+    fn new(item: &'k [u8]) -> Self {
+        Self {
+            item,
+            hashes: [None; HASH_CACHE_SIZE],
+        }
+    }
+
+    fn get(&mut self, seed: u32) -> KeyHashes {
+        for slot in &mut self.hashes {
+            match slot {
+                Some(hashes) if hashes.seed == seed => return *hashes,
+                Some(_) => continue,
+                None => return *slot.insert(Filter::hash_key_with_seed(self.item, seed)),
+            }
+        }
+        Filter::hash_key_with_seed(self.item, seed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter_with(seed: u32, keys: &[&str]) -> Filter {
+        let mut filter = Filter::new(1199, 7);
+        filter.seed = seed;
+        for key in keys {
+            filter.add(key.as_bytes()).unwrap();
+        }
+        filter
+    }
+
+    #[test]
+    fn test_query() {
+        let global = filter_with(0, &["a", "b", "c", "d"]);
+        let shard = filter_with(1, &["a", "b"]);
+        let recent = filter_with(0, &["c"]);
+        let seeds: Vec<Filter> = (2..8).map(|seed| filter_with(seed, &["a"])).collect();
+
+        let cases: Vec<(Query, &[(&str, bool)])> = vec![
+            (Query::new(), &[("a", true), ("z", true)]),
+            (
+                Query::all_of([&global]),
+                &[("a", true), ("d", true), ("z", false)],
+            ),
+            (
+                Query::all_of([&global]).any_of([&shard, &recent]),
+                &[("a", true), ("c", true), ("d", false), ("z", false)],
+            ),
+            (
+                Query::all_of([&global]).any_of([&shard]).any_of([&recent]),
+                &[("a", false), ("c", false)],
+            ),
+            (Query::new().any_of([]), &[("a", false)]),
+            // More seeds than the hash cache holds.
+            (Query::all_of(&seeds), &[("a", true), ("b", false)]),
+        ];
+        for (i, (query, expected)) in cases.iter().enumerate() {
+            for &(key, present) in expected.iter() {
+                assert_eq!(
+                    query.contains_key(key).unwrap(),
+                    present,
+                    "case {} {}",
+                    i,
+                    key
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_hash_cache() {
+        let mut hashes = HashCache::new(b"a");
+        for seed in [0, 1, 2, 3, 4, 0, 4] {
+            assert_eq!(hashes.get(seed), Filter::hash_key_with_seed(b"a", seed));
+        }
+    }
+}
@@ -0,0 +1,215 @@
+use std::io::{Cursor, Read};
+
+use rmp::{decode, encode};
+
+use crate::{Filter, FilterError};
+
+/// MessagePack extension type used to tag serialized `RangedFilter`s.
+const RANGED_TAG: i8 = 2;
+
+/// A `Filter` that also tracks the lexicographic min/max of inserted keys.
+///
+/// Lookups for keys outside the range are answered without touching the bits, which is cheap and
+/// very effective when keys are roughly ordered, e.g. timestamps or sequential ids.
+#[derive(Clone)]
+pub struct RangedFilter {
+    filter: Filter,
+    range: Option<(Vec<u8>, Vec<u8>)>,
+}
+
+impl RangedFilter {
+    /// Wraps an empty `Filter`.
+    ///
+    /// The filter is assumed to contain no items, since the keys already in it are unknown.
+    pub fn new(filter: Filter) -> Self {
+        Self {
+            filter,
+            range: None,
+        }
+    }
+
+    /// Returns the smallest and largest keys added, or `None` if the filter is empty.
+    pub fn range(&self) -> Option<(&[u8], &[u8])> {
+        self.range
+            .as_ref()
+            .map(|(min, max)| (min.as_slice(), max.as_slice()))
+    }
+
+    /// Returns the underlying filter.
+    pub fn filter(&self) -> &Filter {
+        &self.filter
+    }
+
+    /// Consumes the `RangedFilter`, returning the underlying filter.
+    pub fn into_inner(self) -> Filter {
+        self.filter
+    }
+
+    /// Adds an item to the filter, widening the key range if needed.
+    pub fn add(&mut self, item: &[u8]) -> Result<(), FilterError> {
+        self.filter.add(item)?;
+        match &mut self.range {
+            Some((min, max)) => {
+                if item < min.as_slice() {
+                    *min = item.to_vec();
+                } else if item > max.as_slice() {
+                    *max = item.to_vec();
+                }
+            }
+            None => self.range = Some((item.to_vec(), item.to_vec())),
+        }
+        Ok(())
+    }
+
+    /// Returns whether `item` falls within the key range.
+    ///
+    /// `false` means the item was definitely never added.
+    pub fn in_range(&self, item: &[u8]) -> bool {
+        self.range
+            .as_ref()
+            .is_some_and(|(min, max)| min.as_slice() <= item && item <= max.as_slice())
+    }
+
+    /// Checks if an item is present in the filter, skipping the bit probes if it is out of range.
+    pub fn contains(&self, item: &[u8]) -> Result<bool, FilterError> {
+        if !self.in_range(item) {
+            return Ok(false);
+        }
+        self.filter.contains(item)
+    }
+
+    /// Deserializes a `RangedFilter` from a byte slice.
+    pub fn from_serialized(serialized: &[u8]) -> Result<Self, FilterError> {
+        let mut reader = Cursor::new(serialized);
+
+        let meta = decode::read_ext_meta(&mut reader)?;
+        if meta.typeid != RANGED_TAG {
+            return Err(FilterError::InvalidFormat("not a ranged filter"));
+        }
+        if serialized.len() - reader.position() as usize != meta.size as usize {
+            return Err(FilterError::InvalidFormat("truncated input"));
+        }
+
+        let range = match decode::read_array_len(&mut reader)? {
+            0 => None,
+            2 => {
+                let min = read_bin(&mut reader)?;
+                let max = read_bin(&mut reader)?;
+                if min > max {
+                    return Err(FilterError::InvalidFormat("key range is inverted"));
+                }
+                Some((min, max))
+            }
+            _ => return Err(FilterError::InvalidFormat("invalid key range")),
+        };
+
+        let filter = Filter::from_serialized(&serialized[reader.position() as usize..])?;
+
+        Ok(Self { filter, range })
+    }
+
+    /// Serializes the filter and its key range into a byte vector.
+    pub fn serialize(&self) -> Result<Vec<u8>, FilterError> {
+        let mut payload = Vec::new();
+        match &self.range {
+            Some((min, max)) => {
+                encode::write_array_len(&mut payload, 2)?;
+                encode::write_bin(&mut payload, min)?;
+                encode::write_bin(&mut payload, max)?;
+            }
+            None => {
+                encode::write_array_len(&mut payload, 0)?;
+            }
+        }
+        payload.extend_from_slice(&self.filter.serialize()?);
+
+        let mut buf = Vec::with_capacity(payload.len() + 6);
+        encode::write_ext_meta(&mut buf, payload.len() as u32, RANGED_TAG)?;
+        buf.extend_from_slice(&payload);
+        Ok(buf)
+    }
+}
+
+/// Reads a MessagePack `bin` value.
+fn read_bin(reader: &mut Cursor<&[u8]>) -> Result<Vec<u8>, FilterError> {
+    let len = decode::read_bin_len(reader)? as usize;
+    if reader.get_ref().len() - (reader.position() as usize) < len {
+        return Err(FilterError::InvalidFormat("truncated key range"));
+    }
+    let mut buf = vec![0u8; len];
+    reader.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+impl From<Filter> for RangedFilter {
+    fn from(filter: Filter) -> Self {
+        Self::new(filter)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_range() {
+        let mut filter = RangedFilter::new(Filter::new(1024, 7));
+        assert_eq!(filter.range(), None);
+        assert!(!filter.contains(b"2024-01-01").unwrap());
+
+        for key in ["2024-03-01", "2024-01-15", "2024-06-30", "2024-02-10"] {
+            filter.add(key.as_bytes()).unwrap();
+        }
+        assert_eq!(
+            filter.range(),
+            Some((&b"2024-01-15"[..], &b"2024-06-30"[..]))
+        );
+
+        let cases = vec![
+            ("below range", "2023-12-31", false, false),
+            ("min", "2024-01-15", true, true),
+            ("inside, added", "2024-03-01", true, true),
+            ("inside, not added", "2024-04-01", true, false),
+            ("max", "2024-06-30", true, true),
+            ("above range", "2024-07-01", false, false),
+        ];
+
+        for (title, key, in_range, contains) in cases {
+            assert_eq!(filter.in_range(key.as_bytes()), in_range, "{}", title);
+            assert_eq!(
+                filter.contains(key.as_bytes()).unwrap(),
+                contains,
+                "{}",
+                title
+            );
+        }
+    }
+
+    #[test]
+    fn test_serialize() {
+        let empty = RangedFilter::new(Filter::new(64, 3));
+        let defilter = RangedFilter::from_serialized(&empty.serialize().unwrap()).unwrap();
+        assert_eq!(defilter.range(), None);
+
+        let mut filter = RangedFilter::new(Filter::new(1000, 7));
+        filter.add(b"hello").unwrap();
+        filter.add(b"world").unwrap();
+
+        let serialized = filter.serialize().unwrap();
+        let defilter = RangedFilter::from_serialized(&serialized).unwrap();
+        assert_eq!(defilter.range(), Some((&b"hello"[..], &b"world"[..])));
+        assert!(defilter.contains(b"hello").unwrap());
+        assert!(defilter.contains(b"world").unwrap());
+        assert_eq!(
+            defilter.filter().serialize().unwrap(),
+            filter.filter().serialize().unwrap()
+        );
+
+        for len in 0..serialized.len() {
+            assert!(RangedFilter::from_serialized(&serialized[..len]).is_err());
+        }
+
+        let plain = Filter::new(1000, 7).serialize().unwrap();
+        assert!(RangedFilter::from_serialized(&plain).is_err());
+    }
+}
@@ -0,0 +1,111 @@
+use std::fmt;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde::de::{self, SeqAccess, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::Filter;
+
+/// Serializes the filter as its MessagePack wire format.
+///
+/// Human-readable formats such as JSON get the wire bytes as a base64 string, binary formats
+/// get them as a byte array. Either way, the payload is exactly what `Filter::serialize`
+/// produces, so it can be handed to any other pbloom implementation.
+impl Serialize for Filter {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let bytes = Filter::serialize(self)
+            .map_err(|err| serde::ser::Error::custom(format!("{:?}", err)))?;
+        if serializer.is_human_readable() {
+            serializer.serialize_str(&STANDARD.encode(bytes))
+        } else {
+            serializer.serialize_bytes(&bytes)
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Filter {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        if deserializer.is_human_readable() {
+            deserializer.deserialize_str(FilterVisitor)
+        } else {
+            deserializer.deserialize_bytes(FilterVisitor)
+        }
+    }
+}
+
+struct FilterVisitor;
+
+impl FilterVisitor {
+    fn from_wire<E: de::Error>(bytes: &[u8]) -> Result<Filter, E> {
+        Filter::from_serialized(bytes).map_err(|err| E::custom(format!("{:?}", err)))
+    }
+}
+
+impl<'de> Visitor<'de> for FilterVisitor {
+    type Value = Filter;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a serialized pbloom filter as base64 or bytes")
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<Filter, E> {
+        let bytes = STANDARD.decode(v).map_err(E::custom)?;
+        Self::from_wire(&bytes)
+    }
+
+    fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<Filter, E> {
+        Self::from_wire(v)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Filter, A::Error> {
+        let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+        while let Some(byte) = seq.next_element()? {
+            bytes.push(byte);
+        }
+        Self::from_wire(&bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use base64::engine::general_purpose::STANDARD;
+    use base64::Engine;
+
+    use crate::Filter;
+
+    fn filter() -> Filter {
+        let mut filter = Filter::new(100, 7);
+        filter.add(b"hello").unwrap();
+        filter.add(b"world").unwrap();
+        filter
+    }
+
+    #[test]
+    fn test_serde_json() {
+        let filter = filter();
+        let json = serde_json::to_string(&filter).unwrap();
+        let encoded: String = serde_json::from_str(&json).unwrap();
+        assert_eq!(
+            STANDARD.decode(encoded).unwrap(),
+            filter.serialize().unwrap()
+        );
+
+        let defilter: Filter = serde_json::from_str(&json).unwrap();
+        assert!(defilter.contains(b"hello").unwrap());
+        assert!(defilter.contains(b"world").unwrap());
+        assert!(!defilter.contains(b"foo").unwrap());
+    }
+
+    #[test]
+    fn test_serde_binary() {
+        let filter = filter();
+        let packed = rmp_serde::to_vec(&filter).unwrap();
+        let mut reader = &packed[..];
+        let len = rmp::decode::read_bin_len(&mut reader).unwrap();
+        assert_eq!(&reader[..len as usize], &filter.serialize().unwrap()[..]);
+
+        let defilter: Filter = rmp_serde::from_slice(&packed).unwrap();
+        assert_eq!(defilter.serialize().unwrap(), filter.serialize().unwrap());
+    }
+}
@@ -0,0 +1,513 @@
+//! Persistence of versioned filter snapshots.
+//!
+//! A `FilterStore` keeps numbered snapshots of named filters. `FsStore` writes them to a local
+//! directory; with the `store-object` feature `ObjectFilterStore` writes them to any
+//! `object_store` backend such as S3, and with `store-postgres` `PostgresFilterStore` writes
+//! them to a Postgres table.
+//!
+//! Snapshots are written with `Filter::serialize_compressed`, so seeded filters round-trip.
+
+use std::fmt;
+use std::future::Future;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::{Filter, FilterError};
+
+/// Extension of snapshot files written by `FsStore` and `ObjectFilterStore`.
+const EXTENSION: &str = "pbloom";
+
+/// Errors that can occur when loading or saving snapshots.
+#[derive(Debug)]
+pub enum StoreError {
+    /// The snapshot could not be encoded or decoded.
+    Filter(FilterError),
+    /// The filter name is empty, `.` or `..`, or contains a `/` or `\`.
+    InvalidName,
+    /// The snapshot does not exist.
+    NotFound,
+    /// The underlying storage failed.
+    Backend(Box<dyn std::error::Error + Send + Sync>),
+}
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StoreError::Filter(err) => write!(f, "{}", err),
+            StoreError::InvalidName => write!(f, "invalid filter name"),
+            StoreError::NotFound => write!(f, "snapshot not found"),
+            StoreError::Backend(err) => write!(f, "storage error: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for StoreError {}
+
+impl From<FilterError> for StoreError {
+    fn from(err: FilterError) -> Self {
+        StoreError::Filter(err)
+    }
+}
+
+impl From<std::io::Error> for StoreError {
+    fn from(err: std::io::Error) -> Self {
+        match err.kind() {
+            std::io::ErrorKind::NotFound => StoreError::NotFound,
+            _ => StoreError::Backend(err.into()),
+        }
+    }
+}
+
+/// A stored version of a named filter. Versions start at 1 and increase with every save.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Snapshot {
+    pub name: String,
+    pub version: u64,
+}
+
+/// Storage for versioned snapshots of named filters.
+pub trait FilterStore: Sync {
+    /// Saves `filter` as the next version of `name`.
+    fn save(
+        &self,
+        name: &str,
+        filter: &Filter,
+    ) -> impl Future<Output = Result<Snapshot, StoreError>> + Send;
+
+    /// Loads a snapshot.
+    fn load(&self, snapshot: &Snapshot) -> impl Future<Output = Result<Filter, StoreError>> + Send;
+
+    /// Lists the snapshots of `name`, oldest first.
+    fn list(&self, name: &str) -> impl Future<Output = Result<Vec<Snapshot>, StoreError>> + Send;
+
+    /// Loads the newest snapshot of `name`, if there is one.
+    fn load_latest(
+        &self,
+        name: &str,
+    ) -> impl Future<Output = Result<Option<(Snapshot, Filter)>, StoreError>> + Send {
+        async move {
+            let Some(snapshot) = self.list(name).await?.pop() else {
+                return Ok(None);
+            };
+            let filter = self.load(&snapshot).await?;
+            Ok(Some((snapshot, filter)))
+        }
+    }
+}
+
+/// Rejects names that are not a single path component, so snapshots stay under the store's root.
+fn check_name(name: &str) -> Result<(), StoreError> {
+    if matches!(name, "" | "." | "..") || name.contains(['/', '\\']) {
+        return Err(StoreError::InvalidName);
+    }
+    Ok(())
+}
+
+/// Returns the file name of a snapshot version.
+fn file_name(version: u64) -> String {
+    format!("{:020}.{}", version, EXTENSION)
+}
+
+/// Parses a snapshot version back from its file name.
+fn parse_file_name(file_name: &str) -> Option<u64> {
+    file_name
+        .strip_suffix(EXTENSION)?
+        .strip_suffix('.')?
+        .parse()
+        .ok()
+}
+
+/// Returns the next version after the newest of `snapshots`.
+fn next_version(snapshots: &[Snapshot]) -> u64 {
+    snapshots.last().map_or(1, |snapshot| snapshot.version + 1)
+}
+
+/// Counter making temporary file names unique within the process.
+static TMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// A `FilterStore` keeping snapshots as files under `<root>/<name>/`.
+///
+/// Saves never overwrite an existing snapshot, so concurrent writers of the same filter fail
+/// instead of losing a snapshot.
+#[derive(Debug, Clone)]
+pub struct FsStore {
+    root: PathBuf,
+}
+
+impl FsStore {
+    /// Creates a new `FsStore` rooted at `root`. Directories are created on first save.
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    fn path(&self, snapshot: &Snapshot) -> PathBuf {
+        self.root
+            .join(&snapshot.name)
+            .join(file_name(snapshot.version))
+    }
+}
+
+impl FilterStore for FsStore {
+    async fn save(&self, name: &str, filter: &Filter) -> Result<Snapshot, StoreError> {
+        check_name(name)?;
+        let bytes = filter.serialize_compressed()?;
+        tokio::fs::create_dir_all(self.root.join(name)).await?;
+
+        let snapshot = Snapshot {
+            name: name.to_string(),
+            version: next_version(&self.list(name).await?),
+        };
+        let path = self.path(&snapshot);
+        let tmp = path.with_extension(format!(
+            "{}-{}.tmp",
+            std::process::id(),
+            TMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        let written = write_new(&tmp, &bytes).await;
+        // Unlike a rename, linking fails if another writer already saved this version.
+        let linked = match written {
+            Ok(()) => tokio::fs::hard_link(&tmp, &path).await,
+            Err(err) => Err(err),
+        };
+        let _ = tokio::fs::remove_file(&tmp).await;
+        linked?;
+        Ok(snapshot)
+    }
+
+    async fn load(&self, snapshot: &Snapshot) -> Result<Filter, StoreError> {
+        check_name(&snapshot.name)?;
+        let bytes = tokio::fs::read(self.path(snapshot)).await?;
+        Ok(Filter::from_serialized(&bytes)?)
+    }
+
+    async fn list(&self, name: &str) -> Result<Vec<Snapshot>, StoreError> {
+        check_name(name)?;
+        let mut entries = match tokio::fs::read_dir(self.root.join(name)).await {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err.into()),
+        };
+
+        let mut snapshots = Vec::new();
+        while let Some(entry) = entries.next_entry().await? {
+            if let Some(version) = entry.file_name().to_str().and_then(parse_file_name) {
+                snapshots.push(Snapshot {
+                    name: name.to_string(),
+                    version,
+                });
+            }
+        }
+        snapshots.sort();
+        Ok(snapshots)
+    }
+}
+
+/// Writes `bytes` to a file that must not exist yet.
+async fn write_new(path: &std::path::Path, bytes: &[u8]) -> std::io::Result<()> {
+    use tokio::io::AsyncWriteExt;
+
+    let mut file = tokio::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path)
+        .await?;
+    file.write_all(bytes).await?;
+    file.sync_all().await
+}
+
+#[cfg(feature = "store-object")]
+pub use object::ObjectFilterStore;
+
+#[cfg(feature = "store-object")]
+mod object {
+    use object_store::path::Path;
+    use object_store::{ObjectStore, PutMode, PutOptions, PutPayload};
+
+    use super::*;
+
+    impl From<object_store::Error> for StoreError {
+        fn from(err: object_store::Error) -> Self {
+            match err {
+                object_store::Error::NotFound { .. } => StoreError::NotFound,
+                err => StoreError::Backend(err.into()),
+            }
+        }
+    }
+
+    /// A `FilterStore` keeping snapshots as objects under `<prefix>/<name>/` in any
+    /// `object_store` backend, such as `AmazonS3`.
+    ///
+    /// Saves never overwrite an existing object, so concurrent writers of the same filter fail
+    /// instead of losing a snapshot.
+    #[derive(Debug)]
+    pub struct ObjectFilterStore<S> {
+        store: S,
+        prefix: Path,
+    }
+
+    impl<S: ObjectStore> ObjectFilterStore<S> {
+        /// Creates a new `ObjectFilterStore` keeping snapshots under `prefix` in `store`.
+        pub fn new(store: S, prefix: impl Into<Path>) -> Self {
+            Self {
+                store,
+                prefix: prefix.into(),
+            }
+        }
+
+        fn dir(&self, name: &str) -> Path {
+            self.prefix.child(name)
+        }
+
+        fn path(&self, snapshot: &Snapshot) -> Path {
+            self.dir(&snapshot.name).child(file_name(snapshot.version))
+        }
+    }
+
+    impl<S: ObjectStore> FilterStore for ObjectFilterStore<S> {
+        async fn save(&self, name: &str, filter: &Filter) -> Result<Snapshot, StoreError> {
+            check_name(name)?;
+            let bytes = filter.serialize_compressed()?;
+            let snapshot = Snapshot {
+                name: name.to_string(),
+                version: next_version(&self.list(name).await?),
+            };
+            let options = PutOptions {
+                mode: PutMode::Create,
+                ..Default::default()
+            };
+            self.store
+                .put_opts(&self.path(&snapshot), PutPayload::from(bytes), options)
+                .await?;
+            Ok(snapshot)
+        }
+
+        async fn load(&self, snapshot: &Snapshot) -> Result<Filter, StoreError> {
+            check_name(&snapshot.name)?;
+            let bytes = self.store.get(&self.path(snapshot)).await?.bytes().await?;
+            Ok(Filter::from_serialized(&bytes)?)
+        }
+
+        async fn list(&self, name: &str) -> Result<Vec<Snapshot>, StoreError> {
+            check_name(name)?;
+            let result = self
+                .store
+                .list_with_delimiter(Some(&self.dir(name)))
+                .await?;
+            let mut snapshots: Vec<Snapshot> = result
+                .objects
+                .iter()
+                .filter_map(|meta| parse_file_name(meta.location.filename()?))
+                .map(|version| Snapshot {
+                    name: name.to_string(),
+                    version,
+                })
+                .collect();
+            snapshots.sort();
+            Ok(snapshots)
+        }
+    }
+}
+
+#[cfg(feature = "store-postgres")]
+pub use postgres::PostgresFilterStore;
+
+#[cfg(feature = "store-postgres")]
+mod postgres {
+    use tokio_postgres::Client;
+
+    use super::*;
+
+    impl From<tokio_postgres::Error> for StoreError {
+        fn from(err: tokio_postgres::Error) -> Self {
+            StoreError::Backend(err.into())
+        }
+    }
+
+    /// A `FilterStore` keeping snapshots in a Postgres table.
+    ///
+    /// The table is created by `create_table` with the columns `name text`, `version bigint` and
+    /// `filter bytea`, keyed by name and version, so the filters can be queried directly with
+    /// the pbloom extension.
+    pub struct PostgresFilterStore {
+        client: Client,
+        table: String,
+    }
+
+    impl PostgresFilterStore {
+        /// Creates a new `PostgresFilterStore` using `table`, which is quoted as an identifier.
+        pub fn new(client: Client, table: &str) -> Self {
+            Self {
+                client,
+                table: format!("\"{}\"", table.replace('"', "\"\"")),
+            }
+        }
+
+        /// Creates the snapshot table if it does not exist.
+        pub async fn create_table(&self) -> Result<(), StoreError> {
+            let sql = format!(
+                "CREATE TABLE IF NOT EXISTS {} (
+                    name text NOT NULL,
+                    version bigint NOT NULL,
+                    filter bytea NOT NULL,
+                    PRIMARY KEY (name, version)
+                )",
+                self.table
+            );
+            self.client.batch_execute(&sql).await?;
+            Ok(())
+        }
+    }
+
+    impl FilterStore for PostgresFilterStore {
+        async fn save(&self, name: &str, filter: &Filter) -> Result<Snapshot, StoreError> {
+            check_name(name)?;
+            let bytes = filter.serialize_compressed()?;
+            let sql = format!(
+                "INSERT INTO {0} (name, version, filter)
+                 SELECT $1, coalesce(max(version), 0) + 1, $2 FROM {0} WHERE name = $1
+                 RETURNING version",
+                self.table
+            );
+            let row = self.client.query_one(&sql, &[&name, &bytes]).await?;
+            Ok(Snapshot {
+                name: name.to_string(),
+                version: row.get::<_, i64>(0) as u64,
+            })
+        }
+
+        async fn load(&self, snapshot: &Snapshot) -> Result<Filter, StoreError> {
+            let sql = format!(
+                "SELECT filter FROM {} WHERE name = $1 AND version = $2",
+                self.table
+            );
+            let version = snapshot.version as i64;
+            let row = self
+                .client
+                .query_opt(&sql, &[&snapshot.name, &version])
+                .await?
+                .ok_or(StoreError::NotFound)?;
+            Ok(Filter::from_serialized(row.get::<_, &[u8]>(0))?)
+        }
+
+        async fn list(&self, name: &str) -> Result<Vec<Snapshot>, StoreError> {
+            let sql = format!(
+                "SELECT version FROM {} WHERE name = $1 ORDER BY version",
+                self.table
+            );
+            let rows = self.client.query(&sql, &[&name]).await?;
+            Ok(rows
+                .iter()
+                .map(|row| Snapshot {
+                    name: name.to_string(),
+                    version: row.get::<_, i64>(0) as u64,
+                })
+                .collect())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+
+    use super::*;
+
+    async fn exercise<S: FilterStore>(store: &S) {
+        assert!(store.load_latest("users").await.unwrap().is_none());
+        for name in ["", ".", "..", "a/b", "a\\b", "..\\a"] {
+            assert!(
+                matches!(
+                    store.save(name, &Filter::new(8, 1)).await,
+                    Err(StoreError::InvalidName)
+                ),
+                "{}",
+                name
+            );
+        }
+
+        let mut filter = Filter::new(1000, 7);
+        filter.add(b"hello").unwrap();
+        let first = store.save("users", &filter).await.unwrap();
+        assert_eq!(first.version, 1);
+
+        filter.add(b"world").unwrap();
+        let second = store.save("users", &filter).await.unwrap();
+        assert_eq!(second.version, 2);
+
+        let mut seeded = Filter::new(1000, 7);
+        seeded.seed = 42;
+        seeded.add(b"hello").unwrap();
+        assert_eq!(store.save("seeded", &seeded).await.unwrap().version, 1);
+
+        assert_eq!(
+            store.list("users").await.unwrap(),
+            vec![first.clone(), second.clone()]
+        );
+
+        let old = store.load(&first).await.unwrap();
+        assert!(old.contains(b"hello").unwrap());
+        assert!(!old.contains(b"world").unwrap());
+
+        let (snapshot, latest) = store.load_latest("users").await.unwrap().unwrap();
+        assert_eq!(snapshot, second);
+        assert!(latest.contains(b"world").unwrap());
+
+        let (_, latest) = store.load_latest("seeded").await.unwrap().unwrap();
+        assert_eq!(latest.seed(), 42);
+        assert!(latest.contains(b"hello").unwrap());
+
+        let missing = Snapshot {
+            name: "users".to_string(),
+            version: 3,
+        };
+        assert!(matches!(
+            store.load(&missing).await,
+            Err(StoreError::NotFound)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_fs_store() {
+        let root = env::temp_dir().join(format!("pbloom-store-{}", std::process::id()));
+        exercise(&FsStore::new(&root)).await;
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_fs_store_concurrent_saves() {
+        let root = env::temp_dir().join(format!("pbloom-store-race-{}", std::process::id()));
+        let store = FsStore::new(&root);
+        let filter = Filter::new(1000, 7);
+
+        // Interleaved saves pick the same version; only one of them may write it.
+        let results = tokio::join!(
+            store.save("users", &filter),
+            store.save("users", &filter),
+            store.save("users", &filter),
+            store.save("users", &filter),
+        );
+        let saved: Vec<Snapshot> = [results.0, results.1, results.2, results.3]
+            .into_iter()
+            .filter_map(Result::ok)
+            .collect();
+        assert!(!saved.is_empty());
+
+        let mut listed = store.list("users").await.unwrap();
+        listed.sort();
+        let mut expected = saved.clone();
+        expected.sort();
+        assert_eq!(listed, expected);
+        assert_eq!(
+            fs::read_dir(root.join("users")).unwrap().count(),
+            saved.len()
+        );
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[cfg(feature = "store-object")]
+    #[tokio::test]
+    async fn test_object_store() {
+        let store = ObjectFilterStore::new(object_store::memory::InMemory::new(), "filters");
+        exercise(&store).await;
+    }
+}
@@ -0,0 +1,262 @@
+use std::fs::{self, File};
+use std::io::{Cursor, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use rmp::{decode, encode};
+
+use crate::{Filter, FilterError};
+
+/// Builds a `Filter` from a stream of items, optionally checkpointing progress to disk.
+///
+/// A checkpoint records the filter and how many items have been ingested. After a crash,
+/// `resume` restores both, and the caller continues by skipping that many items of the source.
+///
+/// With `max_build_time`, the build stops at the deadline, so a refresh job can finish within its
+/// maintenance window with a partial filter.
+pub struct StreamingBuilder {
+    filter: Filter,
+    ingested: u64,
+    skipped: u64,
+    checkpoint: Option<(u64, PathBuf)>,
+    deadline: Option<Instant>,
+}
+
+impl StreamingBuilder {
+    /// Creates a new `StreamingBuilder` populating the given filter.
+    pub fn new(filter: Filter) -> Self {
+        Self {
+            filter,
+            ingested: 0,
+            skipped: 0,
+            checkpoint: None,
+            deadline: None,
+        }
+    }
+
+    /// Resumes a build from a checkpoint written by a previous `StreamingBuilder`.
+    ///
+    /// Checkpointing continues to the same path; call `checkpoint_every` to change the interval.
+    pub fn resume(path: impl Into<PathBuf>) -> Result<Self, FilterError> {
+        let path = path.into();
+        let bytes = fs::read(&path)?;
+        let mut reader = Cursor::new(bytes.as_slice());
+        let ingested = decode::read_u64(&mut reader)?;
+        let filter = Filter::from_serialized(&bytes[reader.position() as usize..])?;
+
+        Ok(Self {
+            filter,
+            ingested,
+            skipped: 0,
+            checkpoint: Some((0, path)),
+            deadline: None,
+        })
+    }
+
+    /// Writes a checkpoint to `path` every `interval` items. An interval of 0 only writes
+    /// checkpoints when `checkpoint` is called.
+    pub fn checkpoint_every(mut self, interval: u64, path: impl Into<PathBuf>) -> Self {
+        self.checkpoint = Some((interval, path.into()));
+        self
+    }
+
+    /// Stops adding items once `budget` has elapsed. The time starts when this is called.
+    ///
+    /// `extend` stops reading its source at the deadline and returns the unread items, and `add`
+    /// skips its item.
+    ///
+    /// When the deadline passes, a checkpoint is written if a path is configured, so the next
+    /// run can resume with the first skipped item.
+    pub fn max_build_time(mut self, budget: Duration) -> Self {
+        self.deadline = Some(Instant::now() + budget);
+        self
+    }
+
+    /// Returns the number of items ingested so far, including those before a resume.
+    pub fn ingested(&self) -> u64 {
+        self.ingested
+    }
+
+    /// Returns the number of items passed to `add` after the build time ran out. Items `extend`
+    /// did not read are not counted.
+    pub fn skipped(&self) -> u64 {
+        self.skipped
+    }
+
+    /// Returns whether the build time ran out. Sources that are slow to read can check this to
+    /// stop early.
+    pub fn timed_out(&self) -> bool {
+        self.deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+    }
+
+    /// Adds an item to the filter, or skips it if the build time ran out.
+    pub fn add(&mut self, item: &[u8]) -> Result<(), FilterError> {
+        if self.timed_out() {
+            if self.skipped == 0 {
+                self.checkpoint()?;
+            }
+            self.skipped += 1;
+            return Ok(());
+        }
+
+        self.filter.add(item)?;
+        self.ingested += 1;
+
+        if let Some((interval, _)) = self.checkpoint {
+            if interval > 0 && self.ingested.is_multiple_of(interval) {
+                self.checkpoint()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Adds every item from an iterator to the filter.
+    ///
+    /// If the build time runs out, stops without reading further and returns the iterator with
+    /// the remaining items. A checkpoint is written first if a path is configured.
+    pub fn extend<I, K>(&mut self, items: I) -> Result<Option<I::IntoIter>, FilterError>
+    where
+        I: IntoIterator<Item = K>,
+        K: AsRef<[u8]>,
+    {
+        let mut items = items.into_iter();
+        loop {
+            if self.timed_out() {
+                self.checkpoint()?;
+                return Ok(Some(items));
+            }
+            let Some(item) = items.next() else {
+                return Ok(None);
+            };
+            self.add(item.as_ref())?;
+        }
+    }
+
+    /// Writes a checkpoint now, if a checkpoint path is configured.
+    ///
+    /// The checkpoint is written to a temporary file, synced and renamed into place, so a crash
+    /// while checkpointing leaves the previous checkpoint intact.
+    pub fn checkpoint(&self) -> Result<(), FilterError> {
+        let Some((_, path)) = &self.checkpoint else {
+            return Ok(());
+        };
+
+        let mut buf = Vec::with_capacity(self.filter.bits.len() + 16);
+        encode::write_u64(&mut buf, self.ingested)?;
+        buf.extend_from_slice(&self.filter.serialize_compressed()?);
+
+        let tmp = temporary_path(path);
+        let mut file = File::create(&tmp)?;
+        file.write_all(&buf)?;
+        file.sync_all()?;
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+
+    /// Returns the populated filter, which lacks any skipped or unread items.
+    pub fn finish(self) -> Filter {
+        self.filter
+    }
+}
+
+fn temporary_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".tmp");
+    path.with_file_name(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::env;
+
+    use super::*;
+
+    fn keys() -> impl Iterator<Item = String> {
+        (0..1000).map(|i| i.to_string())
+    }
+
+    #[test]
+    fn test_resume_from_checkpoint() {
+        let path = env::temp_dir().join(format!("pbloom-checkpoint-{}", std::process::id()));
+
+        let mut expected = StreamingBuilder::new(Filter::new(1199, 7));
+        expected.extend(keys()).unwrap();
+        let expected = expected.finish();
+
+        // Crash partway through, after the checkpoint at 500 items.
+        let mut builder = StreamingBuilder::new(Filter::new(1199, 7)).checkpoint_every(100, &path);
+        builder.extend(keys().take(550)).unwrap();
+        drop(builder);
+
+        let mut resumed = StreamingBuilder::resume(&path).unwrap();
+        assert_eq!(resumed.ingested(), 500);
+        let skip = resumed.ingested() as usize;
+        resumed.extend(keys().skip(skip)).unwrap();
+        assert_eq!(resumed.ingested(), 1000);
+
+        assert_eq!(
+            resumed.finish().serialize().unwrap(),
+            expected.serialize().unwrap()
+        );
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_checkpoint_seeded() {
+        let path = env::temp_dir().join(format!("pbloom-checkpoint-seed-{}", std::process::id()));
+        let mut filter = Filter::new(1199, 7);
+        filter.seed = 42;
+
+        let mut builder = StreamingBuilder::new(filter).checkpoint_every(100, &path);
+        builder.extend(keys()).unwrap();
+
+        let resumed = StreamingBuilder::resume(&path).unwrap();
+        assert_eq!(resumed.ingested(), 1000);
+        let resumed = resumed.finish();
+        assert_eq!(resumed.seed(), 42);
+        assert!(keys().all(|key| resumed.contains(key.as_bytes()).unwrap()));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_max_build_time() {
+        let path = env::temp_dir().join(format!("pbloom-deadline-{}", std::process::id()));
+
+        let cases = [
+            (Duration::ZERO, 0, Some(1000)),
+            (Duration::from_secs(3600), 1000, None),
+        ];
+        for (budget, ingested, unread) in cases {
+            let mut builder = StreamingBuilder::new(Filter::new(1199, 7)).max_build_time(budget);
+            let rest = builder.extend(keys()).unwrap();
+            assert_eq!(builder.ingested(), ingested, "{:?}", budget);
+            assert_eq!(rest.map(Iterator::count), unread, "{:?}", budget);
+            assert_eq!(builder.timed_out(), unread.is_some(), "{:?}", budget);
+        }
+
+        // Running out partway through stops reading and checkpoints at the first unread item.
+        let mut builder = StreamingBuilder::new(Filter::new(1199, 7))
+            .checkpoint_every(0, &path)
+            .max_build_time(Duration::from_millis(200));
+        assert!(builder.extend(keys().take(300)).unwrap().is_none());
+        std::thread::sleep(Duration::from_millis(250));
+        let read = Cell::new(0);
+        let rest = builder
+            .extend(keys().skip(300).inspect(|_| read.set(read.get() + 1)))
+            .unwrap();
+        assert_eq!(read.get(), 0);
+        assert_eq!(rest.map(Iterator::count), Some(700));
+        assert_eq!((builder.ingested(), builder.skipped()), (300, 0));
+        builder.add(b"late").unwrap();
+        assert_eq!(builder.skipped(), 1);
+
+        let filter = builder.finish();
+        assert!(keys()
+            .take(300)
+            .all(|key| filter.contains(key.as_bytes()).unwrap()));
+        assert_eq!(StreamingBuilder::resume(&path).unwrap().ingested(), 300);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,115 @@
+use std::sync::Arc;
+
+use arc_swap::{ArcSwap, Guard};
+
+use crate::{Filter, FilterError};
+
+/// A `Filter` that can be atomically replaced while other threads keep querying it.
+///
+/// Readers never take a lock: they load the active filter, and a background rebuild
+/// publishes its result with `store` or `swap`. Readers still holding the previous
+/// filter keep using it until they drop their handle.
+pub struct SwappableFilter {
+    current: ArcSwap<Filter>,
+}
+
+impl SwappableFilter {
+    /// Creates a new `SwappableFilter` serving the given filter.
+    pub fn new(filter: Filter) -> Self {
+        Self {
+            current: ArcSwap::from_pointee(filter),
+        }
+    }
+
+    /// Returns a short-lived guard to the active filter.
+    pub fn load(&self) -> Guard<Arc<Filter>> {
+        self.current.load()
+    }
+
+    /// Returns a handle to the active filter that can be kept around.
+    pub fn load_full(&self) -> Arc<Filter> {
+        self.current.load_full()
+    }
+
+    /// Checks if an item is present in the active filter.
+    pub fn contains(&self, item: &[u8]) -> Result<bool, FilterError> {
+        self.current.load().contains(item)
+    }
+
+    /// Replaces the active filter.
+    pub fn store(&self, filter: Filter) {
+        self.current.store(Arc::new(filter));
+    }
+
+    /// Replaces the active filter, returning the previous one.
+    pub fn swap(&self, filter: Filter) -> Arc<Filter> {
+        self.current.swap(Arc::new(filter))
+    }
+
+    /// Replaces the active filter with one derived from it, returning the previous one.
+    ///
+    /// `f` may be called more than once if another thread replaces the filter concurrently.
+    pub fn rcu<F>(&self, mut f: F) -> Arc<Filter>
+    where
+        F: FnMut(&Filter) -> Filter,
+    {
+        self.current.rcu(|current| Arc::new(f(current)))
+    }
+}
+
+impl From<Filter> for SwappableFilter {
+    fn from(filter: Filter) -> Self {
+        Self::new(filter)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+
+    #[test]
+    fn test_swap() {
+        let mut old = Filter::new(1000, 7);
+        old.add(b"hello").unwrap();
+        let swappable = SwappableFilter::new(old);
+
+        let reader = swappable.load_full();
+
+        let mut new = Filter::new(1000, 7);
+        new.add(b"world").unwrap();
+        let previous = swappable.swap(new);
+
+        assert!(previous.contains(b"hello").unwrap());
+        assert!(reader.contains(b"hello").unwrap());
+        assert!(!swappable.contains(b"hello").unwrap());
+        assert!(swappable.contains(b"world").unwrap());
+    }
+
+    #[test]
+    fn test_rcu() {
+        let swappable = Arc::new(SwappableFilter::new(Filter::new(1000, 7)));
+
+        let handles: Vec<_> = (0..4)
+            .map(|i| {
+                let swappable = Arc::clone(&swappable);
+                thread::spawn(move || {
+                    let key = i.to_string();
+                    swappable.rcu(|current| {
+                        let mut next = current.clone();
+                        next.add(key.as_bytes()).unwrap();
+                        next
+                    });
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        for i in 0..4 {
+            assert!(swappable.contains(i.to_string().as_bytes()).unwrap());
+        }
+    }
+}
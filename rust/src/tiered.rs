@@ -0,0 +1,301 @@
+use std::fs::{File, OpenOptions};
+use std::io::{Seek, SeekFrom, Write};
+use std::path::Path;
+
+use memmap2::MmapMut;
+use rmp::decode::Bytes;
+use rmp::{decode, encode};
+
+use crate::{Filter, FilterError};
+
+/// Default fill ratio at which the hot tier is cleared.
+const DEFAULT_MAX_HOT_FILL: f64 = 0.5;
+
+/// A `Filter` stored in a memory-mapped file.
+///
+/// The file holds the regular serialized format, so it can be read back with
+/// `Filter::from_serialized`. Only the pages touched by lookups are loaded, which makes very
+/// large filters usable with little memory.
+pub struct MmapFilter {
+    map: MmapMut,
+    offset: usize,
+    size: usize,
+    hash_count: u8,
+}
+
+impl MmapFilter {
+    /// Creates a file holding an empty filter with the given size in bytes and number of hash
+    /// functions, and maps it.
+    pub fn create<P: AsRef<Path>>(
+        path: P,
+        size: usize,
+        hash_count: u8,
+    ) -> Result<Self, FilterError> {
+        let mut header = Vec::new();
+        encode::write_bin_len(&mut header, size as u32)?;
+        let mut trailer = Vec::new();
+        encode::write_u8(&mut trailer, hash_count)?;
+
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        file.write_all(&header)?;
+        file.set_len((header.len() + size) as u64)?;
+        file.seek(SeekFrom::End(0))?;
+        file.write_all(&trailer)?;
+
+        Self::map(&file)
+    }
+
+    /// Maps an existing filter file.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, FilterError> {
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+        Self::map(&file)
+    }
+
+    fn map(file: &File) -> Result<Self, FilterError> {
+        // SAFETY: the map is only accessed through this struct. Other processes modifying the
+        // file concurrently may produce torn reads of single bytes, which is harmless for a
+        // Bloom filter.
+        let map = unsafe { MmapMut::map_mut(file)? };
+
+        let mut reader = Bytes::new(&map);
+        let size = decode::read_bin_len(&mut reader)? as usize;
+        if size == 0 {
+            return Err(FilterError::InvalidFormat("empty bit array"));
+        }
+        let offset = map.len() - reader.remaining_slice().len();
+        let Some(trailer) = map.get(offset + size..) else {
+            return Err(FilterError::InvalidFormat("truncated bit array"));
+        };
+        let hash_count = decode::read_u8(&mut Bytes::new(trailer))?;
+
+        Ok(Self {
+            map,
+            offset,
+            size,
+            hash_count,
+        })
+    }
+
+    /// Returns the size of the filter in bytes.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Returns the number of hash functions.
+    pub fn hash_count(&self) -> u8 {
+        self.hash_count
+    }
+
+    fn bits(&self) -> &[u8] {
+        &self.map[self.offset..self.offset + self.size]
+    }
+
+    /// Adds an item to the filter.
+    pub fn add(&mut self, item: &[u8]) -> Result<(), FilterError> {
+        let m = (self.size * 8) as u64;
+        let (h1, h2) = Filter::hash(item, 0);
+
+        let bits = &mut self.map[self.offset..self.offset + self.size];
+        for i in 0..self.hash_count as u64 {
+            let index = Filter::probe_index(h1, h2, i, m);
+            bits[index / 8] |= 1 << (index % 8);
+        }
+        Ok(())
+    }
+
+    /// Checks if an item is present in the filter.
+    pub fn contains(&self, item: &[u8]) -> Result<bool, FilterError> {
+        let m = (self.size * 8) as u64;
+        let (h1, h2) = Filter::hash(item, 0);
+
+        let bits = self.bits();
+        Ok((0..self.hash_count as u64).all(|i| {
+            let index = Filter::probe_index(h1, h2, i, m);
+            bits[index / 8] & (1 << (index % 8)) != 0
+        }))
+    }
+
+    /// Merges an in-memory filter of the same size into this one.
+    pub fn union(&mut self, other: &Filter) -> Result<(), FilterError> {
+        if self.size != other.bits.len() {
+            return Err(FilterError::Incompatible("sizes differ"));
+        }
+        if self.hash_count != other.hash_count {
+            return Err(FilterError::Incompatible("hash counts differ"));
+        }
+        if other.seed != 0 {
+            return Err(FilterError::Incompatible("seeds differ"));
+        }
+        let bits = &mut self.map[self.offset..self.offset + self.size];
+        for (a, b) in bits.iter_mut().zip(&other.bits) {
+            *a |= b;
+        }
+        Ok(())
+    }
+
+    /// Copies the filter into memory.
+    pub fn to_filter(&self) -> Filter {
+        Filter {
+            bits: self.bits().to_vec(),
+            hash_count: self.hash_count,
+            seed: 0,
+            limit: None,
+        }
+    }
+
+    /// Writes modified pages back to the file.
+    pub fn flush(&self) -> Result<(), FilterError> {
+        Ok(self.map.flush()?)
+    }
+}
+
+/// A two-tier filter: a small in-memory filter for recent keys in front of a large
+/// memory-mapped filter holding the full history.
+///
+/// Every key is added to both tiers. Lookups try the hot tier first and only touch the cold
+/// file when it misses. `lookup` also promotes keys found in the cold tier, so repeated lookups
+/// of historical keys stay in memory. When the hot tier fills up it is cleared; it only ever
+/// caches what the cold tier already holds.
+///
+/// The false positive rate is that of the cold tier plus that of the hot tier.
+pub struct TieredFilter {
+    hot: Filter,
+    hot_items: u64,
+    hot_capacity: u64,
+    cold: MmapFilter,
+}
+
+impl TieredFilter {
+    /// Creates a new `TieredFilter` with a hot tier of `hot_size` bytes in front of `cold`.
+    pub fn new(cold: MmapFilter, hot_size: usize) -> Self {
+        let hot = Filter::new(hot_size, cold.hash_count);
+        Self {
+            hot,
+            hot_items: 0,
+            hot_capacity: 0,
+            cold,
+        }
+        .max_hot_fill(DEFAULT_MAX_HOT_FILL)
+    }
+
+    /// Sets the expected fill ratio at which the hot tier is cleared. Defaults to 0.5.
+    pub fn max_hot_fill(mut self, max_hot_fill: f64) -> Self {
+        // Number of items that fill the given fraction of bits, inverting `estimated_items`.
+        let m = (self.hot.bits.len() * 8) as f64;
+        let k = self.hot.hash_count.max(1) as f64;
+        self.hot_capacity = (-(m / k) * (1.0 - max_hot_fill.clamp(0.0, 1.0)).ln()) as u64;
+        self
+    }
+
+    /// Returns the hot tier.
+    pub fn hot(&self) -> &Filter {
+        &self.hot
+    }
+
+    /// Returns the cold tier.
+    pub fn cold(&self) -> &MmapFilter {
+        &self.cold
+    }
+
+    /// Adds an item to the hot tier, clearing it first if it is full.
+    fn add_hot(&mut self, item: &[u8]) -> Result<(), FilterError> {
+        if self.hot_items >= self.hot_capacity {
+            self.hot.bits.fill(0);
+            self.hot_items = 0;
+        }
+        self.hot_items += 1;
+        self.hot.add(item)
+    }
+
+    /// Adds an item to both tiers.
+    pub fn add(&mut self, item: &[u8]) -> Result<(), FilterError> {
+        self.cold.add(item)?;
+        self.add_hot(item)
+    }
+
+    /// Checks if an item is present, without promoting it.
+    pub fn contains(&self, item: &[u8]) -> Result<bool, FilterError> {
+        Ok(self.hot.contains(item)? || self.cold.contains(item)?)
+    }
+
+    /// Checks if an item is present, promoting it to the hot tier if only the cold tier has it.
+    pub fn lookup(&mut self, item: &[u8]) -> Result<bool, FilterError> {
+        if self.hot.contains(item)? {
+            return Ok(true);
+        }
+        if !self.cold.contains(item)? {
+            return Ok(false);
+        }
+        self.add_hot(item)?;
+        Ok(true)
+    }
+
+    /// Writes the cold tier back to its file.
+    pub fn flush(&self) -> Result<(), FilterError> {
+        self.cold.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+
+    use super::*;
+
+    #[test]
+    fn test_mmap_filter() {
+        let path = env::temp_dir().join(format!("pbloom-mmap-{}", std::process::id()));
+        let mut filter = MmapFilter::create(&path, 1000, 7).unwrap();
+        filter.add(b"hello").unwrap();
+        filter.add(b"world").unwrap();
+        filter.flush().unwrap();
+        drop(filter);
+
+        let mut expected = Filter::new(1000, 7);
+        expected.add(b"hello").unwrap();
+        expected.add(b"world").unwrap();
+        assert_eq!(fs::read(&path).unwrap(), expected.serialize().unwrap());
+
+        let mut filter = MmapFilter::open(&path).unwrap();
+        assert_eq!(filter.size(), 1000);
+        assert_eq!(filter.hash_count(), 7);
+        assert!(filter.contains(b"hello").unwrap());
+        assert!(!filter.contains(b"foo").unwrap());
+
+        let mut other = Filter::new(1000, 7);
+        other.add(b"foo").unwrap();
+        filter.union(&other).unwrap();
+        assert!(filter.contains(b"foo").unwrap());
+        assert!(filter.union(&Filter::new(10, 7)).is_err());
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_tiered_filter() {
+        let path = env::temp_dir().join(format!("pbloom-tiered-{}", std::process::id()));
+        let cold = MmapFilter::create(&path, 12000, 7).unwrap();
+        let mut filter = TieredFilter::new(cold, 128);
+
+        for i in 0..10000 {
+            filter.add(i.to_string().as_bytes()).unwrap();
+        }
+        assert!(filter.hot().fill_ratio() < 0.5);
+        for i in 0..10000 {
+            assert!(filter.contains(i.to_string().as_bytes()).unwrap());
+        }
+
+        // Old keys were evicted from the hot tier, and come back when looked up.
+        assert!(!filter.hot().contains(b"0").unwrap());
+        assert!(filter.lookup(b"0").unwrap());
+        assert!(filter.hot().contains(b"0").unwrap());
+        assert!(!filter.lookup(b"absent").unwrap());
+
+        fs::remove_file(&path).unwrap();
+    }
+}
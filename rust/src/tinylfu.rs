@@ -0,0 +1,129 @@
+use crate::{ClockFilter, Filter, FilterError};
+
+/// Number of rows of the count-min sketch.
+const SKETCH_DEPTH: u64 = 4;
+
+/// Number of regions of the doorkeeper.
+const DOORKEEPER_REGIONS: usize = 4;
+
+/// A TinyLFU cache admission policy: decides whether a new item is worth evicting another for,
+/// based on how often each was accessed recently.
+///
+/// Access frequencies are kept in a count-min sketch of small counters, fronted by a doorkeeper
+/// `ClockFilter` so items seen only once never reach the sketch. After ten accesses per cached
+/// item, every counter is halved, so old popularity fades.
+#[derive(Clone)]
+pub struct TinyLfu {
+    counters: Vec<u8>,
+    doorkeeper: ClockFilter,
+    sample_size: u64,
+    accesses: u64,
+}
+
+impl TinyLfu {
+    /// Creates a new `TinyLfu` for a cache holding up to `capacity` items.
+    pub fn new(capacity: usize) -> Self {
+        let capacity = capacity.max(1);
+        let width = capacity.next_power_of_two();
+        // The doorkeeper only needs to answer "seen before?" for about one sample period.
+        let doorkeeper = ClockFilter::new_from_entries_and_fp(capacity, 0.01, DOORKEEPER_REGIONS)
+            .expect("valid doorkeeper parameters");
+        Self {
+            counters: vec![0; width * SKETCH_DEPTH as usize],
+            doorkeeper,
+            sample_size: 10 * capacity as u64,
+            accesses: 0,
+        }
+    }
+
+    /// Records an access to a key.
+    pub fn record(&mut self, key: &[u8]) -> Result<(), FilterError> {
+        if self.doorkeeper.contains(key)? {
+            for index in self.counter_indexes(key) {
+                self.counters[index] = self.counters[index].saturating_add(1);
+            }
+        } else {
+            self.doorkeeper.add(key)?;
+        }
+
+        self.accesses += 1;
+        if self.accesses >= self.sample_size {
+            self.reset();
+        }
+        Ok(())
+    }
+
+    /// Estimates how many times a key was accessed recently. Never underestimates, except for
+    /// the decay applied by resets.
+    pub fn frequency(&self, key: &[u8]) -> Result<u32, FilterError> {
+        let seen = self.doorkeeper.contains(key)? as u32;
+        let count = self
+            .counter_indexes(key)
+            .map(|index| self.counters[index])
+            .min()
+            .unwrap_or(0);
+        Ok(seen + count as u32)
+    }
+
+    /// Returns whether `candidate` should replace `victim` in the cache, which is when it was
+    /// accessed more often recently.
+    pub fn admit(&self, candidate: &[u8], victim: &[u8]) -> Result<bool, FilterError> {
+        Ok(self.frequency(candidate)? > self.frequency(victim)?)
+    }
+
+    /// Halves every counter, so that past accesses count for less.
+    pub fn reset(&mut self) {
+        for counter in &mut self.counters {
+            *counter /= 2;
+        }
+        self.accesses /= 2;
+    }
+
+    /// Returns the index of the key's counter in each row of the sketch.
+    fn counter_indexes(&self, key: &[u8]) -> impl Iterator<Item = usize> {
+        let width = self.counters.len() as u64 / SKETCH_DEPTH;
+        let (h1, h2) = Filter::hash(key, 0);
+        (0..SKETCH_DEPTH)
+            .map(move |row| (row * width) as usize + Filter::probe_index(h1, h2, row, width))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frequency() {
+        let mut lfu = TinyLfu::new(1000);
+        let cases: [(&[u8], u32); 4] = [(b"hot", 50), (b"warm", 5), (b"once", 1), (b"never", 0)];
+        for (key, accesses) in cases {
+            for _ in 0..accesses {
+                lfu.record(key).unwrap();
+            }
+        }
+        for (key, accesses) in cases {
+            assert_eq!(lfu.frequency(key).unwrap(), accesses, "{:?}", key);
+        }
+
+        assert!(lfu.admit(b"hot", b"warm").unwrap());
+        assert!(!lfu.admit(b"once", b"warm").unwrap());
+        assert!(!lfu.admit(b"never", b"never").unwrap());
+    }
+
+    #[test]
+    fn test_reset() {
+        let mut lfu = TinyLfu::new(10);
+        for _ in 0..40 {
+            lfu.record(b"hot").unwrap();
+        }
+        assert_eq!(lfu.frequency(b"hot").unwrap(), 40);
+
+        // Another 60 accesses reach the sample size of 100, halving the counters.
+        for i in 0..60 {
+            lfu.record(format!("cold{}", i % 3).as_bytes()).unwrap();
+        }
+        let hot = lfu.frequency(b"hot").unwrap();
+        assert!((15..=25).contains(&hot), "{}", hot);
+        assert!(lfu.admit(b"hot", b"cold0").unwrap());
+    }
+}
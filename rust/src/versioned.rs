@@ -0,0 +1,413 @@
+use alloc::vec::Vec;
+
+use rmp::decode::Bytes;
+use rmp::encode::ByteBuf;
+use rmp::{decode, encode};
+
+use crate::{Filter, FilterError};
+
+/// MessagePack extension type used to tag the versioned filter format.
+pub(crate) const VERSIONED_TAG: i8 = 5;
+
+/// Current version of the versioned format.
+const VERSION: u8 = 1;
+
+/// Bits stored as-is.
+const RAW: u8 = 0;
+
+/// Set bit indexes, delta-encoded as LEB128 varints.
+const SPARSE: u8 = 1;
+
+/// Runs of zero bytes, each followed by a run of literal bytes, with lengths as LEB128 varints.
+const RLE: u8 = 2;
+
+/// Largest bit array, in bytes, accepted when decoding: Postgres' 1 GB varlena limit. The size is
+/// declared by the input, so it is checked before anything is allocated.
+const MAX_SIZE: usize = 1 << 30;
+
+/// Returns whether `serialized` starts with a MessagePack extension, rather than the legacy
+/// `bin` header.
+pub(crate) fn is_extension(serialized: &[u8]) -> bool {
+    matches!(serialized.first(), Some(0xc7..=0xc9 | 0xd4..=0xd8))
+}
+
+fn write_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+fn read_varint(data: &mut &[u8]) -> Result<u64, FilterError> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let (&byte, rest) = data
+            .split_first()
+            .ok_or(FilterError::InvalidFormat("truncated varint"))?;
+        *data = rest;
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(FilterError::InvalidFormat("varint too long"))
+}
+
+fn encode_sparse(bits: &[u8]) -> Vec<u8> {
+    let mut buf = Vec::new();
+    let mut previous = 0;
+    for (i, &byte) in bits.iter().enumerate() {
+        for bit in 0..8 {
+            if byte & (1 << bit) != 0 {
+                let index = (i * 8 + bit) as u64;
+                write_varint(&mut buf, index - previous);
+                previous = index;
+            }
+        }
+    }
+    buf
+}
+
+/// Allocates an empty buffer for `size` bytes of bits, failing instead of aborting when the
+/// memory is not available.
+fn reserve_bits(size: usize) -> Result<Vec<u8>, FilterError> {
+    if size > MAX_SIZE {
+        return Err(FilterError::InvalidFormat("filter too large"));
+    }
+    let mut bits = Vec::new();
+    bits.try_reserve_exact(size)
+        .map_err(|_| FilterError::InvalidFormat("cannot allocate filter"))?;
+    Ok(bits)
+}
+
+fn decode_sparse(mut data: &[u8], size: usize) -> Result<Vec<u8>, FilterError> {
+    let mut bits = reserve_bits(size)?;
+    bits.resize(size, 0);
+    let mut index = 0u64;
+    let mut first = true;
+    while !data.is_empty() {
+        let delta = read_varint(&mut data)?;
+        if delta == 0 && !first {
+            return Err(FilterError::InvalidFormat("bit indexes not increasing"));
+        }
+        index = index
+            .checked_add(delta)
+            .filter(|&index| index < size as u64 * 8)
+            .ok_or(FilterError::InvalidFormat("bit index out of range"))?;
+        bits[(index / 8) as usize] |= 1 << (index % 8);
+        first = false;
+    }
+    Ok(bits)
+}
+
+fn encode_rle(bits: &[u8]) -> Vec<u8> {
+    let mut buf = Vec::new();
+    let mut rest = bits;
+    while !rest.is_empty() {
+        let zeros = rest.iter().take_while(|&&byte| byte == 0).count();
+        rest = &rest[zeros..];
+        let literal = rest.iter().take_while(|&&byte| byte != 0).count();
+        write_varint(&mut buf, zeros as u64);
+        write_varint(&mut buf, literal as u64);
+        buf.extend_from_slice(&rest[..literal]);
+        rest = &rest[literal..];
+    }
+    buf
+}
+
+fn decode_rle(mut data: &[u8], size: usize) -> Result<Vec<u8>, FilterError> {
+    let mut bits = reserve_bits(size)?;
+    while !data.is_empty() {
+        let zeros = read_varint(&mut data)? as usize;
+        let literal = read_varint(&mut data)? as usize;
+        if zeros > size - bits.len() || literal > size - bits.len() - zeros {
+            return Err(FilterError::InvalidFormat("runs exceed filter size"));
+        }
+        if literal > data.len() {
+            return Err(FilterError::InvalidFormat("truncated run"));
+        }
+        bits.resize(bits.len() + zeros, 0);
+        bits.extend_from_slice(&data[..literal]);
+        data = &data[literal..];
+    }
+    if bits.len() != size {
+        return Err(FilterError::InvalidFormat("runs do not cover filter"));
+    }
+    Ok(bits)
+}
+
+impl Filter {
+    /// Serializes the filter into the versioned format, compressing the bits when that is
+    /// smaller.
+    ///
+    /// Sparse filters are stored as a list of set bit indexes and clustered ones as runs of zero
+    /// bytes; dense filters are stored as-is. Unlike `serialize`, the seed is preserved.
+    /// `Filter::from_serialized` reads both formats, but other implementations of the legacy
+    /// format, such as the Go library, cannot read this one.
+    pub fn serialize_compressed(&self) -> Result<Vec<u8>, FilterError> {
+        let sparse = encode_sparse(&self.bits);
+        let rle = encode_rle(&self.bits);
+        let (encoding, data) = if sparse.len() < rle.len() && sparse.len() < self.bits.len() {
+            (SPARSE, sparse.as_slice())
+        } else if rle.len() < self.bits.len() {
+            (RLE, rle.as_slice())
+        } else {
+            (RAW, self.bits.as_slice())
+        };
+
+        let mut payload = ByteBuf::with_capacity(data.len() + 24);
+        encode::write_u8(&mut payload, VERSION)?;
+        encode::write_u8(&mut payload, encoding)?;
+        encode::write_u32(&mut payload, self.bits.len() as u32)?;
+        encode::write_u8(&mut payload, self.hash_count)?;
+        encode::write_u32(&mut payload, self.seed)?;
+        encode::write_bin(&mut payload, data)?;
+        let payload = payload.into_vec();
+
+        let mut buf = ByteBuf::with_capacity(payload.len() + 6);
+        encode::write_ext_meta(&mut buf, payload.len() as u32, VERSIONED_TAG)?;
+        let mut buf = buf.into_vec();
+        buf.extend_from_slice(&payload);
+        Ok(buf)
+    }
+
+    /// Rewrites a serialized filter of any supported format in the current one, the latest
+    /// version of the format written by `serialize_compressed`.
+    ///
+    /// Filters already in the current format are validated and returned as-is, without copying.
+    /// Only the Rust library reads the result.
+    pub fn upgrade_in_place(serialized: Vec<u8>) -> Result<Vec<u8>, FilterError> {
+        if is_extension(&serialized) {
+            Self::from_versioned(&serialized)?;
+            return Ok(serialized);
+        }
+        Self::from_serialized(&serialized)?.serialize_compressed()
+    }
+
+    /// Deserializes a `Filter` from the versioned format.
+    pub(crate) fn from_versioned(serialized: &[u8]) -> Result<Self, FilterError> {
+        let mut reader = Bytes::new(serialized);
+
+        let meta = decode::read_ext_meta(&mut reader)?;
+        if meta.typeid != VERSIONED_TAG {
+            return Err(FilterError::InvalidFormat("not a pbloom filter"));
+        }
+        if reader.remaining_slice().len() != meta.size as usize {
+            return Err(FilterError::InvalidFormat("truncated input"));
+        }
+
+        if decode::read_u8(&mut reader)? != VERSION {
+            return Err(FilterError::Unsupported("unknown format version"));
+        }
+        let encoding = decode::read_u8(&mut reader)?;
+        let size = decode::read_u32(&mut reader)? as usize;
+        if size > MAX_SIZE {
+            return Err(FilterError::InvalidFormat("filter too large"));
+        }
+        if size == 0 {
+            return Err(FilterError::InvalidFormat("empty bit array"));
+        }
+        let hash_count = decode::read_u8(&mut reader)?;
+        let seed = decode::read_u32(&mut reader)?;
+
+        let len = decode::read_bin_len(&mut reader)? as usize;
+        let remaining = reader.remaining_slice();
+        if remaining.len() < len {
+            return Err(FilterError::InvalidFormat("truncated bit array"));
+        }
+        let data = &remaining[..len];
+
+        let bits = match encoding {
+            RAW if data.len() == size => data.to_vec(),
+            RAW => return Err(FilterError::InvalidFormat("bit array size mismatch")),
+            SPARSE => decode_sparse(data, size)?,
+            RLE => decode_rle(data, size)?,
+            _ => return Err(FilterError::Unsupported("unknown encoding")),
+        };
+
+        Ok(Self {
+            bits,
+            hash_count,
+            seed,
+            limit: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hex_literal::hex;
+
+    use super::*;
+
+    /// Returns the encoding of a filter in the versioned format.
+    fn encoding(serialized: &[u8]) -> u8 {
+        let mut reader = Bytes::new(serialized);
+        decode::read_ext_meta(&mut reader).unwrap();
+        decode::read_u8(&mut reader).unwrap();
+        decode::read_u8(&mut reader).unwrap()
+    }
+
+    /// Writes a filter of 4 bytes and 3 hash functions in the versioned format.
+    fn versioned(version: u8, encoding: u8, data: &[u8]) -> Vec<u8> {
+        versioned_sized(version, encoding, 4, data)
+    }
+
+    /// Writes a filter of `size` bytes and 3 hash functions in the versioned format.
+    fn versioned_sized(version: u8, encoding: u8, size: u32, data: &[u8]) -> Vec<u8> {
+        let mut payload = Vec::new();
+        encode::write_u8(&mut payload, version).unwrap();
+        encode::write_u8(&mut payload, encoding).unwrap();
+        encode::write_u32(&mut payload, size).unwrap();
+        encode::write_u8(&mut payload, 3).unwrap();
+        encode::write_u32(&mut payload, 0).unwrap();
+        encode::write_bin(&mut payload, data).unwrap();
+
+        let mut buf = Vec::new();
+        encode::write_ext_meta(&mut buf, payload.len() as u32, VERSIONED_TAG).unwrap();
+        buf.extend_from_slice(&payload);
+        buf
+    }
+
+    #[test]
+    fn test_serialize_compressed() {
+        let mut sparse = Filter::new(1000, 7);
+        sparse.add(b"hello").unwrap();
+
+        let mut clustered = Filter::new(1000, 7);
+        clustered.bits[100..150].fill(0xaa);
+        clustered.bits[600..700].fill(0x55);
+
+        let mut dense = Filter::new(1000, 7);
+        for i in 0..1000 {
+            dense.add(i.to_string().as_bytes()).unwrap();
+        }
+
+        let mut seeded = sparse.clone();
+        seeded.seed = 42;
+
+        let cases = vec![
+            ("empty", Filter::new(1000, 7), SPARSE),
+            ("sparse", sparse, SPARSE),
+            ("clustered", clustered, RLE),
+            ("dense", dense, RAW),
+            ("seeded", seeded, SPARSE),
+        ];
+
+        for (title, filter, expected_encoding) in cases {
+            let serialized = filter.serialize_compressed().unwrap();
+            assert_eq!(encoding(&serialized), expected_encoding, "{}", title);
+            assert!(serialized.len() <= filter.bits.len() + 24, "{}", title);
+
+            let defilter = Filter::from_serialized(&serialized).unwrap();
+            assert_eq!(defilter.bits, filter.bits, "{}", title);
+            assert_eq!(defilter.hash_count, filter.hash_count, "{}", title);
+            assert_eq!(defilter.seed, filter.seed, "{}", title);
+
+            for len in 0..serialized.len() {
+                assert!(
+                    Filter::from_serialized(&serialized[..len]).is_err(),
+                    "{}: {}",
+                    title,
+                    len
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_versioned_format() {
+        let mut filter = Filter::new(4, 3);
+        filter.bits[0] = 0x01;
+        filter.bits[3] = 0x80;
+
+        let serialized = filter.serialize_compressed().unwrap();
+        assert_eq!(serialized, versioned(VERSION, SPARSE, &hex!("00 1f")));
+        assert_eq!(
+            serialized,
+            hex!("c7 14 05 cc01 cc01 ce00000004 cc03 ce00000000 c4 02 001f")
+        );
+
+        let cases = vec![
+            ("raw", versioned(VERSION, RAW, &hex!("01000080")), true),
+            (
+                "rle",
+                versioned(VERSION, RLE, &hex!("00 01 01 02 01 80")),
+                true,
+            ),
+            ("unknown version", versioned(2, SPARSE, &hex!("00")), false),
+            ("unknown encoding", versioned(VERSION, 9, &[]), false),
+            (
+                "raw size mismatch",
+                versioned(VERSION, RAW, &hex!("01")),
+                false,
+            ),
+            (
+                "index out of range",
+                versioned(VERSION, SPARSE, &hex!("20")),
+                false,
+            ),
+            (
+                "repeated index",
+                versioned(VERSION, SPARSE, &hex!("01 00")),
+                false,
+            ),
+            (
+                "runs too long",
+                versioned(VERSION, RLE, &hex!("05 00")),
+                false,
+            ),
+            (
+                "runs too short",
+                versioned(VERSION, RLE, &hex!("03 00")),
+                false,
+            ),
+            (
+                "truncated run",
+                versioned(VERSION, RLE, &hex!("00 04 01")),
+                false,
+            ),
+            ("other extension", hex!("d4 01 00").to_vec(), false),
+        ];
+
+        for (title, serialized, ok) in cases {
+            match Filter::from_serialized(&serialized) {
+                Ok(defilter) => {
+                    assert!(ok, "{}", title);
+                    assert_eq!(defilter.bits, filter.bits, "{}", title);
+                }
+                Err(_) => assert!(!ok, "{}", title),
+            }
+        }
+    }
+
+    #[test]
+    fn test_declared_size_limit() {
+        let cases = [
+            ("sparse", SPARSE, hex!("00").to_vec()),
+            ("rle", RLE, hex!("00 01 01").to_vec()),
+            ("raw", RAW, vec![0]),
+        ];
+        for (title, encoding, data) in cases {
+            for size in [MAX_SIZE as u32 + 1, u32::MAX] {
+                let serialized = versioned_sized(VERSION, encoding, size, &data);
+                assert!(
+                    matches!(
+                        Filter::from_serialized(&serialized),
+                        Err(FilterError::InvalidFormat("filter too large"))
+                    ),
+                    "{}: {}",
+                    title,
+                    size
+                );
+            }
+        }
+
+        let serialized = versioned_sized(VERSION, RAW, 0, &[]);
+        assert!(matches!(
+            Filter::from_serialized(&serialized),
+            Err(FilterError::InvalidFormat("empty bit array"))
+        ));
+    }
+}
@@ -0,0 +1,84 @@
+use wasm_bindgen::prelude::*;
+
+/// A Bloom filter that can be queried from JavaScript.
+///
+/// This wraps the same `pbloom::Filter` used by the Postgres extension, so serialized filters
+/// and hash results are identical on the server and in the browser.
+#[wasm_bindgen]
+pub struct Filter {
+    inner: pbloom::Filter,
+}
+
+#[wasm_bindgen]
+impl Filter {
+    /// Creates a new `Filter` with the specified size in bytes and number of hash functions.
+    #[wasm_bindgen(constructor)]
+    pub fn new(size: usize, hash_count: u8) -> Filter {
+        Filter {
+            inner: pbloom::Filter::new(size, hash_count),
+        }
+    }
+
+    /// Creates a new `Filter` based on the number of entries and desired false positive rate.
+    #[wasm_bindgen(js_name = fromEntriesAndFp)]
+    pub fn from_entries_and_fp(entries: usize, fp_rate: f64) -> Result<Filter, JsError> {
+        let inner =
+            pbloom::Filter::new_from_entries_and_fp(entries, fp_rate).map_err(JsError::new)?;
+        Ok(Filter { inner })
+    }
+
+    /// Deserializes a `Filter` from bytes produced by any pbloom implementation.
+    #[wasm_bindgen(js_name = fromSerialized)]
+    pub fn from_serialized(serialized: &[u8]) -> Result<Filter, JsError> {
+        Ok(Filter {
+            inner: pbloom::Filter::from_serialized(serialized)?,
+        })
+    }
+
+    /// Adds a key to the filter.
+    pub fn add(&mut self, key: &[u8]) -> Result<(), JsError> {
+        Ok(self.inner.add(key)?)
+    }
+
+    /// Checks if a key is present in the filter.
+    pub fn contains(&self, key: &[u8]) -> Result<bool, JsError> {
+        Ok(self.inner.contains(key)?)
+    }
+
+    /// Adds the UTF-8 encoding of a string key to the filter.
+    #[wasm_bindgen(js_name = addString)]
+    pub fn add_string(&mut self, key: &str) -> Result<(), JsError> {
+        Ok(self.inner.add(key.as_bytes())?)
+    }
+
+    /// Checks if the UTF-8 encoding of a string key is present in the filter.
+    #[wasm_bindgen(js_name = containsString)]
+    pub fn contains_string(&self, key: &str) -> Result<bool, JsError> {
+        Ok(self.inner.contains(key.as_bytes())?)
+    }
+
+    /// Serializes the filter into bytes.
+    pub fn serialize(&self) -> Result<Vec<u8>, JsError> {
+        Ok(self.inner.serialize()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matches_core() {
+        let mut core = pbloom::Filter::new(1000, 7);
+        core.add(b"hello").unwrap();
+
+        let mut filter = Filter::from_serialized(&core.serialize().unwrap()).unwrap();
+        assert!(filter.contains(b"hello").unwrap());
+        assert!(filter.contains_string("hello").unwrap());
+        assert!(!filter.contains_string("world").unwrap());
+
+        filter.add_string("world").unwrap();
+        core.add(b"world").unwrap();
+        assert_eq!(filter.serialize().unwrap(), core.serialize().unwrap());
+    }
+}